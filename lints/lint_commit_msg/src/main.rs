@@ -0,0 +1,160 @@
+use std::{fs, io, process::ExitCode};
+
+use clap::Parser;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Maximum length of the subject line, in characters.
+    #[arg(long, default_value_t = 72)]
+    max_subject_length: usize,
+
+    /// Maximum length of lines in the body, in characters. Lines without
+    /// spaces (e.g. URLs) may be longer.
+    #[arg(long, default_value_t = 72)]
+    max_body_line_length: usize,
+
+    /// Commit message files to check.
+    files: Vec<String>,
+}
+
+/// Git's scissors line. It and everything after it are removed from the
+/// message (e.g. the diff from `git commit --verbose`).
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// Get the lines of the message as Git will store it, i.e. without comments.
+/// Line numbers are kept so they match the file.
+fn message_lines(message: &str) -> Vec<(usize, &str)> {
+    message
+        .lines()
+        .enumerate()
+        .take_while(|(_, line)| *line != SCISSORS)
+        .filter(|(_, line)| !line.starts_with('#'))
+        .map(|(index, line)| (index + 1, line.trim_end()))
+        .collect()
+}
+
+/// Check `message` and return the problems, as line number and description.
+fn check_message(message: &str, args: &Args) -> Vec<(usize, String)> {
+    let mut lines = message_lines(message);
+    // Git strips trailing blank lines.
+    while lines.last().is_some_and(|(_, line)| line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut problems = Vec::new();
+    let Some(&(subject_line, subject)) = lines.first() else {
+        problems.push((1, "Commit message is empty".to_owned()));
+        return problems;
+    };
+
+    if subject.is_empty() {
+        problems.push((subject_line, "Subject line is empty".to_owned()));
+    }
+    let subject_length = subject.chars().count();
+    if subject_length > args.max_subject_length {
+        problems.push((
+            subject_line,
+            format!(
+                "Subject line is {subject_length} characters; the maximum is {}",
+                args.max_subject_length
+            ),
+        ));
+    }
+
+    if let Some(&(line_number, line)) = lines.get(1)
+        && !line.is_empty()
+    {
+        problems.push((
+            line_number,
+            "Second line should be blank to separate the subject from the body".to_owned(),
+        ));
+    }
+
+    for &(line_number, line) in lines.iter().skip(1) {
+        let length = line.chars().count();
+        if length > args.max_body_line_length && line.contains(' ') {
+            problems.push((
+                line_number,
+                format!(
+                    "Line is {length} characters; wrap the body at {}",
+                    args.max_body_line_length
+                ),
+            ));
+        }
+    }
+    problems
+}
+
+fn main() -> io::Result<ExitCode> {
+    let args = Args::parse();
+    let mut success = true;
+    for file in &args.files {
+        let message = fs::read_to_string(file)?;
+        for (line_number, problem) in check_message(&message, &args) {
+            eprintln!("{file}:{line_number}: {problem}");
+            success = false;
+        }
+    }
+
+    Ok(ExitCode::from(if success { 0 } else { 1 }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn problems(message: &str) -> Vec<usize> {
+        let args = Args::parse_from(["lint_commit_msg", "--max-subject-length", "20"]);
+        check_message(message, &args)
+            .into_iter()
+            .map(|(line_number, _)| line_number)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(problems("Fix the thing\n"), Vec::<usize>::new());
+        assert_eq!(
+            problems("Fix the thing\n\nIt was broken.\n\n"),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn test_subject() {
+        assert_eq!(problems(""), [1]);
+        assert_eq!(problems("\n\nBody\n"), [1]);
+        assert_eq!(problems("This subject is far too long\n"), [1]);
+        // Characters, not bytes.
+        assert_eq!(problems("ééééééééééééééééééé\n"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_blank_second_line() {
+        assert_eq!(problems("Subject\nBody\n"), [2]);
+    }
+
+    #[test]
+    fn test_body_wrap() {
+        let long = "word ".repeat(20);
+        assert_eq!(problems(&format!("Subject\n\n{long}\n")), [3]);
+        // Probably a URL.
+        let url = format!("https://example.com/{}", "a".repeat(100));
+        assert_eq!(
+            problems(&format!("Subject\n\n{url}\n")),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let long = "word ".repeat(20);
+        assert_eq!(
+            problems(&format!(
+                "# Comment\nSubject\n# {long}\n\nBody\n{SCISSORS}\n{long}\n"
+            )),
+            Vec::<usize>::new()
+        );
+    }
+}
@@ -6,26 +6,57 @@ use std::{fs, io, process::ExitCode};
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Indentation string.
-    #[arg(long, default_value = "    ")]
-    indentation: String,
+    /// Indentation string. Defaults to four spaces.
+    #[arg(long)]
+    indentation: Option<String>,
+
+    /// Keep each file's existing indentation, detected from its first
+    /// indented line. `--indentation` overrides this, and is used for files
+    /// that don't have any indented lines.
+    #[arg(long)]
+    detect_indent: bool,
 
     /// Files to format.
     files: Vec<String>,
 }
 
-fn main() -> io::Result<ExitCode> {
-    let args = Args::parse();
-    let indentation = match args.indentation.as_str() {
+fn parse_indentation(indentation: &str) -> jsonformat::Indentation<'_> {
+    match indentation {
         "  " => jsonformat::Indentation::TwoSpace,
         "    " => jsonformat::Indentation::FourSpace,
         "\t" => jsonformat::Indentation::Tab,
         other => jsonformat::Indentation::Custom(other),
-    };
+    }
+}
+
+/// Find the indentation of the first indented line in `content`. Raw
+/// newlines can't appear in JSON strings, so this is always one level of
+/// indentation in formatted JSON.
+fn detect_indentation(content: &[u8]) -> Option<&str> {
+    content.split(|&b| b == b'\n').skip(1).find_map(|line| {
+        let len = line
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+        // Whitespace is ASCII so this can't fail.
+        (len > 0).then(|| std::str::from_utf8(&line[..len]).unwrap())
+    })
+}
+
+fn main() -> io::Result<ExitCode> {
+    let args = Args::parse();
+    let default_indentation = args.indentation.as_deref().unwrap_or("    ");
     let mut any_modified = false;
     for file in args.files {
         let content = fs::read(&file)?;
 
+        let indentation = parse_indentation(match &args.indentation {
+            None if args.detect_indent => {
+                detect_indentation(&content).unwrap_or(default_indentation)
+            }
+            _ => default_indentation,
+        });
+
         let mut formatted_content = Vec::new();
         let writer = io::BufWriter::new(&mut formatted_content);
 
@@ -39,3 +70,21 @@ fn main() -> io::Result<ExitCode> {
 
     Ok(ExitCode::from(if any_modified { 1 } else { 0 }))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect_indentation() {
+        assert_eq!(
+            detect_indentation(b"{\n  \"a\": [\n    1\n  ]\n}\n"),
+            Some("  ")
+        );
+        assert_eq!(detect_indentation(b"{\n\t\"a\": 1\n}\n"), Some("\t"));
+        assert_eq!(detect_indentation(b"{\r\n   \"a\": 1\r\n}"), Some("   "));
+        assert_eq!(detect_indentation(b"{\"a\": 1}\n"), None);
+        // The first line isn't indented, even if the file starts with spaces.
+        assert_eq!(detect_indentation(b"  1\n"), None);
+    }
+}
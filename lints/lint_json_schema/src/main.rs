@@ -0,0 +1,164 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::Parser;
+use jsonschema::Validator;
+use serde_json::Value;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Path to the schema to validate the files against. If this isn't
+    /// given, each file's `$schema` is used (relative to the file).
+    #[arg(long)]
+    schema: Option<String>,
+
+    /// JSON files to validate.
+    files: Vec<PathBuf>,
+}
+
+/// Whether a schema location is a URL rather than a path.
+fn is_url(location: &str) -> bool {
+    location.contains("://")
+}
+
+/// Read and compile the schema at `path`.
+fn read_schema(path: &Path) -> Result<Validator, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read schema: {e}"))?;
+    let schema: Value =
+        serde_json::from_str(&content).map_err(|e| format!("Schema is not valid JSON: {e}"))?;
+    jsonschema::validator_for(&schema).map_err(|e| format!("Invalid schema: {e}"))
+}
+
+/// Validate `document` and return the errors, each prefixed with the JSON
+/// pointer to the invalid value.
+fn validate(validator: &Validator, document: &Value) -> Vec<String> {
+    validator
+        .iter_errors(document)
+        .map(|error| {
+            let path = error.instance_path.to_string();
+            let path = if path.is_empty() { "/" } else { &path };
+            format!("{path}: {error}")
+        })
+        .collect()
+}
+
+/// Check one file and return the problems with it.
+fn check_file(file: &Path, schema: Option<&Validator>) -> Vec<String> {
+    let document: Value = match fs::read_to_string(file)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+    {
+        Ok(document) => document,
+        Err(e) => return vec![format!("Failed to parse JSON: {e}")],
+    };
+
+    if let Some(schema) = schema {
+        return validate(schema, &document);
+    }
+    let Some(location) = document.get("$schema").and_then(Value::as_str) else {
+        return Vec::new();
+    };
+    if is_url(location) {
+        eprintln!(
+            "{}: Skipped because `$schema` is a URL ({location}); use a local path or --schema",
+            file.display()
+        );
+        return Vec::new();
+    }
+    let path = file.parent().unwrap_or(Path::new("")).join(location);
+    match read_schema(&path) {
+        Ok(schema) => validate(&schema, &document),
+        Err(e) => vec![format!("{e} ({})", path.display())],
+    }
+}
+
+fn main() -> io::Result<ExitCode> {
+    let args = Args::parse();
+
+    let schema = match &args.schema {
+        Some(location) if is_url(location) => {
+            eprintln!(
+                "Schema '{location}' is a URL; linters don't have network access so it must be a local path"
+            );
+            return Ok(ExitCode::from(1));
+        }
+        Some(location) => match read_schema(Path::new(location)) {
+            Ok(schema) => Some(schema),
+            Err(e) => {
+                eprintln!("{e} ({location})");
+                return Ok(ExitCode::from(1));
+            }
+        },
+        None => None,
+    };
+
+    let mut success = true;
+    for file in &args.files {
+        for problem in check_file(file, schema.as_ref()) {
+            eprintln!("{}: {problem}", file.display());
+            success = false;
+        }
+    }
+
+    Ok(ExitCode::from(if success { 0 } else { 1 }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schema() -> Validator {
+        jsonschema::validator_for(&serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "ports": { "type": "array", "items": { "type": "integer" } },
+            },
+            "required": ["name"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_valid() {
+        let document = serde_json::json!({ "name": "server", "ports": [80, 443] });
+        assert!(validate(&schema(), &document).is_empty());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let errors = validate(&schema(), &serde_json::json!({ "ports": [80, "443"] }));
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|e| e.starts_with("/: ")));
+        assert!(errors.iter().any(|e| e.starts_with("/ports/1: ")));
+    }
+
+    #[test]
+    fn test_dollar_schema() {
+        let dir = std::env::temp_dir().join(format!("lint_json_schema_{}", std::process::id()));
+        fs::create_dir_all(dir.join("schemas")).unwrap();
+        fs::write(
+            dir.join("schemas/name.json"),
+            r#"{ "type": "object", "required": ["name"] }"#,
+        )
+        .unwrap();
+        let check = |content: &str| {
+            fs::write(dir.join("doc.json"), content).unwrap();
+            check_file(&dir.join("doc.json"), None)
+        };
+
+        assert!(check(r#"{ "$schema": "schemas/name.json", "name": "a" }"#).is_empty());
+        assert_eq!(check(r#"{ "$schema": "schemas/name.json" }"#).len(), 1);
+        assert_eq!(check(r#"{ "$schema": "missing.json" }"#).len(), 1);
+        // No schema, or one that can't be fetched.
+        assert!(check(r#"{ "other": 1 }"#).is_empty());
+        assert!(check(r#"{ "$schema": "https://example.com/schema.json" }"#).is_empty());
+        assert_eq!(check("{ not json").len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,15 +1,63 @@
-use std::{fs, io, process::ExitCode};
+use std::{
+    fs,
+    io::{self, Read as _},
+    process::ExitCode,
+};
+
+#[derive(Default)]
+struct Options {
+    /// Remove a UTF-8 byte order mark from the start of files.
+    strip_bom: bool,
+}
+
+/// Split the arguments into options and filenames. Options must come first,
+/// optionally followed by `--`.
+fn parse_args(args: impl IntoIterator<Item = String>) -> io::Result<(Options, Vec<String>)> {
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+        match arg.as_str() {
+            "--" => break,
+            "--strip-bom" => options.strip_bom = true,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown option: {arg}"),
+                ));
+            }
+        }
+    }
+    Ok((options, args.collect()))
+}
+
+/// Get the filenames to lint. If there are too many, nit passes them on
+/// stdin instead of as arguments.
+fn filenames(args: Vec<String>) -> io::Result<Vec<String>> {
+    if !args.is_empty() {
+        return Ok(args);
+    }
+    let mut stdin = String::new();
+    io::stdin().read_to_string(&mut stdin)?;
+    Ok(stdin
+        .split('\0')
+        .filter(|f| !f.is_empty())
+        .map(ToOwned::to_owned)
+        .collect())
+}
 
 fn main() -> io::Result<ExitCode> {
+    let (options, args) = parse_args(std::env::args().skip(1))?;
     let mut any_modified = false;
-    for file in std::env::args().skip(1) {
+    for file in filenames(args)? {
         let mut contents = fs::read(&file)?;
 
+        let stripped_bom = options.strip_bom && strip_bom(&mut contents);
+
         let modified_0 = strip_trailing_whitespace(&mut contents);
 
         let modified_1 = ensure_newline_at_end(&mut contents);
 
-        if modified_0 || modified_1 {
+        if stripped_bom || modified_0 || modified_1 {
             fs::write(&file, contents)?;
             any_modified = true;
         }
@@ -18,6 +66,24 @@ fn main() -> io::Result<ExitCode> {
     Ok(ExitCode::from(if any_modified { 1 } else { 0 }))
 }
 
+/// The UTF-8 encoding of U+FEFF.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Remove a UTF-8 BOM from the start of the file, if the rest of it is text
+/// (no 0 byte in the first 8000 bytes, like Nit's `text` type). Binary files
+/// could start with the same bytes by chance.
+/// Returns true if the contents were modified.
+fn strip_bom(contents: &mut Vec<u8>) -> bool {
+    let Some(rest) = contents.strip_prefix(UTF8_BOM) else {
+        return false;
+    };
+    if rest[..rest.len().min(8000)].contains(&0) {
+        return false;
+    }
+    contents.drain(..UTF8_BOM.len());
+    true
+}
+
 /// Strip trailing whitespace. This also magically fixes \r\n endings.
 /// Returns true if the contents were modified.
 fn strip_trailing_whitespace(contents: &mut Vec<u8>) -> bool {
@@ -87,6 +153,41 @@ mod test {
         assert_eq!(contents, b"\n\n");
     }
 
+    #[test]
+    fn test_strip_bom() {
+        let mut contents = b"\xEF\xBB\xBFhello\n".to_vec();
+        assert_eq!(strip_bom(&mut contents), true);
+        assert_eq!(contents, b"hello\n");
+
+        // Only at the start.
+        let mut contents = b"hello\xEF\xBB\xBF\n".to_vec();
+        assert_eq!(strip_bom(&mut contents), false);
+
+        // Not a whole BOM.
+        let mut contents = b"\xEF\xBBhello\n".to_vec();
+        assert_eq!(strip_bom(&mut contents), false);
+
+        // Binary.
+        let mut contents = b"\xEF\xBB\xBF\0\x01".to_vec();
+        assert_eq!(strip_bom(&mut contents), false);
+        assert_eq!(contents, b"\xEF\xBB\xBF\0\x01");
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+        let (options, files) = parse_args(args(&["--strip-bom", "--", "--a.txt"])).unwrap();
+        assert!(options.strip_bom);
+        assert_eq!(files, ["--a.txt"]);
+
+        let (options, files) = parse_args(args(&["a.txt", "--strip-bom"])).unwrap();
+        assert!(!options.strip_bom);
+        assert_eq!(files, ["a.txt", "--strip-bom"]);
+
+        assert!(parse_args(args(&["--strip-bon"])).is_err());
+    }
+
     #[test]
     fn test_ensure_newline_at_end() {
         let mut contents = b"\nhello there\n\nworld\n".to_vec();
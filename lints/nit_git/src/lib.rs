@@ -0,0 +1,28 @@
+use std::{
+    io::{self, Write as _},
+    path::Path,
+};
+
+/// Ask Nit to mark `path` executable, in the index as well as the working
+/// tree. `path` is as it was passed to the linter. Returns `false` if the
+/// linter doesn't have `"git_requests": true` in its metadata, so Nit can't
+/// do it.
+pub fn set_executable_bit(path: &Path) -> io::Result<bool> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path is not UTF-8"))?;
+    request(&serde_json::json!({ "set_executable": path }))
+}
+
+/// Append a request to the file Nit reads them from, as a JSON line.
+fn request(request: &serde_json::Value) -> io::Result<bool> {
+    let Some(requests_file) = std::env::var_os("NIT_GIT_REQUESTS_FILE") else {
+        return Ok(false);
+    };
+    let mut file = std::fs::File::options()
+        .create(true)
+        .append(true)
+        .open(requests_file)?;
+    writeln!(file, "{request}")?;
+    Ok(true)
+}
@@ -61,11 +61,10 @@ pub fn path_to_bash_string(path: &Path) -> Result<String> {
     })
 }
 
-#[cfg(test)]
+#[cfg(all(test, windows))]
 mod test {
     use super::*;
 
-    #[cfg(windows)]
     #[test]
     fn test_path_to_bash_string_windows() {
         assert_eq!(
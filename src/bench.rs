@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+/// Timing results for one linter.
+pub struct BenchResult {
+    pub name: String,
+    /// Number of files the linter was given on each run.
+    pub num_files: usize,
+    pub mean: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+}
+
+impl BenchResult {
+    /// Summarise the durations of several runs. `durations` must not be empty.
+    pub fn new(name: String, num_files: usize, mut durations: Vec<Duration>) -> Self {
+        assert!(!durations.is_empty(), "No benchmark durations");
+        durations.sort();
+
+        let mean = durations.iter().sum::<Duration>() / durations.len() as u32;
+
+        Self {
+            name,
+            num_files,
+            mean,
+            median: percentile(&durations, 50),
+            p95: percentile(&durations, 95),
+        }
+    }
+
+    /// Files processed per second, based on the mean duration.
+    pub fn files_per_second(&self) -> f64 {
+        self.num_files as f64 / self.mean.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Nearest-rank percentile of sorted durations.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// Print results as a table, slowest first.
+pub fn print_bench_results(results: &mut [BenchResult]) {
+    results.sort_by_key(|r| std::cmp::Reverse(r.mean));
+
+    let name_width = results
+        .iter()
+        .map(|r| r.name.len())
+        .chain(std::iter::once("Linter".len()))
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        "{:name_width$}  {:>7}  {:>10}  {:>10}  {:>10}  {:>10}",
+        "Linter", "Files", "Mean", "Median", "p95", "Files/s"
+    );
+    for result in results.iter() {
+        println!(
+            "{:name_width$}  {:>7}  {:>10.3?}  {:>10.3?}  {:>10.3?}  {:>10.1}",
+            result.name,
+            result.num_files,
+            result.mean,
+            result.median,
+            result.p95,
+            result.files_per_second(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bench_result() {
+        let durations = (1..=20).map(Duration::from_millis).collect();
+        let result = BenchResult::new("test".to_owned(), 10, durations);
+        assert_eq!(result.mean, Duration::from_micros(10500));
+        assert_eq!(result.median, Duration::from_millis(10));
+        assert_eq!(result.p95, Duration::from_millis(19));
+
+        let result = BenchResult::new("single".to_owned(), 0, vec![Duration::from_secs(1)]);
+        assert_eq!(result.median, Duration::from_secs(1));
+        assert_eq!(result.p95, Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use wasmtime::Engine;
+
+use crate::{throughput::format_bytes, wasi_cache::compiled_digest};
+
+/// Describe what is in `cache_dir`, for `nit cache info`. Each downloaded
+/// `.wasm` file is listed with its compiled caches, which are marked stale if
+/// they weren't compiled by `engine` (e.g. a different version of nit or
+/// different engine settings). `linters` maps linter paths to the names of
+/// the configured linters that use them.
+pub fn cache_info(
+    cache_dir: &Path,
+    engine: &Engine,
+    linters: &BTreeMap<PathBuf, Vec<String>>,
+) -> Result<String> {
+    let mut out = String::new();
+    writeln!(out, "Cache directory: {}", cache_dir.display())?;
+    if !cache_dir.exists() {
+        writeln!(out, "Empty (it doesn't exist)")?;
+        return Ok(out);
+    }
+
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(cache_dir)
+        .with_context(|| anyhow!("Failed to read {}", cache_dir.display()))?
+    {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+
+    for name in names.iter().filter(|name| name.ends_with(".wasm")) {
+        let path = cache_dir.join(name);
+        let wasi =
+            std::fs::read(&path).with_context(|| anyhow!("Failed to read {}", path.display()))?;
+        writeln!(out)?;
+        writeln!(out, "{name} ({})", format_bytes(wasi.len() as f64))?;
+        match linters.get(&path) {
+            Some(names) => writeln!(out, "  Used by: {}", names.join(", "))?,
+            None => writeln!(out, "  Used by: no configured linters")?,
+        }
+
+        let current = compiled_digest(engine, &wasi);
+        let prefix = format!("{name}.");
+        let mut compiled = false;
+        for cache in &names {
+            let Some(digest) = cache
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".cache"))
+            else {
+                continue;
+            };
+            compiled = true;
+            let size = std::fs::metadata(cache_dir.join(cache))?.len();
+            let state = if digest == current {
+                "current"
+            } else {
+                "stale"
+            };
+            writeln!(
+                out,
+                "  Compiled: {digest} ({}, {state})",
+                format_bytes(size as f64)
+            )?;
+        }
+        if !compiled {
+            writeln!(out, "  Compiled: no")?;
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Total size: {}",
+        format_bytes(dir_size(cache_dir)? as f64)
+    )?;
+    Ok(out)
+}
+
+/// Total size of the files in `dir` and its subdirectories.
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::default();
+        let wasi = b"\0asm\x0d\0\x01\0";
+        let current = compiled_digest(&engine, wasi);
+        std::fs::write(dir.path().join("a.wasm"), wasi).unwrap();
+        std::fs::write(dir.path().join(format!("a.wasm.{current}.cache")), "12").unwrap();
+        std::fs::write(dir.path().join("a.wasm.0123.cache"), "1234").unwrap();
+        std::fs::write(dir.path().join("b.wasm"), wasi).unwrap();
+        std::fs::create_dir(dir.path().join("results")).unwrap();
+        std::fs::write(dir.path().join("results").join("key"), "").unwrap();
+
+        let linters = BTreeMap::from([(
+            dir.path().join("a.wasm"),
+            vec!["lint_a".to_owned(), "lint_a2".to_owned()],
+        )]);
+        let info = cache_info(dir.path(), &engine, &linters).unwrap();
+        assert!(info.contains("a.wasm (8 B)\n  Used by: lint_a, lint_a2\n"));
+        assert!(info.contains(&format!("  Compiled: {current} (2 B, current)\n")));
+        assert!(info.contains("  Compiled: 0123 (4 B, stale)\n"));
+        assert!(info.contains("b.wasm (8 B)\n  Used by: no configured linters\n  Compiled: no\n"));
+        assert!(info.ends_with("Total size: 22 B\n"));
+    }
+}
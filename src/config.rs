@@ -1,29 +1,169 @@
-use std::{collections::BTreeMap, path::Path};
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{Result, anyhow};
-use serde::Deserialize;
+use anyhow::{Context as _, Result, anyhow, bail};
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned, de::Error as _};
+use serde_json::Value;
 
-use crate::file_matching::MatchExpression;
+use crate::file_matching::{MatchExpression, resolve_refs};
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     /// Files to include. This is essentially ANDed with the linter's
     /// own match expression. There's no need for exclude since you
-    /// can just use a Not expression. This must be present but can just
-    /// be `{ "Bool": true }`.
+    /// can just use a Not expression. Defaults to matching everything.
+    #[serde(default)]
     pub include: MatchExpression,
 
+    /// Named match expressions that can be used in the other match
+    /// expressions with `{ ref: "<name>" }`.
+    #[serde(default)]
+    pub definitions: BTreeMap<String, MatchExpression>,
+
     /// Linters to run. These are run in order.
     pub linters: Vec<ConfigLinter>,
+
+    /// Regexes that identify generated files for the `generated_marker`
+    /// match expression. They are matched against the first 8000 bytes of
+    /// text files.
+    #[serde(default = "default_generated_markers", with = "crate::serde_regex")]
+    pub generated_markers: RegexSet,
+
+    /// Settings for compiling linters. These affect the compiled linter
+    /// cache, so if everyone on a team uses the same settings (and the
+    /// same version of Nit) they can share the cache files.
+    #[serde(default)]
+    pub engine: EngineConfig,
+
+    /// Files larger than this many bytes are skipped. This is useful because
+    /// some linters are pathologically slow on e.g. huge minified files.
+    pub max_file_size: Option<u64>,
+
+    /// Maximum number of linters to download at once. Defaults to 4.
+    pub download_parallelism: Option<NonZeroUsize>,
+
+    /// Directory to store downloaded and compiled linters in, instead of
+    /// the global cache directory. Relative paths are relative to the root
+    /// of the repo, e.g. `.git/nit-cache` for an ephemeral CI cache.
+    pub cache_dir: Option<PathBuf>,
+
+    /// Skip running a linter if it passed last time with the same binary,
+    /// arguments and files. Linters that read other files (e.g. their own
+    /// config) won't be re-run when those change. Defaults to true.
+    #[serde(default = "default_cache_results")]
+    pub cache_results: bool,
+
+    /// Names of host environment variables to pass to linters, e.g.
+    /// `["HOME", "LANG"]`. By default linters don't see any of them, so
+    /// they behave the same everywhere.
+    #[serde(default)]
+    pub inherit_env: Vec<String>,
+
+    /// Built-in normalisation of text files, which Nit applies itself before
+    /// running the linters. This is faster than a linter and doesn't need to
+    /// be downloaded.
+    pub text_normalization: Option<TextNormalization>,
+}
+
+fn default_cache_results() -> bool {
+    true
+}
+
+fn default_generated_markers() -> RegexSet {
+    RegexSet::new([r"@generated\b", r"Code generated .* DO NOT EDIT"])
+        .expect("Invalid default generated markers")
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct EngineConfig {
+    /// Target triple to compile for, e.g. `x86_64-unknown-linux-gnu`. This
+    /// must match the host. Setting it (even to the host triple) disables
+    /// detection of host CPU features, which means the compiled code doesn't
+    /// depend on exactly which CPU you have.
+    pub target: Option<String>,
+
+    /// Cranelift optimisation level. Defaults to `speed`.
+    pub opt_level: Option<OptLevel>,
+
+    /// Whether to canonicalise NaN values for fully deterministic execution.
+    /// Defaults to false.
+    pub nan_canonicalization: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TextNormalization {
+    /// Which text files to normalise, in addition to the config's `include`.
+    /// Only UTF-8 text files are normalised. Defaults to all of them.
+    #[serde(default, rename = "match")]
+    pub match_expression: MatchExpression,
+
+    /// Number of newlines at the end of the file. Defaults to `keep`.
+    #[serde(default)]
+    pub final_newline: FinalNewline,
+
+    /// Whether to strip spaces and tabs from the ends of lines. Defaults to
+    /// `keep`.
+    #[serde(default)]
+    pub trailing_whitespace: TrailingWhitespace,
+
+    /// Line endings to convert to. Defaults to `keep`.
+    #[serde(default)]
+    pub line_endings: LineEndings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FinalNewline {
+    /// Remove all newlines from the end of the file.
+    None,
+    /// End the file with exactly one newline. Blank lines at the end are
+    /// removed.
+    One,
+    #[default]
+    Keep,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingWhitespace {
+    Strip,
+    #[default]
+    Keep,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEndings {
+    Lf,
+    Crlf,
+    #[default]
+    Keep,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum OptLevel {
+    None,
+    Speed,
+    SpeedAndSize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct RemoteLocation {
     /// URL of Wasm module to download.
     pub url: String,
 
     /// Hash of the Wasm binary module for integrity.
-    pub hash: String,
+    pub hash: Option<String>,
+
+    /// The start of the hash, instead of `hash`. This is less secure so it's
+    /// only intended for local experiments. It must be at least
+    /// `MIN_HASH_PREFIX_LEN` characters.
+    pub hash_prefix: Option<String>,
     // Commit of the source repo. If this is specified
     // you can be guaranteed that the binary was built
     // from that source.
@@ -31,7 +171,56 @@ pub struct RemoteLocation {
     // pub source_hash: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+/// Minimum length of `RemoteLocation::hash_prefix`, in hex characters. This
+/// is 64 bits, which still makes finding another file with the same prefix
+/// very expensive.
+pub const MIN_HASH_PREFIX_LEN: usize = 16;
+
+impl RemoteLocation {
+    /// Check that exactly one of `hash` and `hash_prefix` is set, and that
+    /// it's valid.
+    fn check_hash(&self) -> Result<()> {
+        let is_hex = |hash: &str| hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        match (&self.hash, &self.hash_prefix) {
+            (Some(hash), None) if hash.len() == 64 && is_hex(hash) => Ok(()),
+            (Some(hash), None) => bail!("Invalid hash for {}: {hash}", self.url),
+            (None, Some(prefix))
+                if (MIN_HASH_PREFIX_LEN..=64).contains(&prefix.len()) && is_hex(prefix) =>
+            {
+                Ok(())
+            }
+            (None, Some(prefix)) => bail!(
+                "Invalid hash_prefix for {}: {prefix}. It must be {MIN_HASH_PREFIX_LEN} to 64 lowercase hex characters",
+                self.url
+            ),
+            (Some(_), Some(_)) => bail!(
+                "Only one of hash and hash_prefix can be set for {}",
+                self.url
+            ),
+            (None, None) => bail!("No hash for {}", self.url),
+        }
+    }
+
+    /// Returns true if `hash` (of a downloaded file) is the expected one.
+    pub fn hash_matches(&self, hash: &str) -> bool {
+        match (&self.hash, &self.hash_prefix) {
+            (Some(expected), _) => hash == expected,
+            (None, Some(prefix)) => hash.starts_with(prefix.as_str()),
+            (None, None) => false,
+        }
+    }
+
+    /// The expected hash, for error messages.
+    pub fn expected_hash(&self) -> String {
+        match (&self.hash, &self.hash_prefix) {
+            (Some(hash), _) => hash.clone(),
+            (None, Some(prefix)) => format!("{prefix}..."),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum LinterLocation {
     /// URL of Wasm module to download.
@@ -41,7 +230,7 @@ pub enum LinterLocation {
     Local(String),
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ConfigLinter {
     /// Name of the linter, for log messages.
     pub name: String,
@@ -54,18 +243,654 @@ pub struct ConfigLinter {
     pub override_match: Option<MatchExpression>,
 
     /// Replace arguments from the linter config. By convention there
-    /// will be an `extra` block that you can replace.
+    /// will be an `extra` block that you can replace. Each block is a list
+    /// of arguments, or a string that is split like a shell would, e.g.
+    /// `"--max-width 100 --name 'a b'"`.
+    #[serde(default, deserialize_with = "deserialize_override_args")]
     pub override_args: Option<BTreeMap<String, Vec<String>>>,
+
+    /// Replace the `argv0` from the linter metadata. This is useful for
+    /// multi-call binaries that select a tool based on argv[0].
+    pub override_argv0: Option<String>,
+
+    /// Replace `max_filenames` from the linter metadata. 0 means the linter
+    /// is run once without any filenames.
+    pub override_max_filenames: Option<u64>,
+
+    /// Replace `require_serial` from the linter metadata, e.g. for a tool
+    /// that deadlocks when run in parallel on your machine.
+    pub override_require_serial: Option<bool>,
+
+    /// Only run this linter if the current branch name matches this regex.
+    /// Use anchors (`^main$`) to match exactly. In detached HEAD state the
+    /// branch name is empty, so this only matches if the regex matches
+    /// the empty string.
+    #[serde(default, with = "crate::serde_regex")]
+    pub branches: Option<Regex>,
+
+    /// Report failures from this linter but don't fail the run. This is
+    /// useful for trying out a new linter before enforcing it.
+    #[serde(default)]
+    pub allow_failure: bool,
+
+    /// Names of linters that must pass for this one to run. They must come
+    /// before this linter in the list. If any of them fail (or are skipped
+    /// because of their own dependencies) this linter is skipped.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    /// Number of times to re-run this linter if it errors in a way that
+    /// might be transient (e.g. it crashes or runs out of memory), for tools
+    /// that fail intermittently. It isn't re-run if it finds problems, or
+    /// for errors that would happen again, like invalid metadata.
+    #[serde(default)]
+    pub retries: u32,
+
+    /// The `repo` that the linter's metadata must have, e.g.
+    /// `https://github.com/example/lint_foo`. The linter isn't run if it
+    /// doesn't match, which catches a binary that has been swapped for a
+    /// different linter. Trailing slashes, `.git` and case are ignored.
+    pub expected_repo: Option<String>,
+
+    /// Tags for selecting groups of linters with `nit run --tag` and
+    /// `--not-tag`, e.g. `["fast", "format"]`.
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
-/// Read JSON config. We always read in JSON5 so this works with JSONC and JSON too.
-pub fn read_config(path: &Path) -> Result<Config> {
-    let content = std::fs::read_to_string(path)?;
+/// An argument block in `override_args`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ArgsOverride {
+    List(Vec<String>),
+    Shell(String),
+}
+
+fn deserialize_override_args<'de, D>(
+    deserializer: D,
+) -> Result<Option<BTreeMap<String, Vec<String>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(blocks) = Option::<BTreeMap<String, ArgsOverride>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    blocks
+        .into_iter()
+        .map(|(name, args)| {
+            let args = match args {
+                ArgsOverride::List(args) => args,
+                ArgsOverride::Shell(args) => shell_words::split(&args).map_err(|e| {
+                    D::Error::custom(format!("Invalid arguments for block '{name}': {e}"))
+                })?,
+            };
+            Ok((name, args))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+impl ConfigLinter {
+    /// Parse a linter given on the command line, for trying out linters that
+    /// aren't in the config. The format is `<location>[:<args>]`, where
+    /// `<location>` is a path (relative to `cwd`) or a URL. URLs need the
+    /// hash (or a hash prefix) after a `#`. `<args>` are separated by spaces
+    /// and replace the linter's `extra` argument block.
+    pub fn from_spec(spec: &str, cwd: &Path) -> Result<Self> {
+        // Only look for the args after the last path component, so that
+        // `C:\` and ports in URLs aren't treated as the separator.
+        let filename_start = spec.rfind(['/', '\\']).map_or(0, |i| i + 1);
+        let (location, args) = match spec[filename_start..].find(':') {
+            Some(i) => (
+                &spec[..filename_start + i],
+                Some(&spec[filename_start + i + 1..]),
+            ),
+            None => (spec, None),
+        };
 
-    serde_json5::from_str(&content).map_err(|e| {
+        let (name, location) =
+            if location.starts_with("https://") || location.starts_with("http://") {
+                let Some((url, hash)) = location.split_once('#') else {
+                    bail!("No hash for {location}. Add it after a '#', e.g. {location}#<hash>");
+                };
+                let (hash, hash_prefix) = if hash.len() == 64 {
+                    (Some(hash.to_owned()), None)
+                } else {
+                    (None, Some(hash.to_owned()))
+                };
+                let remote = RemoteLocation {
+                    url: url.to_owned(),
+                    hash,
+                    hash_prefix,
+                };
+                remote.check_hash()?;
+                (url, LinterLocation::Remote(remote))
+            } else {
+                // Local paths are joined to the repo root, and joining an absolute
+                // path replaces it.
+                let path = cwd.join(location);
+                (
+                    location,
+                    LinterLocation::Local(path.to_string_lossy().into_owned()),
+                )
+            };
+
+        let override_args = args
+            .map(|args| args.split_whitespace().map(ToOwned::to_owned).collect())
+            .map(|args| BTreeMap::from([("extra".to_owned(), args)]));
+
+        Ok(Self {
+            name: name.to_owned(),
+            location,
+            override_match: None,
+            override_args,
+            override_argv0: None,
+            override_max_filenames: None,
+            override_require_serial: None,
+            branches: None,
+            allow_failure: false,
+            depends_on: Vec::new(),
+            retries: 0,
+            expected_repo: None,
+            tags: Vec::new(),
+        })
+    }
+}
+
+/// Format of a config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// JSON5, which also covers JSONC and JSON.
+    Json5,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// Get the format from the file extension. Unknown extensions are
+    /// read as JSON5.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("toml") => Self::Toml,
+            _ => Self::Json5,
+        }
+    }
+
+    fn parse<T: DeserializeOwned>(self, content: &str) -> Result<T> {
+        Ok(match self {
+            Self::Json5 => serde_json5::from_str(content)?,
+            // serde_yaml only accepts enums written as `!tags`, so go via
+            // JSON to accept the `{ variant: value }` form the other formats
+            // use.
+            Self::Yaml => {
+                serde_json::from_value(serde_yaml::from_str::<serde_json::Value>(content)?)?
+            }
+            Self::Toml => toml::from_str(content)?,
+        })
+    }
+}
+
+/// Directory next to the config file containing fragments of config, each
+/// of which has a `linters` list to append to the config's.
+pub const FRAGMENTS_DIR: &str = ".nit.d";
+
+/// A config fragment from `FRAGMENTS_DIR`.
+#[derive(Deserialize, Serialize, Debug)]
+struct ConfigFragment {
+    linters: Vec<ConfigLinter>,
+}
+
+/// Read config. JSON5 is read by default, so this works with JSONC and JSON
+/// too. Files ending in `.yaml`/`.yml` or `.toml` are read as YAML or TOML.
+/// Unknown keys are ignored so that configs for newer versions of Nit can
+/// still be read, unless `strict` is set.
+///
+/// Linters from any fragments in `FRAGMENTS_DIR` are appended to the config's
+/// in filename order.
+pub fn read_config(path: &Path, strict: bool) -> Result<Config> {
+    let mut config: Config = parse_config_file(path, strict)?;
+
+    let fragments_dir = path.parent().unwrap_or(Path::new(".")).join(FRAGMENTS_DIR);
+    for fragment_path in fragment_paths(&fragments_dir)? {
+        let fragment: ConfigFragment = parse_config_file(&fragment_path, strict)?;
+        for linter in fragment.linters {
+            if config.linters.iter().any(|l| l.name == linter.name) {
+                bail!(
+                    "Duplicate linter name '{}' in config fragment ({})",
+                    linter.name,
+                    fragment_path.display()
+                );
+            }
+            config.linters.push(linter);
+        }
+    }
+
+    config
+        .resolve_refs()
+        .and_then(|()| config.check_dependencies())
+        .and_then(|()| config.check_hashes())
+        .with_context(|| anyhow!("Invalid config ({})", path.display()))?;
+    Ok(config)
+}
+
+/// Parse a config file (or fragment) in the format given by its extension.
+fn parse_config_file<T: DeserializeOwned + Serialize>(path: &Path, strict: bool) -> Result<T> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| anyhow!("Failed to read {}", path.display()))?;
+    let format = ConfigFormat::from_path(path);
+
+    let config: T = format.parse(&content).map_err(|e| {
         anyhow!(
             "Config deserialization error ({path}): {e}",
             path = path.display()
         )
-    })
+    })?;
+    if strict {
+        // This must be done before refs are resolved, since that changes the keys.
+        let unknown = unknown_keys(format.parse(&content)?, &config)?;
+        if !unknown.is_empty() {
+            bail!(
+                "Unknown keys in config ({}): {}",
+                path.display(),
+                unknown.join(", ")
+            );
+        }
+    }
+    Ok(config)
+}
+
+/// Get the config fragments in `dir` in filename order. It's fine if `dir`
+/// doesn't exist. Hidden files (e.g. editor swap files) are skipped.
+fn fragment_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| anyhow!("Failed to read {}", dir.display())),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| anyhow!("Failed to read {}", dir.display()))?;
+        if entry.file_name().to_string_lossy().starts_with('.') || !entry.path().is_file() {
+            continue;
+        }
+        paths.push(entry.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Find keys in the parsed config `input` that were ignored when it was
+/// parsed into `config`, e.g. typos like `overide_args`. They are found by
+/// serialising `config` again and comparing the keys. The keys are returned
+/// with their paths, e.g. `linters[0].overide_args`.
+fn unknown_keys(input: Value, config: &impl Serialize) -> Result<Vec<String>> {
+    fn visit(input: &Value, known: &Value, path: &str, unknown: &mut Vec<String>) {
+        match (input, known) {
+            (Value::Object(input), Value::Object(known)) => {
+                for (key, value) in input {
+                    let path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{path}.{key}")
+                    };
+                    match known.get(key) {
+                        Some(known) => visit(value, known, &path, unknown),
+                        None => unknown.push(path),
+                    }
+                }
+            }
+            (Value::Array(input), Value::Array(known)) => {
+                for (index, (value, known)) in input.iter().zip(known).enumerate() {
+                    visit(value, known, &format!("{path}[{index}]"), unknown);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let known = serde_json::to_value(config)?;
+    let mut unknown = Vec::new();
+    visit(&input, &known, "", &mut unknown);
+    unknown.sort();
+    Ok(unknown)
+}
+
+impl Config {
+    fn check_hashes(&self) -> Result<()> {
+        for linter in &self.linters {
+            if let LinterLocation::Remote(remote) = &linter.location {
+                remote
+                    .check_hash()
+                    .with_context(|| anyhow!("In linter '{}'", linter.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `depends_on` only refers to earlier linters. That also
+    /// means there can't be any cycles, but they are reported separately
+    /// because the error is clearer.
+    fn check_dependencies(&self) -> Result<()> {
+        let dependencies: BTreeMap<&str, Vec<&str>> = self
+            .linters
+            .iter()
+            .map(|linter| {
+                (
+                    linter.name.as_str(),
+                    linter.depends_on.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+
+        fn visit<'a>(
+            name: &'a str,
+            dependencies: &BTreeMap<&'a str, Vec<&'a str>>,
+            stack: &mut Vec<&'a str>,
+        ) -> Result<()> {
+            if stack.contains(&name) {
+                bail!("Linter dependency cycle: {} -> {name}", stack.join(" -> "));
+            }
+            let Some(depends_on) = dependencies.get(name) else {
+                bail!(
+                    "Linter '{}' depends on unknown linter '{name}'",
+                    stack.last().unwrap_or(&"")
+                );
+            };
+            stack.push(name);
+            for dependency in depends_on {
+                visit(dependency, dependencies, stack)?;
+            }
+            stack.pop();
+            Ok(())
+        }
+        for name in dependencies.keys() {
+            visit(name, &dependencies, &mut Vec::new())?;
+        }
+
+        for (index, linter) in self.linters.iter().enumerate() {
+            for dependency in &linter.depends_on {
+                if !self.linters[..index].iter().any(|l| &l.name == dependency) {
+                    bail!(
+                        "Linter '{}' depends on '{dependency}', so it must come after it",
+                        linter.name
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace references to `definitions` in the match expressions.
+    fn resolve_refs(&mut self) -> Result<()> {
+        // Check all definitions, even unused ones.
+        for definition in self.definitions.values() {
+            resolve_refs(&mut definition.clone(), &self.definitions)?;
+        }
+        resolve_refs(&mut self.include, &self.definitions)?;
+        if let Some(normalization) = &mut self.text_normalization {
+            resolve_refs(&mut normalization.match_expression, &self.definitions)
+                .context("In text_normalization")?;
+        }
+        for linter in &mut self.linters {
+            if let Some(expr) = &mut linter.override_match {
+                resolve_refs(expr, &self.definitions)
+                    .with_context(|| anyhow!("In linter '{}'", linter.name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{file_matching::matching_files, git::text_file as file};
+
+    #[test]
+    fn test_hash_prefix() {
+        let hash = "becf10f9e95dbb08d66b01a662c6041abb53aac64f3af669e81b6abd24b7b015";
+        let remote = |hash: Option<&str>, hash_prefix: Option<&str>| RemoteLocation {
+            url: "https://example.com/lint.wasm".to_owned(),
+            hash: hash.map(ToOwned::to_owned),
+            hash_prefix: hash_prefix.map(ToOwned::to_owned),
+        };
+
+        let full = remote(Some(hash), None);
+        assert!(full.check_hash().is_ok());
+        assert!(full.hash_matches(hash));
+        assert!(!full.hash_matches(&hash[..16]));
+
+        let prefix = remote(None, Some(&hash[..16]));
+        assert!(prefix.check_hash().is_ok());
+        assert!(prefix.hash_matches(hash));
+        assert!(!prefix.hash_matches(&hash[1..]));
+
+        assert!(remote(None, Some(&hash[..15])).check_hash().is_err());
+        assert!(remote(None, Some("BECF10F9E95DBB08")).check_hash().is_err());
+        assert!(remote(Some(hash), Some(hash)).check_hash().is_err());
+        assert!(remote(None, None).check_hash().is_err());
+    }
+
+    #[test]
+    fn test_linter_from_spec() {
+        let cwd = Path::new("/repo/sub");
+
+        let linter = ConfigLinter::from_spec("lints/foo.wasm", cwd).unwrap();
+        assert_eq!(linter.name, "lints/foo.wasm");
+        assert!(
+            matches!(&linter.location, LinterLocation::Local(path) if path == "/repo/sub/lints/foo.wasm")
+        );
+        assert!(linter.override_args.is_none());
+
+        let linter = ConfigLinter::from_spec("/abs/foo.wasm:--fix  --all", cwd).unwrap();
+        assert!(matches!(&linter.location, LinterLocation::Local(path) if path == "/abs/foo.wasm"));
+        assert_eq!(
+            linter.override_args,
+            Some(BTreeMap::from([(
+                "extra".to_owned(),
+                vec!["--fix".to_owned(), "--all".to_owned()]
+            )]))
+        );
+
+        let hash = "becf10f9e95dbb08d66b01a662c6041abb53aac64f3af669e81b6abd24b7b015";
+        let linter =
+            ConfigLinter::from_spec(&format!("https://host:8080/foo.wasm#{hash}:-v"), cwd).unwrap();
+        assert_eq!(linter.name, "https://host:8080/foo.wasm");
+        let LinterLocation::Remote(remote) = &linter.location else {
+            panic!("Expected a remote linter");
+        };
+        assert_eq!(remote.url, "https://host:8080/foo.wasm");
+        assert_eq!(remote.hash.as_deref(), Some(hash));
+        assert!(linter.override_args.is_some());
+
+        let linter =
+            ConfigLinter::from_spec(&format!("https://host/foo.wasm#{}", &hash[..16]), cwd)
+                .unwrap();
+        assert!(matches!(&linter.location, LinterLocation::Remote(r) if r.hash_prefix.is_some()));
+
+        assert!(ConfigLinter::from_spec("https://host/foo.wasm", cwd).is_err());
+        assert!(ConfigLinter::from_spec("https://host/foo.wasm#abc", cwd).is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let content = r#"{
+            linterz: [],
+            definitions: { rust: { glob: "*.rs" } },
+            include: { and: [{ ref: "rust" }, { not: "generated_marker" }] },
+            linters: [
+                {
+                    name: "foo",
+                    location: { local: "foo.wasm" },
+                    overide_args: { extra: [] },
+                    override_args: { extra: ["--fix"] },
+                },
+            ],
+            engine: { opt_lvl: "none" },
+        }"#;
+        let config: Config = serde_json5::from_str(content).unwrap();
+        let input = serde_json5::from_str(content).unwrap();
+        assert_eq!(
+            unknown_keys(input, &config).unwrap(),
+            ["engine.opt_lvl", "linters[0].overide_args", "linterz"]
+        );
+
+        let sample_config = include_str!("../sample_config.json5");
+        let config: Config = serde_json5::from_str(sample_config).unwrap();
+        let input = serde_json5::from_str(sample_config).unwrap();
+        assert!(unknown_keys(input, &config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_override_args_string() {
+        let args = |json: &str| {
+            serde_json5::from_str::<ConfigLinter>(&format!(
+                r#"{{ name: "foo", location: {{ local: "foo.wasm" }}, override_args: {json} }}"#
+            ))
+            .map(|linter| linter.override_args.unwrap())
+        };
+
+        assert_eq!(
+            args(r#"{ extra: "--max-width 100  --edition 2021" }"#).unwrap()["extra"],
+            ["--max-width", "100", "--edition", "2021"]
+        );
+        assert_eq!(
+            args(r#"{ extra: "--name 'a b' \"c \\\"d\\\"\" e\\ f" }"#).unwrap()["extra"],
+            ["--name", "a b", "c \"d\"", "e f"]
+        );
+        assert_eq!(
+            args(r#"{ extra: "" }"#).unwrap()["extra"],
+            Vec::<String>::new()
+        );
+        // The list form is used as is.
+        assert_eq!(
+            args(r#"{ base: ["a b"], extra: "'c d'" }"#).unwrap(),
+            BTreeMap::from([
+                ("base".to_owned(), vec!["a b".to_owned()]),
+                ("extra".to_owned(), vec!["c d".to_owned()]),
+            ])
+        );
+        assert!(args(r#"{ extra: "'unterminated" }"#).is_err());
+    }
+
+    #[test]
+    fn test_config_formats() {
+        let to_json = |format: ConfigFormat, content: &str| {
+            let config: Config = format.parse(content).unwrap();
+            serde_json::to_string(&config).unwrap()
+        };
+
+        let json5 = to_json(
+            ConfigFormat::Json5,
+            r#"{
+                include: { and: [{ glob: "*.rs" }, { not: "generated_marker" }] },
+                linters: [
+                    {
+                        name: "foo",
+                        location: { local: "foo.wasm" },
+                        override_match: { type: "text" },
+                        override_args: { extra: ["--fix"] },
+                    },
+                ],
+            }"#,
+        );
+        let yaml = to_json(
+            ConfigFormat::Yaml,
+            r#"
+include:
+  and:
+    - glob: "*.rs"
+    - not: generated_marker
+linters:
+  - name: foo
+    location:
+      local: foo.wasm
+    override_match:
+      type: text
+    override_args:
+      extra: ["--fix"]
+"#,
+        );
+        let toml = to_json(
+            ConfigFormat::Toml,
+            r#"
+include = { and = [{ glob = "*.rs" }, { not = "generated_marker" }] }
+
+[[linters]]
+name = "foo"
+location = { local = "foo.wasm" }
+override_match = { type = "text" }
+override_args = { extra = ["--fix"] }
+"#,
+        );
+        assert_eq!(yaml, json5);
+        assert_eq!(toml, json5);
+
+        assert_eq!(
+            ConfigFormat::from_path(Path::new(".nit.yml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new(".nit.jsonc")),
+            ConfigFormat::Json5
+        );
+    }
+
+    #[test]
+    fn test_config_fragments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".nit.json5");
+        std::fs::write(
+            &path,
+            r#"{
+                definitions: { rust: { glob: "*.rs" } },
+                linters: [{ name: "a", location: { local: "a.wasm" } }],
+            }"#,
+        )
+        .unwrap();
+        let fragments = dir.path().join(FRAGMENTS_DIR);
+        std::fs::create_dir(&fragments).unwrap();
+        std::fs::write(
+            fragments.join("2.toml"),
+            "[[linters]]\nname = \"c\"\nlocation = { local = \"c.wasm\" }\ndepends_on = [\"b\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            fragments.join("1.yaml"),
+            "linters:\n  - name: b\n    location: { local: b.wasm }\n    override_match: { ref: rust }\n",
+        )
+        .unwrap();
+
+        let config = read_config(&path, true).unwrap();
+        let names: Vec<&str> = config.linters.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        // Refs to the base config's definitions are resolved.
+        assert!(matches!(
+            config.linters[1].override_match,
+            Some(MatchExpression::Glob(_))
+        ));
+
+        std::fs::write(
+            fragments.join("3.json"),
+            r#"{ "linters": [{ "name": "a", "location": { "local": "a2.wasm" } }] }"#,
+        )
+        .unwrap();
+        let error = read_config(&path, true).unwrap_err();
+        assert!(format!("{error:#}").contains("Duplicate linter name 'a'"));
+    }
+
+    #[test]
+    fn test_default_include() {
+        let files = vec![file("foo.rs")];
+
+        let config: Config = serde_json5::from_str("{ linters: [] }").unwrap();
+        assert_eq!(matching_files(&files, &config.include).len(), 1);
+        assert!(config.cache_results);
+
+        // A present `include` is still checked.
+        assert!(
+            serde_json5::from_str::<Config>(r#"{ include: { glob: 1 }, linters: [] }"#).is_err()
+        );
+    }
 }
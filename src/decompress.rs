@@ -0,0 +1,160 @@
+use std::io::Read as _;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+
+/// Compression formats that downloaded linters can use. These are detected
+/// from the data itself, because servers don't always set `Content-Encoding`
+/// (e.g. for `.wasm.gz` files), and may ignore `Accept-Encoding`.
+#[derive(Debug, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Value for the `Accept-Encoding` header when downloading linters.
+pub const ACCEPT_ENCODING: &str = "zstd, gzip";
+
+/// Largest decompressed linter we accept, so a small malicious download
+/// can't decompress to fill the memory or disk.
+const MAX_DECOMPRESSED_SIZE: usize = 1 << 30;
+
+impl Compression {
+    fn detect(data: &[u8]) -> Self {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Decompress `data` if it's gzip or zstd compressed. Returns `None` if it
+/// isn't compressed.
+pub fn decompress(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    decompress_with_limit(data, MAX_DECOMPRESSED_SIZE)
+}
+
+/// Like `decompress()`, but fail if the output would be more than
+/// `max_size` bytes.
+fn decompress_with_limit(data: &[u8], max_size: usize) -> Result<Option<Vec<u8>>> {
+    match Compression::detect(data) {
+        Compression::None => Ok(None),
+        Compression::Gzip => gunzip(data, max_size).map(Some),
+        Compression::Zstd => unzstd(data, max_size).map(Some),
+    }
+}
+
+fn too_large(max_size: usize) -> anyhow::Error {
+    anyhow!("Decompressed linter is larger than the limit of {max_size} bytes")
+}
+
+fn unzstd(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let decoder = zstd::stream::read::Decoder::new(data).context("Decompressing zstd data")?;
+    let mut decompressed = Vec::new();
+    // Read one byte more than allowed to tell if there is more.
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .context("Decompressing zstd data")?;
+    if decompressed.len() > max_size {
+        return Err(too_large(max_size));
+    }
+    Ok(decompressed)
+}
+
+// Gzip header flags.
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+/// Decompress a gzip member (RFC 1952). The CRC isn't checked because the
+/// linter's hash is checked afterwards anyway.
+fn gunzip(data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let truncated = || anyhow!("Truncated gzip header");
+
+    // ID1, ID2, CM, FLG, MTIME (4), XFL, OS
+    let header = data.get(..10).ok_or_else(truncated)?;
+    if header[2] != 8 {
+        bail!("Unsupported gzip compression method {}", header[2]);
+    }
+    let flags = header[3];
+    let mut rest = &data[10..];
+
+    if flags & FEXTRA != 0 {
+        let len = rest.get(..2).ok_or_else(truncated)?;
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        rest = rest.get(2 + len..).ok_or_else(truncated)?;
+    }
+    // Zero-terminated strings.
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = memchr::memchr(0, rest).ok_or_else(truncated)?;
+            rest = &rest[end + 1..];
+        }
+    }
+    if flags & FHCRC != 0 {
+        rest = rest.get(2..).ok_or_else(truncated)?;
+    }
+
+    miniz_oxide::inflate::decompress_to_vec_with_limit(rest, max_size).map_err(|e| {
+        if e.status == miniz_oxide::inflate::TINFLStatus::HasMoreOutput {
+            too_large(max_size)
+        } else {
+            anyhow!("Decompressing gzip data: {e}")
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    #[test]
+    fn test_decompress() {
+        // Uncompressed data is left alone.
+        assert_eq!(decompress(WASM).unwrap(), None);
+
+        let zstd = zstd::stream::encode_all(WASM, 0).unwrap();
+        assert_eq!(decompress(&zstd).unwrap().as_deref(), Some(WASM));
+
+        // Header with FNAME set, followed by the deflate stream and trailer.
+        let mut gzip = vec![0x1f, 0x8b, 8, FNAME, 0, 0, 0, 0, 0, 3];
+        gzip.extend_from_slice(b"lint.wasm\0");
+        gzip.extend(miniz_oxide::deflate::compress_to_vec(WASM, 6));
+        gzip.extend([0; 8]);
+        assert_eq!(decompress(&gzip).unwrap().as_deref(), Some(WASM));
+
+        assert!(decompress(&[0x1f, 0x8b, 8]).is_err());
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let zeros = vec![0; 1000];
+        let limit = |data: &[u8], max_size| {
+            decompress_with_limit(data, max_size).map(|data| data.map(|data| data.len()))
+        };
+
+        let zstd = zstd::stream::encode_all(zeros.as_slice(), 0).unwrap();
+        assert_eq!(limit(&zstd, 1000).unwrap(), Some(1000));
+        let err = limit(&zstd, 999).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Decompressed linter is larger than the limit of 999 bytes"
+        );
+
+        let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+        gzip.extend(miniz_oxide::deflate::compress_to_vec(&zeros, 6));
+        gzip.extend([0; 8]);
+        assert_eq!(limit(&gzip, 1000).unwrap(), Some(1000));
+        assert_eq!(
+            limit(&gzip, 999).unwrap_err().to_string(),
+            "Decompressed linter is larger than the limit of 999 bytes"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Directory that linters with `structured_diagnostics` can write their
+/// diagnostics to. It's a separate preopen from the repo.
+pub const DIAGNOSTICS_DIR: &str = "/.nit-diagnostics";
+
+/// File in `DIAGNOSTICS_DIR` that the diagnostics are written to, as JSON
+/// lines. Its full path is passed to linters in `NIT_DIAGNOSTICS_FILE`.
+pub const DIAGNOSTICS_FILENAME: &str = "diagnostics.jsonl";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        })
+    }
+}
+
+/// A problem reported by a linter. Linters that set `structured_diagnostics`
+/// in their metadata write one of these per line, e.g.
+///
+///     {"path":"src/foo.rs","line":3,"column":5,"severity":"warning","message":"Unused variable","rule":"unused"}
+///
+/// Only `path` and `message` are required.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub path: String,
+    /// 1-based line number.
+    pub line: Option<u64>,
+    /// 1-based column number. Ignored if there's no `line`.
+    pub column: Option<u64>,
+    #[serde(default)]
+    pub severity: Severity,
+    pub message: String,
+    /// Name of the check that failed, e.g. `trailing-whitespace`.
+    pub rule: Option<String>,
+}
+
+/// Formatted like most linters' output, `<path>:<line>:<column>: ...`, so
+/// that e.g. `--only-changed-hunks` works with it.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+            if let Some(column) = self.column {
+                write!(f, ":{column}")?;
+            }
+        }
+        write!(f, ": {}: {}", self.severity, self.message)?;
+        if let Some(rule) = &self.rule {
+            write!(f, " [{rule}]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse JSON lines of diagnostics. Invalid lines are skipped with a warning.
+pub fn parse_diagnostics(data: &[u8], linter_name: &str) -> Vec<Diagnostic> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            serde_json::from_str(line)
+                .inspect_err(|e| {
+                    warn!("Ignoring invalid diagnostic from linter {linter_name}: {e}: {line}")
+                })
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let data =
+            br#"{"path":"a.rs","line":3,"column":5,"severity":"warning","message":"bad","rule":"r"}
+not json
+
+{"path":"b.rs","message":"whole file"}
+{"path":"c.rs","line":1,"message":"no column"}
+"#;
+        let diagnostics: Vec<String> = parse_diagnostics(data, "test")
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            diagnostics,
+            [
+                "a.rs:3:5: warning: bad [r]",
+                "b.rs: error: whole file",
+                "c.rs:1: error: no column",
+            ]
+        );
+    }
+}
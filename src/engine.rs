@@ -1,26 +1,44 @@
 use anyhow::{Context as _, Result, anyhow, bail};
 use futures::{StreamExt as _, stream};
-use log::{debug, info};
+use log::{Level, debug, info, log_enabled, warn};
 use std::{
-    collections::BTreeSet,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap},
     env,
     path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
 };
 use wasmtime::{
-    Engine, Store,
+    Engine, Store, Trap,
     component::{Component, Linker},
 };
-use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, ResourceTable};
+use wasmtime_wasi::{
+    DirPerms, FilePerms, I32Exit, ResourceTable, WasiCtx, WasiCtxBuilder, WasiCtxView, WasiView,
+};
 
 use wasmtime_wasi::p2::{
-    IoView, WasiCtx, WasiCtxBuilder, WasiView, bindings::Command, pipe::MemoryOutputPipe,
+    bindings::Command,
+    pipe::{MemoryInputPipe, MemoryOutputPipe},
 };
 
 use crate::{
-    config::{ConfigLinter, LinterLocation},
-    file_matching::matching_files,
-    git::FileInfo,
-    metadata::{ArgBlock, read_metadata},
+    config::{ConfigLinter, EngineConfig, LinterLocation, OptLevel},
+    diagnostics::{DIAGNOSTICS_DIR, DIAGNOSTICS_FILENAME, parse_diagnostics},
+    file_hashes::{FileHash, hash_file, rehash_file},
+    file_matching::{MatchExpression, includes_symlinks, matching_files, mismatch_reason},
+    git::{FileInfo, FileType, git_set_executable_bit},
+    git_requests::{GIT_REQUESTS_DIR, GIT_REQUESTS_FILENAME, GitRequest, parse_git_requests},
+    hunks::{ChangedLines, filter_diagnostics},
+    manifest::Manifest,
+    metadata::{ArgBlock, Granularity, NitMetadata, PathStyle, Stage},
+    output::Output,
+    profile,
+    results_cache::ResultsCache,
+    unique_filename::unique_filename,
     wasi_cache,
 };
 
@@ -29,14 +47,29 @@ pub fn get_cache_dir() -> Option<PathBuf> {
         Some(cache_dir.into())
     } else {
         dirs::cache_dir()
-            .or_else(|| dirs::home_dir())
+            .or_else(dirs::home_dir)
             .map(|d| d.join("nit"))
     }
 }
 
+/// Create `cache_dir` if necessary and check that we can write to it, so that
+/// a read-only or full filesystem is reported clearly rather than as an IO
+/// error partway through downloading.
+pub fn ensure_cache_dir(cache_dir: &Path) -> Result<()> {
+    let probe = cache_dir.join(unique_filename("tmp-", ".probe"));
+    let result = std::fs::create_dir_all(cache_dir).and_then(|_| std::fs::write(&probe, "probe"));
+    let _ = std::fs::remove_file(&probe);
+    result.with_context(|| {
+        anyhow!(
+            "Can't write to the cache directory {}. Set NIT_CACHE_DIR (or use --cache-dir) to use a writable directory with free space",
+            cache_dir.display()
+        )
+    })
+}
+
 /// Get the path to the .wasm file for a linter. This is either in the
 /// repo for local paths (starting with /) or in the cache directory for URLs.
-pub fn get_linter_path(top_level: &PathBuf, cache_dir: &Path, linter: &ConfigLinter) -> PathBuf {
+pub fn get_linter_path(top_level: &Path, cache_dir: &Path, linter: &ConfigLinter) -> PathBuf {
     match &linter.location {
         LinterLocation::Local(path) => top_level.join(path),
         LinterLocation::Remote(remote) => get_url_linter_path(cache_dir, &remote.url),
@@ -52,64 +85,209 @@ pub fn get_url_linter_path(cache_dir: &Path, url: &str) -> PathBuf {
     cache_dir.join(hash_str)
 }
 
+/// Create the WASM engine used to compile and run linters.
+pub fn make_engine(settings: &EngineConfig) -> Result<Engine> {
+    let mut config = wasmtime::Config::new();
+    config.async_support(true);
+
+    if let Some(target) = &settings.target {
+        config
+            .target(target)
+            .with_context(|| anyhow!("setting WASM engine target '{target}'"))?;
+    }
+    if let Some(opt_level) = settings.opt_level {
+        config.cranelift_opt_level(match opt_level {
+            OptLevel::None => wasmtime::OptLevel::None,
+            OptLevel::Speed => wasmtime::OptLevel::Speed,
+            OptLevel::SpeedAndSize => wasmtime::OptLevel::SpeedAndSize,
+        });
+    }
+    if let Some(nan_canonicalization) = settings.nan_canonicalization {
+        config.cranelift_nan_canonicalization(nan_canonicalization);
+    }
+    // Linters yield to the executor every epoch (see `EPOCH_PERIOD`), so they
+    // can be cancelled (e.g. by Ctrl-C) even if they never make a WASI call.
+    config.epoch_interruption(true);
+
+    let engine = Engine::new(&config).context("creating WASM engine")?;
+    let weak = engine.weak();
+    std::thread::spawn(move || {
+        // Stop when the engine is dropped.
+        while let Some(engine) = weak.upgrade() {
+            engine.increment_epoch();
+            drop(engine);
+            std::thread::sleep(EPOCH_PERIOD);
+        }
+    });
+    Ok(engine)
+}
+
+/// How often running linters yield.
+const EPOCH_PERIOD: Duration = Duration::from_millis(10);
+
 struct ComponentRunStates {
     wasi_ctx: WasiCtx,
     resource_table: ResourceTable,
+    memory: MemoryTracker,
+}
+
+/// Tracks the total size of a linter's linear memories. It doesn't limit
+/// them.
+#[derive(Default)]
+struct MemoryTracker {
+    current: usize,
+    peak: usize,
+}
+
+impl wasmtime::ResourceLimiter for MemoryTracker {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        self.current += desired.saturating_sub(current);
+        self.peak = self.peak.max(self.current);
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// The largest amount of memory used by any one run of a linter. Runs may
+/// be in parallel so this isn't the total.
+#[derive(Default)]
+pub struct PeakMemory(AtomicU64);
+
+impl PeakMemory {
+    fn record(&self, bytes: u64) {
+        self.0.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    /// Peak memory in bytes.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
 impl WasiView for ComponentRunStates {
-    fn ctx(&mut self) -> &mut WasiCtx {
-        &mut self.wasi_ctx
+    fn ctx(&mut self) -> WasiCtxView<'_> {
+        WasiCtxView {
+            ctx: &mut self.wasi_ctx,
+            table: &mut self.resource_table,
+        }
     }
 }
 
-impl IoView for ComponentRunStates {
-    fn table(&mut self) -> &mut ResourceTable {
-        &mut self.resource_table
+/// The linter's match expression, from the config or its metadata.
+pub fn linter_match<'a>(
+    metadata: &'a NitMetadata,
+    linter: &'a ConfigLinter,
+) -> &'a MatchExpression {
+    if let Some(m) = &linter.override_match {
+        m
+    } else {
+        &metadata.default_match
     }
 }
 
-/// Run a single linter and return whether all executions returned EXIT_SUCCESS.
-/// This does not check git diff.
-pub async fn run_single_linter(
-    files: &[FileInfo],
-    cache_dir: &PathBuf,
-    top_level: &PathBuf,
-    linter: ConfigLinter,
-) -> Result<bool> {
-    let linter_path = get_linter_path(top_level, cache_dir, &linter);
-    let metadata = read_metadata(&linter_path)?;
+/// Replace the settings in `metadata` that `linter` overrides.
+fn apply_overrides(metadata: &mut NitMetadata, linter: &ConfigLinter) {
+    if let Some(max_filenames) = linter.override_max_filenames {
+        if max_filenames == 0 && metadata.max_filenames != 0 {
+            warn!(
+                "override_max_filenames is 0 for linter {}, so it will be run once without any filenames",
+                linter.name
+            );
+        }
+        metadata.max_filenames = max_filenames;
+    }
+    if let Some(require_serial) = linter.override_require_serial {
+        metadata.require_serial = require_serial;
+    }
+}
 
-    log::info!("Running linter: {} ({})", linter.name, metadata.repo);
+/// Check the linter's metadata `repo` against the config's `expected_repo`.
+fn check_repo(metadata: &NitMetadata, linter: &ConfigLinter) -> Result<()> {
+    if metadata.repo.is_empty() && matches!(linter.location, LinterLocation::Remote(_)) {
+        warn!(
+            "Linter {} doesn't say which repo it was built from",
+            linter.name
+        );
+    }
+    let normalise = |repo: &str| {
+        let repo = repo.trim_end_matches('/');
+        repo.strip_suffix(".git")
+            .unwrap_or(repo)
+            .to_ascii_lowercase()
+    };
+    if let Some(expected) = &linter.expected_repo
+        && normalise(&metadata.repo) != normalise(expected)
+    {
+        bail!(
+            "Linter {} was built from '{}' but the config expects '{expected}'; the binary may have been replaced",
+            linter.name,
+            metadata.repo
+        );
+    }
+    Ok(())
+}
 
-    let files = matching_files(
-        files,
-        if let Some(m) = &linter.override_match {
-            m
-        } else {
-            &metadata.default_match
-        },
-    );
+/// Build the arguments for `linter` (including argv[0]) from its metadata,
+/// with the blocks in `override_args` replaced. Filenames are appended to
+/// these.
+pub fn linter_args<'a>(
+    metadata: &'a NitMetadata,
+    linter: &'a ConfigLinter,
+) -> Result<Vec<&'a str>> {
+    let argv0 = match &linter.override_argv0 {
+        Some(argv0) if argv0.is_empty() => {
+            bail!("override_argv0 for linter '{}' is empty", linter.name);
+        }
+        Some(argv0) => argv0,
+        None => &metadata.argv0,
+    };
 
-    let mut full_args: Vec<&str> = vec![metadata.argv0.as_str()];
+    let mut full_args: Vec<&'a str> = vec![argv0.as_str()];
 
     // Check that none of the override_args are invalid.
     if let Some(override_args) = &linter.override_args {
         let all_metadata_arg_names: BTreeSet<&str> =
             metadata.args.iter().map(|a| a.name.as_str()).collect();
-        for (arg, _) in override_args {
+        let overridable_arg_names: BTreeSet<&str> = metadata
+            .args
+            .iter()
+            .filter(|a| a.overridable)
+            .map(|a| a.name.as_str())
+            .collect();
+        for arg in override_args.keys() {
             if !all_metadata_arg_names.contains(arg.as_str()) {
                 bail!(
                     "Override arg '{}' isn't valid for linter '{}'. Valid options are {:?}.",
                     arg,
                     linter.name,
-                    all_metadata_arg_names
+                    overridable_arg_names
+                );
+            }
+            if !overridable_arg_names.contains(arg.as_str()) {
+                bail!(
+                    "Override arg '{}' for linter '{}' can't be overridden because the linter needs it. Overridable options are {:?}.",
+                    arg,
+                    linter.name,
+                    overridable_arg_names
                 );
             }
         }
     }
 
-    for ArgBlock { name, args } in metadata.args.iter() {
+    for ArgBlock { name, args, .. } in metadata.args.iter() {
         let args = linter
             .override_args
             .as_ref()
@@ -120,38 +298,510 @@ pub async fn run_single_linter(
         }
     }
 
+    Ok(full_args)
+}
+
+/// Filter `files` to the ones that `linter` should be run on, using the
+/// match expression from the config if there is one, or the linter's default.
+/// Symlinks are skipped unless the expression explicitly includes them, and
+/// binary files are skipped if the linter is `text_only`.
+/// The result is sorted by path so linters always see files in the same
+/// order, regardless of the order Git listed them in.
+///
+/// This also returns the matching files that were skipped because the linter
+/// doesn't support their encoding.
+fn select_files<'a>(
+    files: &'a [FileInfo],
+    metadata: &NitMetadata,
+    linter: &ConfigLinter,
+) -> (Vec<&'a FileInfo>, Vec<&'a FileInfo>) {
+    let expr = linter_match(metadata, linter);
+    let mut files = matching_files(files, expr);
+    if !includes_symlinks(expr) {
+        files.retain(|f| f.ty != FileType::Symlink);
+    }
+    if metadata.text_only {
+        files.retain(|f| !matches!(f.ty, FileType::Binary | FileType::ExecutableBinary));
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files.into_iter().partition(|f| {
+        metadata
+            .encodings
+            .as_ref()
+            .is_none_or(|encodings| encodings.contains(&f.encoding))
+    })
+}
+
+/// Whether `linter` matches none of `files`, so it has nothing to do and
+/// doesn't need to be fetched. This uses its `override_match`, or the
+/// metadata of a remote linter's binary if it was read on an earlier run.
+/// Otherwise it can't be known until the linter has been fetched.
+pub fn matches_no_files(files: &[FileInfo], linter: &ConfigLinter, manifest: &Manifest) -> bool {
+    let metadata = match &linter.location {
+        LinterLocation::Remote(remote) => remote
+            .hash
+            .as_ref()
+            .and_then(|hash| manifest.metadata_for_hash(hash, Path::new(&remote.url))),
+        LinterLocation::Local(_) => None,
+    };
+    if let Some(metadata) = metadata {
+        let (matching, unsupported) = select_files(files, &metadata, linter);
+        return matching.is_empty() && unsupported.is_empty();
+    }
+    let Some(expr) = &linter.override_match else {
+        return false;
+    };
+    let symlinks = includes_symlinks(expr);
+    !matching_files(files, expr)
+        .iter()
+        .any(|f| symlinks || f.ty != FileType::Symlink)
+}
+
+/// Maximum number of files that `log_mismatches()` explains.
+const MAX_LOGGED_MISMATCHES: usize = 10;
+
+/// Log how many of the candidate files matched a linter, and why some of the
+/// others didn't. This is for debugging match expressions.
+fn log_mismatches(files: &[FileInfo], metadata: &NitMetadata, linter: &ConfigLinter) {
+    let expr = linter_match(metadata, linter);
+    let matched = matching_files(files, expr).len();
+    debug!(
+        "Linter {}: {matched} of {} files matched",
+        linter.name,
+        files.len()
+    );
+    let mut rejected = files
+        .iter()
+        .filter_map(|file| mismatch_reason(file, expr).map(|reason| (file, reason)));
+    for (file, reason) in rejected.by_ref().take(MAX_LOGGED_MISMATCHES) {
+        debug!("  skipped {}: {reason}", file.path.display());
+    }
+    let remaining = rejected.count();
+    if remaining > 0 {
+        debug!("  ...and {remaining} more");
+    }
+}
+
+/// Get the paths to pass to a linter for `files`, with the total size of the
+/// files that each one covers.
+fn linter_paths(files: &[&FileInfo], granularity: Granularity) -> Vec<(PathBuf, u64)> {
+    match granularity {
+        Granularity::File => files.iter().map(|f| (f.path.clone(), f.size)).collect(),
+        Granularity::Directory => {
+            let mut directories: BTreeMap<PathBuf, u64> = BTreeMap::new();
+            for file in files {
+                let directory = match file.path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                *directories.entry(directory.to_owned()).or_default() += file.size;
+            }
+            directories.into_iter().collect()
+        }
+    }
+}
+
+/// Split `paths` into chunks of at most `max_filenames`, each of which is
+/// passed to one invocation of the linter. Chunks can run in parallel, so
+/// paths are distributed so that each chunk has roughly the same total size.
+/// The order of `paths` doesn't affect the result.
+fn chunk_filenames(
+    paths: &[(PathBuf, u64)],
+    max_filenames: usize,
+    path_style: PathStyle,
+) -> Result<Vec<Vec<String>>> {
+    let mut all_filenames = paths
+        .iter()
+        .map(|(path, _)| {
+            let path = path
+                .to_str()
+                .ok_or_else(|| anyhow!("Couldn't convert path to UTF-8: {:?}", path))?;
+            Ok(match path_style {
+                PathStyle::Relative => path.to_owned(),
+                PathStyle::Absolute if path == "." => "/".to_owned(),
+                PathStyle::Absolute => format!("/{path}"),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Add the largest first, each to the smallest chunk that has space.
+    let mut order: Vec<usize> = (0..paths.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (path_a, size_a) = &paths[a];
+        let (path_b, size_b) = &paths[b];
+        (size_b, path_a).cmp(&(size_a, path_b))
+    });
+
+    let num_chunks = paths.len().div_ceil(max_filenames);
+    let mut chunks = vec![Vec::new(); num_chunks];
+    let mut smallest: BinaryHeap<Reverse<(u64, usize)>> =
+        (0..num_chunks).map(|chunk| Reverse((0, chunk))).collect();
+    for index in order {
+        let Reverse((size, chunk)) = smallest
+            .pop()
+            .expect("there are enough chunks for all files");
+        chunks[chunk].push(index);
+        if chunks[chunk].len() < max_filenames {
+            smallest.push(Reverse((size + paths[index].1, chunk)));
+        }
+    }
+
+    Ok(chunks
+        .into_iter()
+        .map(|mut chunk| {
+            chunk.sort_by_key(|&index| &paths[index].0);
+            chunk
+                .into_iter()
+                .map(|index| std::mem::take(&mut all_filenames[index]))
+                .collect()
+        })
+        .collect())
+}
+
+/// Total size of the arguments above which we pass filenames on stdin
+/// instead if the linter supports it, or split them over more runs if not.
+/// Windows limits the command line to 32k characters and WASI runtimes may
+/// have their own limits, so this is deliberately conservative.
+const MAX_ARGV_BYTES: usize = 16 * 1024;
+
+/// How a chunk of filenames is given to a linter.
+#[derive(Debug, PartialEq, Eq)]
+enum FilenameInput<'a> {
+    /// Appended to the arguments.
+    Args(Vec<&'a str>),
+    /// Written to stdin, NUL separated.
+    Stdin(Vec<u8>),
+}
+
+/// Decide how to pass `chunk` to a linter that is run with `args`. Filenames
+/// are passed as arguments unless that would take the total size over
+/// `max_argv_bytes` and `filenames_on_stdin` is set.
+fn filename_input<'a>(
+    args: &[&str],
+    chunk: &[&'a str],
+    filenames_on_stdin: bool,
+    max_argv_bytes: usize,
+) -> FilenameInput<'a> {
+    // Each argument is NUL terminated.
+    let argv_bytes: usize = args.iter().chain(chunk).map(|a| a.len() + 1).sum();
+    if argv_bytes <= max_argv_bytes {
+        FilenameInput::Args(chunk.to_vec())
+    } else if filenames_on_stdin {
+        debug!("Arguments are {argv_bytes} bytes; passing filenames on stdin");
+        let mut stdin = Vec::with_capacity(argv_bytes);
+        for filename in chunk {
+            stdin.extend_from_slice(filename.as_bytes());
+            stdin.push(0);
+        }
+        FilenameInput::Stdin(stdin)
+    } else {
+        debug!("Arguments are {argv_bytes} bytes which may be too long for some platforms");
+        FilenameInput::Args(chunk.to_vec())
+    }
+}
+
+/// Split chunks (from `chunk_filenames()`) whose filenames would take the
+/// size of `args` and the filenames over `max_argv_bytes` into smaller
+/// chunks, so every linter works with long chunks without opting in.
+/// Linters with `filenames_on_stdin` are given long chunks on stdin instead
+/// (see `filename_input()`), so their chunks aren't split.
+fn split_long_chunks(
+    args: &[&str],
+    chunks: Vec<Vec<String>>,
+    filenames_on_stdin: bool,
+    max_argv_bytes: usize,
+) -> Vec<Vec<String>> {
+    if filenames_on_stdin {
+        return chunks;
+    }
+    // Each argument is NUL terminated.
+    let args_bytes: usize = args.iter().map(|arg| arg.len() + 1).sum();
+    let mut split = Vec::new();
+    for chunk in chunks {
+        let mut current = Vec::new();
+        let mut bytes = args_bytes;
+        for filename in chunk {
+            let len = filename.len() + 1;
+            // A filename that is too long on its own still gets a run.
+            if !current.is_empty() && bytes + len > max_argv_bytes {
+                split.push(std::mem::take(&mut current));
+                bytes = args_bytes;
+            }
+            bytes += len;
+            current.push(filename);
+        }
+        if !current.is_empty() {
+            split.push(current);
+        }
+    }
+    split
+}
+
+/// Settings shared by all of the linters in a run.
+pub struct RunContext<'a> {
+    /// Root of the repo. Local linter paths are relative to this.
+    pub top_level: &'a Path,
+    /// The directory linters are given access to, and that file paths
+    /// are relative to. Normally this is `top_level`.
+    pub work_dir: &'a Path,
+    /// Where downloaded linters are stored.
+    pub cache_dir: &'a Path,
+    /// Environment variables visible to linters.
+    pub env: &'a [(String, String)],
+    /// Engine used to compile and run linters.
+    pub engine: &'a Engine,
+    /// Cached linter metadata.
+    pub manifest: &'a Mutex<Manifest>,
+    /// If set, only diagnostics on these lines are reported.
+    pub changed_lines: Option<&'a ChangedLines>,
+    /// Where linter output is written.
+    pub output: &'a Output,
+    /// Give each run of a linter a temporary directory containing only the
+    /// files it is linting, instead of the whole of `work_dir`.
+    pub sandbox_strict: bool,
+    /// If set, linters that passed before on the same files are skipped.
+    pub results_cache: Option<&'a ResultsCache>,
+    /// If set, the peak memory used by the linter's runs is recorded here.
+    pub peak_memory: Option<&'a PeakMemory>,
+    /// Known state of the files, so they only need to be read again before
+    /// a linter runs if their stat data changed.
+    pub file_hashes: Option<&'a Recorded>,
+    /// Where linters' Git requests are made. `work_dir` may be a sandbox or
+    /// a temporary copy, so it can't be used.
+    pub git_requests: GitRequestsTarget<'a>,
+}
+
+/// Where the changes that linters ask for with `git_requests` are made.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum GitRequestsTarget<'a> {
+    /// Fixes are discarded (e.g. `work_dir` is a temporary copy), so the
+    /// changes are only reported.
+    #[default]
+    Report,
+    /// Files in this directory, which isn't in a Git repo.
+    Directory(&'a Path),
+    /// The index and working tree of the repo at this path.
+    Repo(&'a Path),
+}
+
+/// Get the files that `linter` will be run on. The linter must already
+/// have been fetched.
+pub fn linter_matching_files<'a>(
+    context: &RunContext<'_>,
+    files: &'a [FileInfo],
+    linter: &ConfigLinter,
+) -> Result<Vec<&'a FileInfo>> {
+    let linter_path = get_linter_path(context.top_level, context.cache_dir, linter);
+    let metadata = context.manifest.lock().unwrap().metadata(&linter_path)?;
+    Ok(select_files(files, &metadata, linter).0)
+}
+
+/// When `linter` is run, from its metadata. It must have been fetched.
+pub fn linter_stage(
+    top_level: &Path,
+    cache_dir: &Path,
+    manifest: &Mutex<Manifest>,
+    linter: &ConfigLinter,
+) -> Result<Stage> {
+    let linter_path = get_linter_path(top_level, cache_dir, linter);
+    Ok(manifest.lock().unwrap().metadata(&linter_path)?.stage)
+}
+
+/// The result of running a linter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinterStatus {
+    /// All executions returned EXIT_SUCCESS.
+    Passed,
+    /// It wasn't run because it passed last time with the same inputs.
+    CachedPass,
+    Failed,
+}
+
+impl LinterStatus {
+    pub fn passed(self) -> bool {
+        self != Self::Failed
+    }
+}
+
+/// Run a single linter and return whether all executions returned EXIT_SUCCESS.
+/// This does not check git diff.
+pub async fn run_single_linter(
+    context: &RunContext<'_>,
+    files: &[FileInfo],
+    linter: &ConfigLinter,
+) -> Result<LinterStatus> {
+    let linter_path = get_linter_path(context.top_level, context.cache_dir, linter);
+    let mut metadata = {
+        let _span = profile::span(format!("{}: read metadata", linter.name));
+        context.manifest.lock().unwrap().metadata(&linter_path)?
+    };
+    apply_overrides(&mut metadata, linter);
+
+    log::info!("Running linter: {} ({})", linter.name, metadata.repo);
+    check_repo(&metadata, linter)?;
+
+    if log_enabled!(Level::Debug) {
+        log_mismatches(files, &metadata, linter);
+    }
+
+    let (files, unsupported) = select_files(files, &metadata, linter);
+    for file in unsupported {
+        warn!(
+            "Skipping {} for linter {} because it doesn't support {:?} encoding",
+            file.path.display(),
+            linter.name,
+            file.encoding
+        );
+    }
+
+    let files = retain_inside_root(context.work_dir, files, &linter.name);
+
+    let full_args = linter_args(&metadata, linter)?;
+
+    // Symlinks are given to linters as files containing their target, which
+    // is only possible in a sandbox without changing the work dir.
+    let sandboxed_context;
+    let context =
+        if !context.sandbox_strict && files.iter().any(|file| file.ty == FileType::Symlink) {
+            info!(
+                "Running linter {} in a sandbox for its symlinks",
+                linter.name
+            );
+            sandboxed_context = RunContext {
+                sandbox_strict: true,
+                ..*context
+            };
+            &sandboxed_context
+        } else {
+            context
+        };
+
+    // Record the files before the linter runs, so edits made by something
+    // else (e.g. the user) can be detected.
+    let recorded = record_files(context.work_dir, &files, context.file_hashes)?;
+
+    // The result depends on which lines changed too, so don't cache it.
+    let results_cache = context
+        .results_cache
+        .filter(|_| context.changed_lines.is_none());
+    let run_key = results_cache
+        .map(|cache| {
+            let binary_hash = context
+                .manifest
+                .lock()
+                .unwrap()
+                .content_hash(&linter_path)?;
+            anyhow::Ok(cache.key(
+                &binary_hash,
+                &metadata,
+                &full_args,
+                context.env,
+                &files,
+                &recorded,
+            ))
+        })
+        .transpose()?;
+    if let (Some(cache), Some(run_key)) = (results_cache, &run_key)
+        && cache.passed(run_key)
+    {
+        info!(
+            "Skipping linter {} because it passed last time and nothing has changed",
+            linter.name
+        );
+        return Ok(LinterStatus::CachedPass);
+    }
+
     info!("Loading component");
 
-    let engine =
-        Engine::new(wasmtime::Config::new().async_support(true)).context("creating WASM engine")?;
+    let component = {
+        let _span = profile::span(format!("{}: load and compile", linter.name));
+        wasi_cache::load_component_cached(context.engine, &linter_path, context.cache_dir).await?
+    };
+    wasi_cache::check_is_command(context.engine, &component, &linter_path)?;
+
+    // Check-only linters shouldn't modify files, so keep copies to restore
+    // if they do. Sandboxed linters' changes are never copied back instead.
+    let snapshot = (metadata.check_only && !context.sandbox_strict)
+        .then(|| {
+            let paths: Vec<&Path> = files.iter().map(|f| f.path.as_path()).collect();
+            Sandbox::new(context.work_dir, &paths, &recorded)
+        })
+        .transpose()?;
 
-    let component = wasi_cache::load_component_cached(&engine, &linter_path).await?;
+    let started = SystemTime::now();
 
-    if metadata.max_filenames == 0 {
-        run_linter_command(top_level, &full_args, &engine, &component).await
+    let _span = profile::span(format!("{}: run", linter.name));
+
+    let result = if metadata.max_filenames == 0 {
+        let paths: Vec<&Path> = files.iter().map(|f| f.path.as_path()).collect();
+        run_linter_command_sandboxed(
+            context,
+            &paths,
+            &recorded,
+            &component,
+            &full_args,
+            Vec::new(),
+            &metadata,
+            &linter.name,
+        )
+        .await
     } else {
-        let all_filenames = files
-            .iter()
-            .map(|f| {
-                f.path
-                    .to_str()
-                    .ok_or_else(|| anyhow!("Couldn't convert path to UTF-8: {:?}", f.path))
-            })
-            .collect::<Result<Vec<_>>>()?;
+        let chunks = split_long_chunks(
+            &full_args,
+            chunk_filenames(
+                &linter_paths(&files, metadata.granularity),
+                metadata.max_filenames as usize,
+                metadata.path_style,
+            )?,
+            metadata.filenames_on_stdin,
+            MAX_ARGV_BYTES,
+        );
+        // If this says 0 files when there are matching files, the linter's
+        // match expression is probably wrong.
+        info!(
+            "Passing {} files to {} in {} runs",
+            files.len(),
+            linter.name,
+            chunks.len()
+        );
         // Iterator of tasks to run.
-        let tasks = all_filenames
-            .chunks(metadata.max_filenames as usize)
-            .map(|chunk| {
-                let mut full_args = full_args.clone();
-                full_args.extend_from_slice(&chunk);
+        let tasks = chunks.iter().map(|chunk| {
+            let mut full_args = full_args.clone();
+            let paths = chunk_files(&files, chunk, metadata.granularity);
+            let chunk: Vec<&str> = chunk.iter().map(String::as_str).collect();
+            let stdin = match filename_input(
+                &full_args,
+                &chunk,
+                metadata.filenames_on_stdin,
+                MAX_ARGV_BYTES,
+            ) {
+                FilenameInput::Args(filenames) => {
+                    full_args.extend_from_slice(&filenames);
+                    Vec::new()
+                }
+                FilenameInput::Stdin(stdin) => stdin,
+            };
 
-                // We want to move full_args in and Rust doesn't have syntax to
-                // only move some variables, so we convert these to references
-                // and move the references in (so we don't move the actual engine/component).
-                let component = &component;
-                let engine = &engine;
-                async move { run_linter_command(top_level, &full_args, engine, component).await }
-            });
+            // We want to move full_args in and Rust doesn't have syntax to
+            // only move some variables, so we convert these to references
+            // and move the references in (so we don't move the actual engine/component).
+            let component = &component;
+            let metadata = &metadata;
+            let recorded = &recorded;
+            async move {
+                run_linter_command_sandboxed(
+                    context,
+                    &paths,
+                    recorded,
+                    component,
+                    &full_args,
+                    stdin,
+                    metadata,
+                    &linter.name,
+                )
+                .await
+            }
+        });
 
         // TODO (2.0): Add an option to explicitly set the parallelism, since
         // this doesn't always work perfectly (see the docs for available_parallelism()).
@@ -170,24 +820,403 @@ pub async fn run_single_linter(
             .collect()
             .await;
 
-        for result in results.into_iter() {
-            if !result? {
-                return Ok(false);
+        results
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map(|results| results.into_iter().all(|success| success))
+    };
+
+    // Sandboxed linters' files are checked when they are copied back. Others
+    // write to the work dir directly, so only edits made before the linter
+    // started (e.g. while it was compiled) can be told apart from its own.
+    let mut modified = false;
+    let mut check_only_modified = Vec::new();
+    for (path, before) in &recorded {
+        let after = rehash_file(&context.work_dir.join(path), before)?;
+        if after == *before {
+            continue;
+        }
+        modified = true;
+        if !context.sandbox_strict && after.modified().is_some_and(|modified| modified < started) {
+            warn!(
+                "{} was modified while linter {} was starting, so its changes may be attributed to the linter",
+                path.display(),
+                linter.name
+            );
+        } else if snapshot.is_some() {
+            check_only_modified.push(path.clone());
+        }
+    }
+    let result = match &snapshot {
+        Some(snapshot) if !check_only_modified.is_empty() => {
+            snapshot.restore(context.work_dir, &check_only_modified)?;
+            report_check_only_changes(context, &linter.name, &check_only_modified)?;
+            result.map(|_| false)
+        }
+        _ => result,
+    };
+
+    // A run that fixed files fails, so only unchanged files can be recorded.
+    if let (Some(cache), Some(run_key), Ok(true), false) =
+        (results_cache, &run_key, &result, modified)
+    {
+        // It's only a cache, so don't fail the run.
+        if let Err(e) = cache.record_pass(run_key) {
+            warn!("Failed to record that linter {} passed: {e:#}", linter.name);
+        }
+    }
+    result.map(|passed| {
+        if passed {
+            LinterStatus::Passed
+        } else {
+            LinterStatus::Failed
+        }
+    })
+}
+
+/// The state of a linter's files before it ran.
+pub type Recorded = BTreeMap<PathBuf, FileHash>;
+
+/// Record the state of `files`. Those in `known` are only read again if
+/// their stat data changed.
+fn record_files(
+    work_dir: &Path,
+    files: &[&FileInfo],
+    known: Option<&Recorded>,
+) -> Result<Recorded> {
+    let _span = profile::span("record files");
+    files
+        .iter()
+        .map(|file| {
+            let full_path = work_dir.join(&file.path);
+            let hash = match known.and_then(|known| known.get(&file.path)) {
+                Some(before) => rehash_file(&full_path, before)?,
+                None => hash_file(&full_path)?,
+            };
+            Ok((file.path.clone(), hash))
+        })
+        .collect()
+}
+
+/// Run `linter` like `run_single_linter()`, but if it errors (rather than
+/// failing) in a way that might not happen again, re-run it up to
+/// `linter.retries` times.
+pub async fn run_single_linter_with_retries(
+    context: &RunContext<'_>,
+    files: &[FileInfo],
+    linter: &ConfigLinter,
+) -> Result<LinterStatus> {
+    let mut attempt = 0;
+    loop {
+        match run_single_linter(context, files, linter).await {
+            Err(e) if attempt < linter.retries && is_transient(&e) => {
+                attempt += 1;
+                warn!(
+                    "Linter {} errored: {e:#}. Retrying ({attempt} of {})",
+                    linter.name, linter.retries
+                );
             }
+            result => return result,
         }
-        Ok(true)
     }
 }
 
-async fn run_linter_command(
-    top_level: &Path,
+/// Whether an error might not happen again if the linter is re-run: a trap
+/// (which is how running out of memory usually shows up in WASM), or an IO
+/// error caused by contention for a resource. Errors in the config, missing
+/// files and compile failures would just happen again.
+fn is_transient(error: &anyhow::Error) -> bool {
+    use std::io::ErrorKind;
+    error.chain().any(|cause| {
+        if let Some(trap) = cause.downcast_ref::<Trap>() {
+            // The run was stopped on purpose, e.g. by Ctrl-C.
+            return *trap != Trap::Interrupt;
+        }
+        cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                ErrorKind::Interrupted
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::TimedOut
+                    | ErrorKind::ResourceBusy
+                    | ErrorKind::OutOfMemory
+            )
+        })
+    })
+}
+
+/// Remove files that are outside `root` once symlinks in their parent
+/// directories are resolved (e.g. `link/foo.rs` where `link` points to `/`).
+/// Nit reads and writes them on the linter's behalf, so this would let it
+/// escape the sandbox.
+pub fn retain_inside_root<'a>(
+    root: &Path,
+    mut files: Vec<&'a FileInfo>,
+    linter_name: &str,
+) -> Vec<&'a FileInfo> {
+    let Ok(root) = root.canonicalize() else {
+        return files;
+    };
+    files.retain(|file| {
+        let parent = root.join(&file.path);
+        let inside = parent
+            .parent()
+            .and_then(|parent| parent.canonicalize().ok())
+            .is_some_and(|parent| parent.starts_with(&root));
+        if !inside {
+            warn!(
+                "Skipping {} for linter {linter_name} because it is outside the repo",
+                file.path.display()
+            );
+        }
+        inside
+    });
+    files
+}
+
+/// Get the files that `chunk` (from `chunk_filenames()`) refers to.
+fn chunk_files<'a>(
+    files: &[&'a FileInfo],
+    chunk: &[String],
+    granularity: Granularity,
+) -> Vec<&'a Path> {
+    // Undo `PathStyle::Absolute`.
+    let chunk: BTreeSet<&Path> = chunk
+        .iter()
+        .map(|path| match path.strip_prefix('/') {
+            Some("") => Path::new("."),
+            Some(path) => Path::new(path),
+            None => Path::new(path),
+        })
+        .collect();
+    files
+        .iter()
+        .map(|file| file.path.as_path())
+        .filter(|path| match granularity {
+            Granularity::File => chunk.contains(path),
+            Granularity::Directory => match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => chunk.contains(parent),
+                _ => chunk.contains(Path::new(".")),
+            },
+        })
+        .collect()
+}
+
+/// Like `run_linter_command()`, but with `sandbox_strict` the linter is only
+/// given access to a copy of `paths`. Changes to them are copied back
+/// afterwards, unless the file was edited outside the sandbox since it was
+/// `recorded`.
+#[allow(clippy::too_many_arguments)]
+async fn run_linter_command_sandboxed(
+    context: &RunContext<'_>,
+    paths: &[&Path],
+    recorded: &Recorded,
+    component: &Component,
     args: &[&str],
-    engine: &Engine,
+    stdin: Vec<u8>,
+    metadata: &NitMetadata,
+    linter_name: &str,
+) -> Result<bool> {
+    if !context.sandbox_strict {
+        return run_linter_command(context, component, args, stdin, metadata, linter_name).await;
+    }
+    // Copying the files blocks, so it's done on a thread where that's allowed.
+    let work_dir = context.work_dir.to_owned();
+    let paths: Vec<PathBuf> = paths.iter().map(|path| path.to_path_buf()).collect();
+    let recorded: Recorded = paths
+        .iter()
+        .filter_map(|path| Some((path.clone(), recorded.get(path)?.clone())))
+        .collect();
+    let sandbox = tokio::task::spawn_blocking(move || {
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        Sandbox::new(&work_dir, &paths, &recorded)
+    })
+    .await??;
+    let sandbox_context = RunContext {
+        work_dir: &sandbox.dir,
+        ..*context
+    };
+    let result = run_linter_command(
+        &sandbox_context,
+        component,
+        args,
+        stdin,
+        metadata,
+        linter_name,
+    )
+    .await;
+    // Check-only linters' changes are discarded rather than copied back.
+    if metadata.check_only {
+        let modified = tokio::task::spawn_blocking(move || sandbox.modified()).await??;
+        if modified.is_empty() {
+            return result;
+        }
+        report_check_only_changes(context, linter_name, &modified)?;
+        return result.map(|_| false);
+    }
+    let work_dir = context.work_dir.to_owned();
+    // The sandbox is removed on the blocking thread too.
+    let copied = tokio::task::spawn_blocking(move || sandbox.copy_back(&work_dir)).await??;
+    debug!(
+        "Copied {} modified files back from the sandbox",
+        copied.modified.len() - copied.edited_externally.len()
+    );
+    for path in &copied.edited_externally {
+        warn!(
+            "{} was modified while linter {linter_name} was running, so the linter's changes to it were discarded",
+            path.display()
+        );
+    }
+    result
+}
+
+/// Tell the user that a check-only linter modified `paths`, which it
+/// promised not to do. The linter fails.
+fn report_check_only_changes(
+    context: &RunContext<'_>,
+    linter_name: &str,
+    paths: &[PathBuf],
+) -> Result<()> {
+    context.output.line(format_args!(
+        "Linter '{linter_name}' is check-only but tried to modify these files, so the changes were discarded:"
+    ))?;
+    for path in paths {
+        context.output.line(format_args!("  {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Temporary directory containing copies of some of the files in the work
+/// dir, so a linter can't see or modify any others. It's also used as a
+/// snapshot to restore check-only linters' files from. It's removed when
+/// this is dropped.
+struct Sandbox {
+    dir: PathBuf,
+    /// Paths of the files and their state in the work dir before the linter
+    /// ran.
+    paths: Vec<(PathBuf, FileHash)>,
+}
+
+/// Result of `Sandbox::copy_back()`.
+struct CopiedBack {
+    /// Files that were modified (or deleted) in the sandbox.
+    modified: Vec<PathBuf>,
+    /// Files that were modified in the sandbox and the work dir, so they
+    /// weren't copied back.
+    edited_externally: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// Copy `paths` (relative to `work_dir`) into a new temporary directory.
+    /// Their state in `recorded` is used to detect changes. Symlinks are
+    /// replaced by regular files containing their target, like Git does when
+    /// `core.symlinks` is false, so linters get the link itself and can't
+    /// follow it out of the sandbox. Changes to them are discarded.
+    fn new(work_dir: &Path, paths: &[&Path], recorded: &Recorded) -> Result<Self> {
+        let mut sandbox = Self {
+            dir: env::temp_dir().join(unique_filename("nit-sandbox-", "")),
+            paths: Vec::new(),
+        };
+        std::fs::create_dir_all(&sandbox.dir)?;
+        for path in paths {
+            let copy = sandbox.dir.join(path);
+            if let Some(parent) = copy.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let hash = recorded
+                .get(*path)
+                .with_context(|| anyhow!("{} wasn't recorded", path.display()))?;
+            let original = work_dir.join(path);
+            if let Ok(link_target) = std::fs::read_link(&original) {
+                std::fs::write(&copy, link_target.as_os_str().as_encoded_bytes()).with_context(
+                    || anyhow!("Failed to write symlink target {}", path.display()),
+                )?;
+                continue;
+            }
+            std::fs::copy(&original, &copy)
+                .with_context(|| anyhow!("Failed to copy {} to the sandbox", path.display()))?;
+            sandbox.paths.push((path.to_path_buf(), hash.clone()));
+        }
+        Ok(sandbox)
+    }
+
+    /// Copy files that were modified (or deleted) in the sandbox back to
+    /// `work_dir`. New files are ignored. Files that were also modified in
+    /// `work_dir` since they were recorded (e.g. by the user) aren't
+    /// overwritten.
+    fn copy_back(&self, work_dir: &Path) -> Result<CopiedBack> {
+        let mut copied = CopiedBack {
+            modified: Vec::new(),
+            edited_externally: Vec::new(),
+        };
+        for (path, before) in &self.paths {
+            let fixed = self.dir.join(path);
+            if hash_file(&fixed)? == *before {
+                continue;
+            }
+            copied.modified.push(path.clone());
+            let original = work_dir.join(path);
+            if rehash_file(&original, before)? != *before {
+                copied.edited_externally.push(path.clone());
+                continue;
+            }
+            if fixed.exists() {
+                std::fs::copy(&fixed, &original)
+                    .with_context(|| anyhow!("Failed to write {}", path.display()))?;
+            } else {
+                std::fs::remove_file(&original)
+                    .with_context(|| anyhow!("Failed to remove {}", path.display()))?;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Files that were modified (or deleted) in the sandbox.
+    fn modified(&self) -> Result<Vec<PathBuf>> {
+        let mut modified = Vec::new();
+        for (path, before) in &self.paths {
+            if hash_file(&self.dir.join(path))? != *before {
+                modified.push(path.clone());
+            }
+        }
+        Ok(modified)
+    }
+
+    /// Overwrite `paths` in `work_dir` with the sandbox's copies, e.g. to
+    /// undo a check-only linter's changes when this is used as a snapshot.
+    fn restore(&self, work_dir: &Path, paths: &[PathBuf]) -> Result<()> {
+        for path in paths {
+            std::fs::copy(self.dir.join(path), work_dir.join(path))
+                .with_context(|| anyhow!("Failed to restore {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn run_linter_command(
+    context: &RunContext<'_>,
     component: &Component,
+    args: &[&str],
+    stdin: Vec<u8>,
+    metadata: &NitMetadata,
+    linter_name: &str,
 ) -> Result<bool> {
+    let RunContext {
+        work_dir,
+        env,
+        engine,
+        ..
+    } = context;
+
     debug!("Running linter with args: {:?}", args);
 
-    let mut linker = Linker::new(&engine);
+    let mut linker = Linker::new(engine);
 
     wasmtime_wasi::p2::add_to_linker_async(&mut linker)?;
 
@@ -195,56 +1224,904 @@ async fn run_linter_command(
     let stdout = MemoryOutputPipe::new(10 * 1024 * 1024);
     let stderr = MemoryOutputPipe::new(10 * 1024 * 1024);
 
-    let wasi = WasiCtxBuilder::new()
-        .allow_tcp(false)
+    let mut wasi = WasiCtxBuilder::new();
+    wasi.allow_tcp(false)
         .allow_udp(false)
         .allow_ip_name_lookup(false)
         .preopened_dir(
-            top_level,
-            // TODO (2.0): Use `top_level` so reported paths are correct.
+            work_dir, // TODO (2.0): Use `top_level` so reported paths are correct.
             ".",
             DirPerms::all(),
             FilePerms::all(),
-        )?
-        .stdout(stdout)
-        .stderr(stderr)
+        )?;
+    if metadata.path_style == PathStyle::Absolute {
+        wasi.preopened_dir(work_dir, "/", DirPerms::all(), FilePerms::all())?;
+    }
+    let diagnostics_dir = if metadata.structured_diagnostics {
+        wasi.env("NIT_STRUCTURED", "1");
+        Some(LinterOutputDir::preopen(
+            &mut wasi,
+            DIAGNOSTICS_DIR,
+            DIAGNOSTICS_FILENAME,
+            "NIT_DIAGNOSTICS_FILE",
+        )?)
+    } else {
+        None
+    };
+    let git_requests_dir = (metadata.git_requests && !metadata.check_only)
+        .then(|| {
+            LinterOutputDir::preopen(
+                &mut wasi,
+                GIT_REQUESTS_DIR,
+                GIT_REQUESTS_FILENAME,
+                "NIT_GIT_REQUESTS_FILE",
+            )
+        })
+        .transpose()?;
+    let wasi = wasi
+        .stdin(MemoryInputPipe::new(stdin))
+        .stdout(stdout.clone())
+        .stderr(stderr.clone())
         .args(args)
+        .envs(env)
         // TODO (1.0): Set cwd: https://github.com/bytecodealliance/wasmtime/pull/9831
         .build();
 
     let state = ComponentRunStates {
         wasi_ctx: wasi,
         resource_table: ResourceTable::new(),
+        memory: MemoryTracker::default(),
     };
 
-    let mut store = Store::new(&engine, state);
+    let mut store = Store::new(engine, state);
+    store.limiter(|state| &mut state.memory);
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_async_yield_and_update(1);
 
     info!("Instantiating");
-    let command = Command::instantiate_async(&mut store, &component, &linker).await?;
+    let command = Command::instantiate_async(&mut store, component, &linker).await?;
 
     info!("Starting call");
 
     let run_result = command.wasi_cli_run().call_run(&mut store).await;
+    if let Some(peak_memory) = context.peak_memory {
+        peak_memory.record(store.data().memory.peak as u64);
+    }
 
-    // The return type here is very weird. See
-    // https://github.com/bytecodealliance/wasmtime/issues/10767
-    match run_result {
-        Ok(res) => res.map_err(|_| anyhow!("Unknown error running linter"))?,
-        Err(error) => {
-            if let Some(exit) = error.downcast_ref::<I32Exit>() {
-                // Err(I32Exit(0)) is actually success.
-                if exit.0 != 0 {
-                    info!("Call failed with exit code {:?}", exit.0);
-                    return Ok(false);
-                }
-            } else {
-                return Err(error);
-            }
+    let mut outputs = vec![stdout.contents().to_vec(), stderr.contents().to_vec()];
+    if let Some(diagnostics_dir) = &diagnostics_dir {
+        let data = diagnostics_dir
+            .read()
+            .context("Failed to read structured diagnostics")?;
+        outputs.push(
+            parse_diagnostics(&data, linter_name)
+                .iter()
+                .map(|diagnostic| format!("{diagnostic}\n"))
+                .collect::<String>()
+                .into_bytes(),
+        );
+    }
+
+    // If we only care about changed lines, remove other diagnostics. If at
+    // least one was found and they were all removed we ignore the linter
+    // failing, since it can only be blamed on unchanged lines.
+    let mut only_unchanged_lines = false;
+    if let Some(changed_lines) = context.changed_lines {
+        let (mut kept, mut removed) = (0, 0);
+        for output in outputs.iter_mut() {
+            let filtered = filter_diagnostics(
+                output,
+                changed_lines,
+                metadata.path_style == PathStyle::Absolute,
+                |path| work_dir.join(path).is_file(),
+            );
+            kept += filtered.kept;
+            removed += filtered.removed;
+            *output = filtered.output;
         }
-    };
+        if removed > 0 {
+            info!("Ignored {removed} diagnostics on unchanged lines");
+        }
+        only_unchanged_lines = kept == 0 && removed > 0;
+    }
+
+    // Show the linter's output. Its stdout is not forwarded to our stdout
+    // because that may be used for other things (e.g. `check-stdin`).
+    for output in &outputs {
+        context.output.write(output)?;
+    }
+
+    let exit_code = exit_code(run_result)
+        .with_context(|| anyhow!("Linter '{linter_name}' didn't exit normally"))?;
+
+    // Like fixing a file, making a change the linter asked for fails it.
+    if let Some(git_requests_dir) = &git_requests_dir {
+        let data = git_requests_dir
+            .read()
+            .context("Failed to read Git requests")?;
+        if apply_git_requests(context, &data, linter_name)? {
+            return Ok(false);
+        }
+    }
+
+    if exit_code != 0 {
+        info!("Call failed with exit code {exit_code}");
+        if !only_unchanged_lines {
+            return Ok(false);
+        }
+    }
 
     info!("Call finished");
 
     // TODO (2.0): Use WASI to check if files were modified.
     Ok(true)
 }
+
+/// Get the exit code from the result of calling a command's `run` function.
+/// The return type here is very weird; a command can exit in several ways. See
+/// https://github.com/bytecodealliance/wasmtime/issues/10767
+///
+/// * Returning from `main` gives `Ok(Ok(()))`.
+/// * Returning an error from `run` (e.g. `exit(Err)`) gives `Ok(Err(()))`,
+///   which is exit code 1.
+/// * `proc_exit(n)` (including `proc_exit(0)`) gives `Err(I32Exit(n))`.
+///
+/// Any other error (e.g. a trap) is returned as an error.
+fn exit_code(run_result: wasmtime::Result<Result<(), ()>>) -> Result<i32> {
+    match run_result {
+        Ok(Ok(())) => Ok(0),
+        Ok(Err(())) => Ok(1),
+        Err(error) => match error.downcast_ref::<I32Exit>() {
+            Some(exit) => Ok(exit.0),
+            None => Err(error),
+        },
+    }
+}
+
+/// Make the changes that a linter with `git_requests` asked for. Returns
+/// whether there were any.
+fn apply_git_requests(context: &RunContext<'_>, data: &[u8], linter_name: &str) -> Result<bool> {
+    let mut executable = Vec::new();
+    for request in parse_git_requests(data, linter_name) {
+        // The linter could only see files in the work dir (which may be a
+        // sandbox), so it can't ask for anything else.
+        let Some(path) = request.path().filter(|path| {
+            context
+                .work_dir
+                .join(path)
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.is_file())
+        }) else {
+            warn!(
+                "Ignoring Git request from linter {linter_name} for a file it can't see: {request:?}"
+            );
+            continue;
+        };
+        match request {
+            GitRequest::SetExecutable(_) => executable.push(path.to_owned()),
+        }
+    }
+    if executable.is_empty() {
+        return Ok(false);
+    }
+
+    let dir = match context.git_requests {
+        GitRequestsTarget::Report => None,
+        GitRequestsTarget::Directory(dir) | GitRequestsTarget::Repo(dir) => Some(dir),
+    };
+    for path in &executable {
+        let message = if dir.is_some() {
+            "marked executable"
+        } else {
+            "would mark executable"
+        };
+        context
+            .output
+            .write(format!("{}: {message}\n", path.display()).as_bytes())?;
+        #[cfg(unix)]
+        if let Some(dir) = dir {
+            make_executable(&dir.join(path))?;
+        }
+    }
+    if let GitRequestsTarget::Repo(top_level) = context.git_requests {
+        git_set_executable_bit(top_level, &executable)?;
+    }
+    Ok(true)
+}
+
+/// Make a file executable by everyone who can read it, like `chmod +x`. The
+/// file may not exist in the working tree if the linter was run on the index.
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+    let mut permissions = match path.metadata() {
+        Ok(metadata) => metadata.permissions(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| anyhow!("Failed to read metadata of {}", path.display()));
+        }
+    };
+    let mode = permissions.mode();
+    permissions.set_mode(mode | (mode & 0o444) >> 2);
+    std::fs::set_permissions(path, permissions)
+        .with_context(|| anyhow!("Failed to make {} executable", path.display()))
+}
+
+/// Temporary directory containing a file that a linter can write to, e.g.
+/// its structured diagnostics. It's removed when this is dropped.
+struct LinterOutputDir {
+    dir: PathBuf,
+    filename: &'static str,
+}
+
+impl LinterOutputDir {
+    /// Create the directory and give the linter access to it at `guest_dir`.
+    /// The full path of `filename` is passed to it in `env_var`.
+    fn preopen(
+        wasi: &mut WasiCtxBuilder,
+        guest_dir: &str,
+        filename: &'static str,
+        env_var: &str,
+    ) -> Result<Self> {
+        let dir = env::temp_dir().join(unique_filename("nit-linter-output-", ""));
+        std::fs::create_dir_all(&dir)?;
+        let dir = Self { dir, filename };
+        wasi.preopened_dir(&dir.dir, guest_dir, DirPerms::all(), FilePerms::all())?
+            .env(env_var, format!("{guest_dir}/{filename}"));
+        Ok(dir)
+    }
+
+    /// Read the file. It's fine for the linter not to write it.
+    fn read(&self) -> std::io::Result<Vec<u8>> {
+        match std::fs::read(self.dir.join(self.filename)) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            result => result,
+        }
+    }
+}
+
+impl Drop for LinterOutputDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            debug!("Failed to remove {}: {e}", self.dir.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        file_matching::MatchExpression,
+        git::{Encoding, FileType, text_file as file},
+        metadata::parse_metadata,
+    };
+
+    #[test]
+    fn test_chunks_are_deterministic() {
+        let metadata = NitMetadata {
+            argv0: "test".to_owned(),
+            max_filenames: 2,
+            require_serial: false,
+            filenames_on_stdin: false,
+            path_style: PathStyle::Relative,
+            granularity: Granularity::File,
+            structured_diagnostics: false,
+            check_only: false,
+            git_requests: false,
+            stage: Stage::Files,
+            args: Vec::new(),
+            default_match: MatchExpression::Bool(true),
+            text_only: true,
+            encodings: None,
+            self_test: None,
+            repo: String::new(),
+        };
+        let linter = ConfigLinter {
+            name: "test".to_owned(),
+            location: LinterLocation::Local("test.wasm".to_owned()),
+            override_match: None,
+            override_args: None,
+            override_argv0: None,
+            override_max_filenames: None,
+            override_require_serial: None,
+            branches: None,
+            allow_failure: false,
+            depends_on: Vec::new(),
+            retries: 0,
+            expected_repo: None,
+            tags: Vec::new(),
+        };
+
+        let files_a = vec![
+            file("c.rs"),
+            file("a.rs"),
+            file("e.rs"),
+            file("b.rs"),
+            file("d.rs"),
+        ];
+        let files_b = vec![
+            file("e.rs"),
+            file("d.rs"),
+            file("c.rs"),
+            file("b.rs"),
+            file("a.rs"),
+        ];
+
+        let chunks_a = chunk_filenames(
+            &linter_paths(
+                &select_files(&files_a, &metadata, &linter).0,
+                Granularity::File,
+            ),
+            2,
+            PathStyle::Relative,
+        )
+        .unwrap();
+        let chunks_b = chunk_filenames(
+            &linter_paths(
+                &select_files(&files_b, &metadata, &linter).0,
+                Granularity::File,
+            ),
+            2,
+            PathStyle::Relative,
+        )
+        .unwrap();
+
+        assert_eq!(chunks_a, chunks_b);
+        assert_eq!(
+            chunks_a,
+            vec![vec!["a.rs", "b.rs"], vec!["c.rs", "d.rs"], vec!["e.rs"]]
+        );
+    }
+
+    #[test]
+    fn test_explicit_non_matching_file_is_skipped() {
+        let metadata = NitMetadata {
+            argv0: "test".to_owned(),
+            max_filenames: 10,
+            require_serial: false,
+            filenames_on_stdin: false,
+            path_style: PathStyle::Relative,
+            granularity: Granularity::File,
+            structured_diagnostics: false,
+            check_only: false,
+            git_requests: false,
+            stage: Stage::Files,
+            args: Vec::new(),
+            default_match: MatchExpression::Bool(true),
+            text_only: true,
+            encodings: None,
+            self_test: None,
+            repo: String::new(),
+        };
+        let linter = ConfigLinter {
+            name: "test".to_owned(),
+            location: LinterLocation::Local("test.wasm".to_owned()),
+            override_match: Some(MatchExpression::Glob(glob::Pattern::new("*.rs").unwrap())),
+            override_args: None,
+            override_argv0: None,
+            override_max_filenames: None,
+            override_require_serial: None,
+            branches: None,
+            allow_failure: false,
+            depends_on: Vec::new(),
+            retries: 0,
+            expected_repo: None,
+            tags: Vec::new(),
+        };
+
+        // As if from `--files a.rs b.png`.
+        let files = vec![
+            file("a.rs"),
+            FileInfo {
+                ty: FileType::Binary,
+                ..file("b.png")
+            },
+        ];
+
+        let (selected, _) = select_files(&files, &metadata, &linter);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].path, Path::new("a.rs"));
+    }
+
+    #[test]
+    fn test_binary_files_are_skipped() {
+        let metadata = |text_only: bool| {
+            parse_metadata(
+                format!(
+                    r#"{{
+                        "argv0": "lint",
+                        "max_filenames": 10,
+                        "require_serial": false,
+                        "args": [],
+                        "default_match": {{ "bool": true }},
+                        "text_only": {text_only},
+                        "repo": ""
+                    }}"#
+                )
+                .as_bytes(),
+                Path::new("lint.wasm"),
+            )
+            .unwrap()
+        };
+        let linter = ConfigLinter::from_spec("lint.wasm", Path::new("/repo")).unwrap();
+        let mut files = vec![file("a.txt"), file("b.png"), file("c.exe")];
+        files[1].ty = FileType::Binary;
+        files[2].ty = FileType::ExecutableBinary;
+
+        let (selected, _) = select_files(&files, &metadata(true), &linter);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].path, Path::new("a.txt"));
+        let (selected, _) = select_files(&files, &metadata(false), &linter);
+        assert_eq!(selected.len(), 3);
+    }
+
+    #[test]
+    fn test_unsupported_encoding_is_skipped() {
+        let metadata = NitMetadata {
+            argv0: "test".to_owned(),
+            max_filenames: 10,
+            require_serial: false,
+            filenames_on_stdin: false,
+            path_style: PathStyle::Relative,
+            granularity: Granularity::File,
+            structured_diagnostics: false,
+            check_only: false,
+            git_requests: false,
+            stage: Stage::Files,
+            args: Vec::new(),
+            default_match: MatchExpression::Bool(true),
+            text_only: true,
+            encodings: Some(vec![Encoding::Utf8]),
+            self_test: None,
+            repo: String::new(),
+        };
+        let linter = ConfigLinter {
+            name: "test".to_owned(),
+            location: LinterLocation::Local("test.wasm".to_owned()),
+            override_match: None,
+            override_args: None,
+            override_argv0: None,
+            override_max_filenames: None,
+            override_require_serial: None,
+            branches: None,
+            allow_failure: false,
+            depends_on: Vec::new(),
+            retries: 0,
+            expected_repo: None,
+            tags: Vec::new(),
+        };
+
+        let files = vec![
+            file("utf8.txt"),
+            FileInfo {
+                encoding: Encoding::Other,
+                ..file("latin1.txt")
+            },
+        ];
+
+        let (selected, skipped) = select_files(&files, &metadata, &linter);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].path, Path::new("utf8.txt"));
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].path, Path::new("latin1.txt"));
+    }
+
+    #[test]
+    fn test_matches_no_files() {
+        let files = [file("a.rs"), file("b.rs")];
+        let hash = "0".repeat(64);
+        let mut linter = ConfigLinter::from_spec(
+            &format!("https://example.com/lint.wasm#{hash}"),
+            Path::new("/repo"),
+        )
+        .unwrap();
+        // The metadata's match expression isn't known.
+        let manifest = Manifest::default();
+        assert!(!matches_no_files(&files, &linter, &manifest));
+
+        linter.override_match = Some(serde_json5::from_str(r#"{ glob: "*.py" }"#).unwrap());
+        assert!(matches_no_files(&files, &linter, &manifest));
+        linter.override_match = Some(serde_json5::from_str(r#"{ glob: "a.rs" }"#).unwrap());
+        assert!(!matches_no_files(&files, &linter, &manifest));
+
+        // The metadata from an earlier run is used.
+        let section = |glob: &str| {
+            serde_json::json!({
+                "argv0": "lint",
+                "max_filenames": 10,
+                "require_serial": false,
+                "args": [],
+                "default_match": { "glob": glob },
+                "repo": "",
+            })
+            .to_string()
+        };
+        let manifest = |glob: &str| -> Manifest {
+            serde_json::from_value(serde_json::json!({
+                "entries": {},
+                "metadata_by_hash": { &hash: section(glob) },
+            }))
+            .unwrap()
+        };
+        linter.override_match = None;
+        assert!(matches_no_files(&files, &linter, &manifest("*.py")));
+        assert!(!matches_no_files(&files, &linter, &manifest("*.rs")));
+    }
+
+    #[test]
+    fn test_sandbox_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "b").unwrap();
+        let files = [file("a.txt"), file("b.txt")];
+        let files: Vec<&FileInfo> = files.iter().collect();
+        let paths: Vec<&Path> = files.iter().map(|file| file.path.as_path()).collect();
+        let recorded = record_files(dir.path(), &files, None).unwrap();
+        let snapshot = Sandbox::new(dir.path(), &paths, &recorded).unwrap();
+
+        std::fs::write(dir.path().join("a.txt"), "fixed").unwrap();
+        std::fs::remove_file(dir.path().join("b.txt")).unwrap();
+        let modified = [PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+        snapshot.restore(dir.path(), &modified).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "a"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("b.txt")).unwrap(),
+            "b"
+        );
+
+        assert!(snapshot.modified().unwrap().is_empty());
+        std::fs::write(snapshot.dir.join("b.txt"), "changed").unwrap();
+        assert_eq!(snapshot.modified().unwrap(), [PathBuf::from("b.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_in_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+        std::os::unix::fs::symlink("a.txt", dir.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("escape")).unwrap();
+
+        let link = FileInfo {
+            ty: FileType::Symlink,
+            ..file("link")
+        };
+        let files = [file("a.txt"), link, file("escape/b.txt")];
+        let files: Vec<&FileInfo> = files.iter().collect();
+        let files = retain_inside_root(dir.path(), files, "test");
+        assert_eq!(files.len(), 2);
+
+        let paths: Vec<&Path> = files.iter().map(|file| file.path.as_path()).collect();
+        let recorded = record_files(dir.path(), &files, None).unwrap();
+        let sandbox = Sandbox::new(dir.path(), &paths, &recorded).unwrap();
+        let link_copy = sandbox.dir.join("link");
+        assert!(!link_copy.is_symlink());
+        assert_eq!(std::fs::read_to_string(&link_copy).unwrap(), "a.txt");
+
+        std::fs::write(&link_copy, "b.txt").unwrap();
+        assert!(sandbox.copy_back(dir.path()).unwrap().modified.is_empty());
+        let link_path = dir.path().join("link");
+        assert_eq!(std::fs::read_link(&link_path).unwrap(), Path::new("a.txt"));
+    }
+
+    #[test]
+    fn test_chunk_files() {
+        let files = [file("a.rs"), file("src/b.rs"), file("src/c.rs")];
+        let files: Vec<&FileInfo> = files.iter().collect();
+        let chunk = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            chunk_files(&files, &chunk(&["src/b.rs", "a.rs"]), Granularity::File),
+            [Path::new("a.rs"), Path::new("src/b.rs")]
+        );
+        assert_eq!(
+            chunk_files(&files, &chunk(&["/src/c.rs"]), Granularity::File),
+            [Path::new("src/c.rs")]
+        );
+        assert_eq!(
+            chunk_files(&files, &chunk(&["src"]), Granularity::Directory),
+            [Path::new("src/b.rs"), Path::new("src/c.rs")]
+        );
+        assert_eq!(
+            chunk_files(&files, &chunk(&["/"]), Granularity::Directory),
+            [Path::new("a.rs")]
+        );
+    }
+
+    fn record(work_dir: &Path, paths: &[&Path]) -> Recorded {
+        let files: Vec<FileInfo> = paths
+            .iter()
+            .map(|path| file(path.to_str().unwrap()))
+            .collect();
+        record_files(work_dir, &files.iter().collect::<Vec<_>>(), None).unwrap()
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        for name in ["a.rs", "src/b.rs", "src/c.rs", "secret.txt"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+
+        let sandbox_dir;
+        {
+            let paths = [
+                Path::new("a.rs"),
+                Path::new("src/b.rs"),
+                Path::new("src/c.rs"),
+            ];
+            let recorded = record(dir.path(), &paths);
+            let sandbox = Sandbox::new(dir.path(), &paths, &recorded).unwrap();
+            sandbox_dir = sandbox.dir.clone();
+            assert!(!sandbox.dir.join("secret.txt").exists());
+            std::fs::write(sandbox.dir.join("src/b.rs"), "fixed").unwrap();
+            std::fs::remove_file(sandbox.dir.join("src/c.rs")).unwrap();
+            std::fs::write(sandbox.dir.join("new.rs"), "new").unwrap();
+            let copied = sandbox.copy_back(dir.path()).unwrap();
+            assert_eq!(
+                copied.modified,
+                [Path::new("src/b.rs"), Path::new("src/c.rs")]
+            );
+            assert!(copied.edited_externally.is_empty());
+        }
+        assert!(!sandbox_dir.exists());
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).ok();
+        assert_eq!(read("a.rs").as_deref(), Some("a.rs"));
+        assert_eq!(read("src/b.rs").as_deref(), Some("fixed"));
+        assert_eq!(read("src/c.rs"), None);
+        assert_eq!(read("new.rs"), None);
+    }
+
+    #[test]
+    fn test_sandbox_external_edit() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.rs", "b.rs"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let paths = [Path::new("a.rs"), Path::new("b.rs")];
+        let sandbox = Sandbox::new(dir.path(), &paths, &record(dir.path(), &paths)).unwrap();
+        std::fs::write(sandbox.dir.join("a.rs"), "fixed").unwrap();
+        std::fs::write(sandbox.dir.join("b.rs"), "fixed").unwrap();
+        // Edited by the user while the linter was running.
+        std::fs::write(dir.path().join("a.rs"), "edited").unwrap();
+        let copied = sandbox.copy_back(dir.path()).unwrap();
+        assert_eq!(copied.edited_externally, [Path::new("a.rs")]);
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("a.rs"), "edited");
+        assert_eq!(read("b.rs"), "fixed");
+    }
+
+    #[test]
+    fn test_check_repo() {
+        let metadata = parse_metadata(
+            br#"{
+                "argv0": "lint",
+                "max_filenames": 10,
+                "require_serial": false,
+                "args": [],
+                "default_match": { "bool": true },
+                "repo": "https://github.com/Example/lint/"
+            }"#,
+            Path::new("lint.wasm"),
+        )
+        .unwrap();
+        let mut linter = ConfigLinter::from_spec("lint.wasm", Path::new("/repo")).unwrap();
+        check_repo(&metadata, &linter).unwrap();
+
+        linter.expected_repo = Some("https://github.com/example/lint.git".to_owned());
+        check_repo(&metadata, &linter).unwrap();
+
+        linter.expected_repo = Some("https://github.com/attacker/lint".to_owned());
+        assert!(check_repo(&metadata, &linter).is_err());
+    }
+
+    #[test]
+    fn test_override_args() {
+        let metadata = parse_metadata(
+            br#"{
+                "argv0": "lint",
+                "max_filenames": 10,
+                "require_serial": false,
+                "args": [
+                    { "name": "extra", "args": [] },
+                    { "name": "separator", "args": ["--"], "overridable": false }
+                ],
+                "default_match": { "bool": true },
+                "repo": ""
+            }"#,
+            Path::new("lint.wasm"),
+        )
+        .unwrap();
+        let mut linter = ConfigLinter::from_spec("lint.wasm:--strict", Path::new("/repo")).unwrap();
+        assert_eq!(
+            linter_args(&metadata, &linter).unwrap(),
+            ["lint", "--strict", "--"]
+        );
+
+        linter.override_args = Some(BTreeMap::from([("separator".to_owned(), Vec::new())]));
+        let err = linter_args(&metadata, &linter).unwrap_err();
+        assert!(err.to_string().contains("can't be overridden"));
+
+        linter.override_args = Some(BTreeMap::from([("unknown".to_owned(), Vec::new())]));
+        let err = linter_args(&metadata, &linter).unwrap_err();
+        assert!(err.to_string().contains("isn't valid"));
+    }
+
+    #[test]
+    fn test_ensure_cache_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        ensure_cache_dir(&cache_dir).unwrap();
+        assert!(cache_dir.is_dir());
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 0);
+
+        // Can't create a directory inside a file.
+        std::fs::write(dir.path().join("file"), "").unwrap();
+        let err = ensure_cache_dir(&dir.path().join("file/cache")).unwrap_err();
+        assert!(err.to_string().contains("NIT_CACHE_DIR"));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(Ok(Ok(()))).unwrap(), 0);
+        assert_eq!(exit_code(Ok(Err(()))).unwrap(), 1);
+        assert_eq!(exit_code(Err(anyhow::Error::new(I32Exit(0)))).unwrap(), 0);
+        assert_eq!(exit_code(Err(anyhow::Error::new(I32Exit(3)))).unwrap(), 3);
+        // The exit may have context added.
+        let exit = anyhow::Error::new(I32Exit(2)).context("wasm backtrace");
+        assert_eq!(exit_code(Err(exit)).unwrap(), 2);
+        assert!(exit_code(Err(anyhow!("wasm trap: unreachable"))).is_err());
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let trap = anyhow::Error::new(Trap::UnreachableCodeReached)
+            .context("wasm backtrace")
+            .context("Linter 'test' didn't exit normally");
+        assert!(is_transient(&trap));
+        assert!(!is_transient(&anyhow::Error::new(Trap::Interrupt)));
+        let busy = std::io::Error::from(std::io::ErrorKind::ResourceBusy);
+        assert!(is_transient(
+            &anyhow::Error::new(busy).context("Hashing a.txt")
+        ));
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(!is_transient(
+            &anyhow::Error::new(missing).context("Hashing a.txt")
+        ));
+        assert!(!is_transient(&anyhow!("Invalid metadata")));
+    }
+
+    #[test]
+    fn test_chunks_are_balanced() {
+        let files: Vec<FileInfo> = (1..=10)
+            .map(|size| FileInfo {
+                size,
+                ..file(&format!("{size:02}.rs"))
+            })
+            .collect();
+        let files: Vec<&FileInfo> = files.iter().collect();
+        let chunks = chunk_filenames(
+            &linter_paths(&files, Granularity::File),
+            5,
+            PathStyle::Relative,
+        )
+        .unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        let totals: Vec<u64> = chunks
+            .iter()
+            .map(|chunk| {
+                assert!(chunk.len() <= 5);
+                chunk
+                    .iter()
+                    .map(|name| name[..2].parse::<u64>().unwrap())
+                    .sum()
+            })
+            .collect();
+        assert_eq!(totals.iter().sum::<u64>(), 55);
+        assert!(totals[0].abs_diff(totals[1]) <= 1, "{totals:?}");
+    }
+
+    #[test]
+    fn test_absolute_paths() {
+        let files = [file("a.rs"), file("src/b.rs")];
+        let files: Vec<&FileInfo> = files.iter().collect();
+        assert_eq!(
+            chunk_filenames(
+                &linter_paths(&files, Granularity::File),
+                10,
+                PathStyle::Absolute
+            )
+            .unwrap(),
+            vec![vec!["/a.rs", "/src/b.rs"]]
+        );
+    }
+
+    #[test]
+    fn test_directory_granularity() {
+        let files = [
+            FileInfo {
+                size: 1,
+                ..file("src/a.rs")
+            },
+            FileInfo {
+                size: 2,
+                ..file("src/b.rs")
+            },
+            file("src/nested/c.rs"),
+            FileInfo {
+                size: 4,
+                ..file("build.rs")
+            },
+        ];
+        let files: Vec<&FileInfo> = files.iter().collect();
+        let directories = linter_paths(&files, Granularity::Directory);
+        assert_eq!(
+            directories,
+            [
+                (PathBuf::from("."), 4),
+                ("src".into(), 3),
+                ("src/nested".into(), 0)
+            ]
+        );
+        assert_eq!(
+            chunk_filenames(&directories, 10, PathStyle::Absolute).unwrap(),
+            vec![vec!["/", "/src", "/src/nested"]]
+        );
+    }
+
+    #[test]
+    fn test_long_argv_uses_stdin() {
+        let args = ["lint", "--fix"];
+        let chunk = ["a.rs", "b.rs"];
+
+        // "lint\0--fix\0a.rs\0b.rs\0" is 21 bytes.
+        assert_eq!(
+            filename_input(&args, &chunk, true, 21),
+            FilenameInput::Args(vec!["a.rs", "b.rs"])
+        );
+        assert_eq!(
+            filename_input(&args, &chunk, true, 20),
+            FilenameInput::Stdin(b"a.rs\0b.rs\0".to_vec())
+        );
+        // Linters that can't read stdin still get their arguments.
+        assert_eq!(
+            filename_input(&args, &chunk, false, 20),
+            FilenameInput::Args(vec!["a.rs", "b.rs"])
+        );
+    }
+
+    #[test]
+    fn test_split_long_chunks() {
+        let args = ["lint", "--fix"];
+        let chunks = || {
+            vec![
+                vec!["a.rs".to_owned(), "b.rs".to_owned(), "c.rs".to_owned()],
+                vec!["d.rs".to_owned()],
+            ]
+        };
+
+        // "lint\0--fix\0" is 11 bytes and each filename is 5.
+        assert_eq!(split_long_chunks(&args, chunks(), false, 26), chunks());
+        assert_eq!(
+            split_long_chunks(&args, chunks(), false, 21),
+            [vec!["a.rs", "b.rs"], vec!["c.rs"], vec!["d.rs"]]
+        );
+        // Each filename still gets a run, even if it doesn't fit.
+        assert_eq!(
+            split_long_chunks(&args, chunks(), false, 0),
+            [vec!["a.rs"], vec!["b.rs"], vec!["c.rs"], vec!["d.rs"]]
+        );
+        // Linters that read stdin get the whole chunk there instead.
+        assert_eq!(split_long_chunks(&args, chunks(), true, 21), chunks());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{fmt::Write as _, path::Path};
+
+use anyhow::Result;
+
+use crate::{
+    config::{ConfigLinter, LinterLocation},
+    engine::{linter_args, linter_match},
+    metadata::NitMetadata,
+};
+
+/// Where a setting came from.
+fn source(overridden: bool) -> &'static str {
+    if overridden { "config" } else { "metadata" }
+}
+
+/// Describe how `linter` is configured and how it will be invoked, for
+/// `nit explain`. `linter_path` is where its `.wasm` file is.
+pub fn explain_linter(
+    linter: &ConfigLinter,
+    metadata: &NitMetadata,
+    linter_path: &Path,
+) -> Result<String> {
+    let mut out = String::new();
+    writeln!(out, "Linter: {}", linter.name)?;
+    match &linter.location {
+        LinterLocation::Remote(remote) => {
+            writeln!(out, "URL: {}", remote.url)?;
+            writeln!(out, "Hash: {}", remote.expected_hash())?;
+        }
+        LinterLocation::Local(path) => writeln!(out, "Local path: {path}")?,
+    }
+    writeln!(out, "Binary: {}", linter_path.display())?;
+    writeln!(out, "Repo: {}", metadata.repo)?;
+    if let Some(expected_repo) = &linter.expected_repo {
+        writeln!(out, "Expected repo: {expected_repo}")?;
+    }
+
+    writeln!(out)?;
+    let argv0 = linter.override_argv0.as_ref().unwrap_or(&metadata.argv0);
+    writeln!(
+        out,
+        "argv0: {argv0} ({})",
+        source(linter.override_argv0.is_some())
+    )?;
+    writeln!(out, "Argument blocks:")?;
+    let override_args = linter.override_args.as_ref();
+    for block in &metadata.args {
+        let overridden = override_args.and_then(|args| args.get(&block.name));
+        writeln!(
+            out,
+            "  {}: {:?} ({}{})",
+            block.name,
+            overridden.unwrap_or(&block.args),
+            source(overridden.is_some()),
+            if block.overridable { "" } else { ", fixed" }
+        )?;
+    }
+    let args = linter_args(metadata, linter)?;
+    let max_filenames = linter
+        .override_max_filenames
+        .unwrap_or(metadata.max_filenames);
+    let filenames = if max_filenames == 0 {
+        ""
+    } else if metadata.filenames_on_stdin {
+        " <filenames (on stdin if too long)>"
+    } else {
+        " <filenames>"
+    };
+    writeln!(out, "Command line: {}{filenames}", args.join(" "))?;
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Match ({}): {}",
+        source(linter.override_match.is_some()),
+        serde_json::to_string(linter_match(metadata, linter))?
+    )?;
+    writeln!(out, "Text only: {}", metadata.text_only)?;
+    if let Some(encodings) = &metadata.encodings {
+        writeln!(out, "Encodings: {encodings:?}")?;
+    }
+    if let Some(branches) = &linter.branches {
+        writeln!(out, "Branches: {branches}")?;
+    }
+    if !linter.tags.is_empty() {
+        writeln!(out, "Tags: {}", linter.tags.join(", "))?;
+    }
+    if !linter.depends_on.is_empty() {
+        writeln!(out, "Depends on: {}", linter.depends_on.join(", "))?;
+    }
+
+    writeln!(out)?;
+    let filenames_source = source(linter.override_max_filenames.is_some());
+    if max_filenames == 0 {
+        writeln!(out, "Filenames ({filenames_source}): none (run once)")?;
+    } else {
+        writeln!(
+            out,
+            "Filenames ({filenames_source}): up to {max_filenames} per run, {:?} paths, {:?} granularity",
+            metadata.path_style, metadata.granularity
+        )?;
+    }
+    let require_serial = linter
+        .override_require_serial
+        .unwrap_or(metadata.require_serial);
+    writeln!(
+        out,
+        "Parallelism ({}): {}",
+        source(linter.override_require_serial.is_some()),
+        if require_serial { "serial" } else { "parallel" }
+    )?;
+    writeln!(out, "Check only: {}", metadata.check_only)?;
+    writeln!(
+        out,
+        "Structured diagnostics: {}",
+        metadata.structured_diagnostics
+    )?;
+    writeln!(out, "Git requests: {}", metadata.git_requests)?;
+    writeln!(out, "Stage: {:?}", metadata.stage)?;
+    writeln!(out, "Allow failure: {}", linter.allow_failure)?;
+    writeln!(out, "Retries: {}", linter.retries)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::metadata::parse_metadata;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_explain_linter() {
+        let metadata = parse_metadata(
+            br#"{
+                "argv0": "lint",
+                "max_filenames": 10,
+                "require_serial": true,
+                "args": [{ "name": "base", "args": ["--fix"] }, { "name": "extra", "args": [] }],
+                "default_match": { "glob": "*.rs" },
+                "repo": "https://example.com/lint"
+            }"#,
+            Path::new("lint.wasm"),
+        )
+        .unwrap();
+        let linter = ConfigLinter::from_spec("lint.wasm:--strict", Path::new("/repo")).unwrap();
+
+        let explanation =
+            explain_linter(&linter, &metadata, &PathBuf::from("/repo/lint.wasm")).unwrap();
+        assert!(explanation.contains("  base: [\"--fix\"] (metadata)\n"));
+        assert!(explanation.contains("  extra: [\"--strict\"] (config)\n"));
+        assert!(explanation.contains("Command line: lint --fix --strict <filenames>\n"));
+        assert!(explanation.contains("Match (metadata): {\"glob\":\"*.rs\"}\n"));
+        assert!(explanation.contains("Parallelism (metadata): serial\n"));
+    }
+}
@@ -1,11 +1,12 @@
 use indicatif::ProgressBar;
-use log::info;
-use reqwest::Url;
+use log::{info, warn};
+use reqwest::{Url, redirect::Policy};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     io::Write,
+    num::NonZeroUsize,
     path::Path,
-    sync::{Arc, atomic::AtomicU64},
+    sync::{Arc, Mutex, atomic::AtomicU64},
 };
 
 use anyhow::{Context, Result, anyhow, bail};
@@ -16,11 +17,34 @@ use tokio::{
 };
 
 use crate::{
-    config::{ConfigLinter, LinterLocation},
-    engine::get_url_linter_path,
+    config::{ConfigLinter, LinterLocation, RemoteLocation},
+    decompress::{ACCEPT_ENCODING, decompress},
+    engine::{ensure_cache_dir, get_url_linter_path},
+    manifest::Manifest,
+    policy::load_policy,
+    profile,
     unique_filename::unique_filename,
+    wasi_cache,
 };
 
+/// Maximum number of redirects to follow, like reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// Check that the host of `url` is one of `allowed_hosts` (ignoring case).
+fn check_allowed_host(url: &Url, allowed_hosts: &[String]) -> Result<()> {
+    let host = url.host_str().unwrap_or_default();
+    if !allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        bail!(
+            "Linter URL {url} isn't from an allowed host. The allowed hosts are: {}",
+            allowed_hosts.join(", ")
+        );
+    }
+    Ok(())
+}
+
 /// Calculate the SHA3 hash of a file.
 pub async fn file_binary_hash(path: &Path) -> Result<String> {
     let mut file = File::open(path).await?;
@@ -38,8 +62,36 @@ pub async fn file_binary_hash(path: &Path) -> Result<String> {
     Ok(hasher.finalize().to_hex().to_string())
 }
 
-pub async fn download(url: Url, save_to: &Path, progress_bar: ProgressBar) -> Result<()> {
-    let response = reqwest::get(url.clone())
+/// Follow redirects, but only to `allowed_hosts` if it is set, so a server
+/// can't send us to a host that the policy doesn't allow.
+fn redirect_policy(allowed_hosts: Option<Vec<String>>) -> Policy {
+    Policy::custom(move |attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        match &allowed_hosts {
+            Some(allowed_hosts) => match check_allowed_host(attempt.url(), allowed_hosts) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(format!("Redirect rejected: {e}")),
+            },
+            None => attempt.follow(),
+        }
+    })
+}
+
+pub async fn download(
+    url: Url,
+    save_to: &Path,
+    progress_bar: ProgressBar,
+    allowed_hosts: Option<&[String]>,
+) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .redirect(redirect_policy(allowed_hosts.map(<[String]>::to_vec)))
+        .build()?;
+    let response = client
+        .get(url.clone())
+        .header(reqwest::header::ACCEPT_ENCODING, ACCEPT_ENCODING)
+        .send()
         .await
         .with_context(|| anyhow!("GET '{url}'"))?;
 
@@ -69,12 +121,22 @@ pub async fn download(url: Url, save_to: &Path, progress_bar: ProgressBar) -> Re
 
     let downloaded_bytes = downloaded_bytes.load(std::sync::atomic::Ordering::Acquire);
 
-    if let Some(content_length) = content_length {
-        if downloaded_bytes != content_length {
-            bail!(
-                "Content length from server was {content_length} but we downloaded {downloaded_bytes} bytes"
-            );
-        }
+    if let Some(content_length) = content_length
+        && downloaded_bytes != content_length
+    {
+        bail!(
+            "Content length from server was {content_length} but we downloaded {downloaded_bytes} bytes"
+        );
+    }
+
+    // Check the data rather than `Content-Encoding`, which is often missing
+    // for pre-compressed files. The hash is of the decompressed linter.
+    let data = fs::read(save_to).await?;
+    let decompressed = tokio::task::spawn_blocking(move || decompress(&data)).await??;
+    if let Some(decompressed) = decompressed {
+        fs::write(save_to, decompressed)
+            .await
+            .with_context(|| anyhow!("Writing to destination file: '{}'", save_to.display()))?;
     }
 
     Ok(())
@@ -84,30 +146,76 @@ fn to_async_read(
     stream: impl Stream<Item = std::result::Result<tokio_util::bytes::Bytes, reqwest::Error>>,
 ) -> impl AsyncBufRead {
     // Map Arc<reqwest::Error> back to io::Error, and wrap with StreamReader.
-    tokio_util::io::StreamReader::new(stream.map_err(|ae| std::io::Error::other(ae)))
+    tokio_util::io::StreamReader::new(stream.map_err(std::io::Error::other))
+}
+
+/// Get the URLs of linters that `fetch_linters()` would download because they
+/// aren't in the cache (or have the wrong hash).
+pub async fn missing_linters<'a>(
+    linters: &'a [ConfigLinter],
+    cache_dir: &Path,
+) -> Result<BTreeSet<&'a str>> {
+    let mut missing = BTreeSet::new();
+    for linter in linters {
+        if let LinterLocation::Remote(remote) = &linter.location {
+            let binary_path = get_url_linter_path(cache_dir, &remote.url);
+            let maybe_hash = file_binary_hash(&binary_path).await;
+            if !matches!(maybe_hash, Ok(h) if remote.hash_matches(&h)) {
+                missing.insert(remote.url.as_str());
+            }
+        }
+    }
+    Ok(missing)
 }
 
-pub async fn fetch_linters(linters: &[ConfigLinter], cache_dir: &Path) -> Result<()> {
+/// Download any linters that aren't in the cache (or have the wrong hash).
+/// Verified hashes are recorded in `manifest` so that unchanged linters don't
+/// need to be re-hashed next time. Up to `max_concurrent_downloads` linters are
+/// downloaded at once. Linters from hosts that the user's or system's policy
+/// doesn't allow are rejected.
+pub async fn fetch_linters(
+    linters: &[ConfigLinter],
+    cache_dir: &Path,
+    manifest: &Mutex<Manifest>,
+    max_concurrent_downloads: NonZeroUsize,
+) -> Result<()> {
+    let _span = profile::span("fetch linters");
     info!("Fetching linters...");
 
+    let policy = load_policy()?;
+    let allowed_hosts = policy.allowed_hosts.as_deref();
+
     // 1. Collect all the URL/binary hash pairs.
     // 2. Deduplicate URLs. Throw an error if different binary hashes
     //    were given for the same URL.
     // 3. Check which ones are already downloaded.
     // 4. Download the missing ones atomically.
 
-    let mut url_to_hash = BTreeMap::new();
+    let mut url_to_hash: BTreeMap<&str, &RemoteLocation> = BTreeMap::new();
     for linter in linters {
         // Don't need to download local linters.
         match &linter.location {
             LinterLocation::Local(_) => {}
             LinterLocation::Remote(remote) => {
-                if let Some(hash) = url_to_hash.get(&remote.url) {
-                    if hash != &remote.hash {
+                if let Some(other) = url_to_hash.get(remote.url.as_str()) {
+                    if (&other.hash, &other.hash_prefix) != (&remote.hash, &remote.hash_prefix) {
                         bail!("Different binary hashes for the same URL: {}", remote.url);
                     }
                 } else {
-                    url_to_hash.insert(remote.url.clone(), remote.hash.clone());
+                    if let Some(allowed_hosts) = allowed_hosts {
+                        let url = remote
+                            .url
+                            .parse()
+                            .with_context(|| anyhow!("Invalid linter URL {}", remote.url))?;
+                        check_allowed_host(&url, allowed_hosts)?;
+                    }
+                    if remote.hash.is_none() {
+                        warn!(
+                            "Linter '{}' only has a hash_prefix. This is less secure than a full hash, so only use it for experiments",
+                            linter.name
+                        );
+                    }
+                    url_to_hash.insert(&remote.url, remote);
                 }
             }
         }
@@ -137,19 +245,21 @@ pub async fn fetch_linters(linters: &[ConfigLinter], cache_dir: &Path) -> Result
     // first task to finish.
     main_pb.tick();
 
-    let max_concurrent_downloads = 4;
+    // A read-only cache is fine if the linters are already in it (e.g. baked
+    // into a Docker image), so this is only an error if we need to download.
+    let cache_dir_error = ensure_cache_dir(cache_dir).err();
 
-    std::fs::create_dir_all(cache_dir)?;
-
-    // Set up a future to iterate over tasks and run up to 3 at a time.
+    // Set up a future to iterate over tasks and run up to
+    // `max_concurrent_downloads` at a time.
     task_info_stream
         .enumerate()
         // Weirdly try_for_each_concurrent needs its *input* to be fallible.
         .map(Ok)
-        .try_for_each_concurrent(max_concurrent_downloads, |(i, (url, hash))| {
+        .try_for_each_concurrent(max_concurrent_downloads.get(), |(i, (url, remote))| {
             // Clone multibar and main_pb.  We will move the clones into each task.
             let multibar = multibar.clone();
             let main_pb = main_pb.clone();
+            let cache_dir_error = &cache_dir_error;
             async move {
                 // Add a new progress indicator to the multibar.
                 let task_pb = multibar.add(indicatif::ProgressBar::no_length());
@@ -163,26 +273,42 @@ pub async fn fetch_linters(linters: &[ConfigLinter], cache_dir: &Path) -> Result
 
                 let binary_path = get_url_linter_path(cache_dir, url);
 
+                // Skip hashing if we already verified it and it hasn't changed.
+                let verified_hash = manifest.lock().unwrap().hash(&binary_path);
+                if verified_hash.is_some_and(|h| remote.hash_matches(&h)) {
+                    main_pb.inc(1);
+                    task_pb.finish_and_clear();
+                    return Ok(());
+                }
+
                 // Check if it already exists.
                 let maybe_hash = file_binary_hash(&binary_path).await;
-                if !matches!(maybe_hash, Ok(h) if h == *hash) {
+                if !matches!(maybe_hash, Ok(h) if remote.hash_matches(&h)) {
                     let url = url.parse()?;
 
                     info!("Downloading {url}");
 
+                    if let Some(e) = cache_dir_error {
+                        bail!("{e:#}");
+                    }
+
                     let tmpfile = binary_path.with_file_name(unique_filename("tmp-", ".wasm"));
 
-                    download(url, &tmpfile, task_pb.clone()).await?;
+                    download(url, &tmpfile, task_pb.clone(), allowed_hosts).await?;
                     fs::rename(tmpfile, &binary_path).await?;
+                    wasi_cache::remove_compiled_caches(&binary_path).await?;
                 }
 
                 let read_hash = file_binary_hash(&binary_path).await?;
-                if read_hash != *hash {
+                if !remote.hash_matches(&read_hash) {
                     bail!(
-                        "Hash mismatch for {url} after download: expected {hash}, got {read_hash}"
+                        "Hash mismatch for {url} after download: expected {}, got {read_hash}",
+                        remote.expected_hash()
                     );
                 }
 
+                manifest.lock().unwrap().set_hash(&binary_path, read_hash);
+
                 // Increment the overall progress indicator.
                 main_pb.inc(1);
 
@@ -200,3 +326,60 @@ pub async fn fetch_linters(linters: &[ConfigLinter], cache_dir: &Path) -> Result
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_allowed_host() {
+        let allowed = ["lints.example.com".to_owned(), "github.com".to_owned()];
+        let check = |url: &str| check_allowed_host(&url.parse().unwrap(), &allowed);
+        check("https://lints.example.com/foo.wasm").unwrap();
+        check("https://GitHub.com:443/a/b.wasm").unwrap();
+        let err = check("https://evil.com/lints.example.com/foo.wasm").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Linter URL https://evil.com/lints.example.com/foo.wasm isn't from an allowed host. The allowed hosts are: lints.example.com, github.com"
+        );
+        assert!(check("https://lints.example.com.evil.com/").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_redirect_to_disallowed_host() {
+        // Redirect to the same server by a name that isn't allowed.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            use std::io::Read as _;
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 302 Found\r\nLocation: http://localhost:{port}/lint.wasm\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let url: Url = format!("http://127.0.0.1:{port}/lint.wasm")
+            .parse()
+            .unwrap();
+        let err = download(
+            url,
+            &dir.path().join("lint.wasm"),
+            ProgressBar::hidden(),
+            Some(&["127.0.0.1".to_owned()]),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            format!("{err:#}").contains(&format!(
+                "Linter URL http://localhost:{port}/lint.wasm isn't from an allowed host"
+            )),
+            "{err:#}"
+        );
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context as _, Result, anyhow};
+
+use crate::{git::FileInfo, profile};
+
+/// Files modified this recently may be modified again without their
+/// modification time changing, because of its granularity, so their stat
+/// data can't be trusted.
+const RACY_PERIOD: Duration = Duration::from_secs(2);
+
+/// The state of a file, so we can tell if it was modified. Two states are
+/// equal if the contents are, regardless of the stat data.
+#[derive(Clone, Debug)]
+pub struct FileHash {
+    /// Size and modification time, if the file exists.
+    stat: Option<(u64, SystemTime)>,
+    /// Whether the stat data can be used to skip reading the file again.
+    trusted: bool,
+    contents: Contents,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Contents {
+    /// The file doesn't exist (e.g. because a linter deleted it).
+    Missing,
+    File(blake3::Hash),
+    Symlink(PathBuf),
+    /// Directories etc. which aren't read.
+    Other,
+}
+
+impl PartialEq for FileHash {
+    fn eq(&self, other: &Self) -> bool {
+        self.contents == other.contents
+    }
+}
+
+impl FileHash {
+    /// When the file was last modified, if it exists.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.stat.map(|(_, modified)| modified)
+    }
+
+    /// Hash of the contents (or symlink target) for use in cache keys.
+    pub fn digest(&self) -> blake3::Hash {
+        match &self.contents {
+            Contents::Missing => blake3::hash(b"missing"),
+            Contents::File(hash) => *hash,
+            Contents::Symlink(target) => {
+                blake3::hash(format!("symlink {}", target.display()).as_bytes())
+            }
+            Contents::Other => blake3::hash(b"other"),
+        }
+    }
+}
+
+/// Hash the contents of `files` so we can tell if they were modified.
+/// Symlinks are recorded by their target and other files that aren't regular
+/// files (e.g. directories) aren't read.
+pub fn hash_files(top_level: &Path, files: &[&FileInfo]) -> Result<Vec<FileHash>> {
+    let _span = profile::span("hash files");
+    let now = SystemTime::now();
+    files
+        .iter()
+        .map(|file| hash_path(&top_level.join(&file.path), now, None))
+        .collect()
+}
+
+/// Like `hash_files()`, but files whose size and modification time haven't
+/// changed since `before` (from hashing the same `files`) aren't read again.
+pub fn rehash_files(
+    top_level: &Path,
+    files: &[&FileInfo],
+    before: &[FileHash],
+) -> Result<Vec<FileHash>> {
+    let _span = profile::span("rehash files");
+    let now = SystemTime::now();
+    files
+        .iter()
+        .zip(before)
+        .map(|(file, before)| hash_path(&top_level.join(&file.path), now, Some(before)))
+        .collect()
+}
+
+/// Like `hash_files()` for a single file.
+pub fn hash_file(full_path: &Path) -> Result<FileHash> {
+    hash_path(full_path, SystemTime::now(), None)
+}
+
+/// Like `rehash_files()` for a single file.
+pub fn rehash_file(full_path: &Path, before: &FileHash) -> Result<FileHash> {
+    hash_path(full_path, SystemTime::now(), Some(before))
+}
+
+fn hash_path(full_path: &Path, now: SystemTime, before: Option<&FileHash>) -> Result<FileHash> {
+    let metadata = match full_path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(FileHash {
+                stat: None,
+                trusted: false,
+                contents: Contents::Missing,
+            });
+        }
+        Err(e) => return Err(e).with_context(|| anyhow!("Hashing {}", full_path.display())),
+    };
+    let stat = metadata
+        .modified()
+        .ok()
+        .map(|modified| (metadata.len(), modified));
+    let trusted = stat.is_some_and(|(_, modified)| {
+        now.duration_since(modified)
+            .is_ok_and(|age| age > RACY_PERIOD)
+    });
+
+    if let Some(before) = before
+        && trusted
+        && before.trusted
+        && before.stat == stat
+    {
+        return Ok(before.clone());
+    }
+
+    let contents = if metadata.is_symlink() {
+        Contents::Symlink(
+            std::fs::read_link(full_path)
+                .with_context(|| anyhow!("Hashing {}", full_path.display()))?,
+        )
+    } else if metadata.is_file() {
+        // Stream the file rather than reading it into memory, since linted
+        // files can be large and this is done before every linter.
+        let mut hasher = blake3::Hasher::new();
+        std::fs::File::open(full_path)
+            .and_then(|file| hasher.update_reader(file).map(|_| ()))
+            .with_context(|| anyhow!("Hashing {}", full_path.display()))?;
+        Contents::File(hasher.finalize())
+    } else {
+        Contents::Other
+    };
+    Ok(FileHash {
+        stat,
+        trusted,
+        contents,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::text_file as file;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_hash_files() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+
+        let files = [file("a.txt"), file("missing.txt")];
+        let files: Vec<&FileInfo> = files.iter().collect();
+
+        let before = hash_files(dir.path(), &files).unwrap();
+        assert_eq!(before[0].contents, Contents::File(blake3::hash(b"a")));
+        assert_eq!(before[1].contents, Contents::Missing);
+
+        std::fs::write(dir.path().join("a.txt"), "b").unwrap();
+        let after = rehash_files(dir.path(), &files, &before).unwrap();
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn test_rehash_skips_unchanged_stat() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, "a").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let files = [file("a.txt")];
+        let files: Vec<&FileInfo> = files.iter().collect();
+        let before = hash_files(dir.path(), &files).unwrap();
+        assert!(before[0].trusted);
+
+        // Same size and modification time, so it isn't read again.
+        std::fs::write(&path, "b").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert_eq!(rehash_files(dir.path(), &files, &before).unwrap(), before);
+        assert_ne!(hash_files(dir.path(), &files).unwrap(), before);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hash_symlink_to_dir() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        std::fs::create_dir(dir.path().join("dir")).unwrap();
+        std::os::unix::fs::symlink("dir", dir.path().join("link")).unwrap();
+
+        let files = [file("link"), file("dir")];
+        let files: Vec<&FileInfo> = files.iter().collect();
+        let hashes = hash_files(dir.path(), &files).unwrap();
+        assert_eq!(hashes[0].contents, Contents::Symlink("dir".into()));
+        assert_eq!(hashes[1].contents, Contents::Other);
+    }
+}
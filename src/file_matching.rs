@@ -1,9 +1,13 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Result, bail};
+use log::warn;
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::git::{FileInfo, FileType};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MatchExpression {
     /// Matches a Glob (* and ? and **).
@@ -12,6 +16,10 @@ pub enum MatchExpression {
     /// Matches a regex on the path.
     #[serde(with = "crate::serde_regex")]
     Regex(Regex),
+    /// The file's extension (the part after the last `.`) is one of these,
+    /// ignoring case, e.g. `{ extension: ["rs", "toml"] }`. The leading `.`
+    /// is optional.
+    Extension(Vec<String>),
     /// Is a specific file type.
     Type(FileType),
     /// Shebang matches this regex.
@@ -25,6 +33,117 @@ pub enum MatchExpression {
     And(Vec<MatchExpression>),
     /// Bool literal.
     Bool(bool),
+    /// The start of the file matches one of the config's `generated_markers`,
+    /// e.g. `@generated`. Use `{ not: "generated_marker" }` to skip
+    /// generated files.
+    GeneratedMarker,
+    /// One of the config's `definitions`. These are replaced by the
+    /// definition when the config is loaded, so they can't be used in linter
+    /// metadata.
+    Ref(String),
+    /// Alternative expressions for different platforms, e.g.
+    /// `{ platform: { windows: { glob: "*.bat" }, any: { glob: "*.sh" } } }`.
+    /// The host's entry is used if there is one, otherwise `any`. If neither
+    /// is present nothing matches.
+    Platform(PlatformAlternatives),
+}
+
+/// The alternatives in a `platform` match expression.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlatformAlternatives {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix: Option<Box<MatchExpression>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub windows: Option<Box<MatchExpression>>,
+    /// Fallback for platforms without their own entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub any: Option<Box<MatchExpression>>,
+}
+
+impl PlatformAlternatives {
+    /// The name of the platform Nit is running on.
+    fn host_name() -> &'static str {
+        if cfg!(windows) { "windows" } else { "unix" }
+    }
+
+    /// Get the expression that applies on this host.
+    fn for_host(&self) -> Option<&MatchExpression> {
+        let host = if cfg!(windows) {
+            &self.windows
+        } else {
+            &self.unix
+        };
+        host.as_deref().or(self.any.as_deref())
+    }
+
+    fn all_mut(&mut self) -> impl Iterator<Item = &mut MatchExpression> {
+        [&mut self.unix, &mut self.windows, &mut self.any]
+            .into_iter()
+            .filter_map(|alternative| alternative.as_deref_mut())
+    }
+}
+
+/// Matches everything.
+impl Default for MatchExpression {
+    fn default() -> Self {
+        MatchExpression::Bool(true)
+    }
+}
+
+/// Replace `Ref`s in `expr` with the corresponding expression from
+/// `definitions`. Fails if a name isn't defined or a definition refers to
+/// itself.
+pub fn resolve_refs(
+    expr: &mut MatchExpression,
+    definitions: &BTreeMap<String, MatchExpression>,
+) -> Result<()> {
+    resolve_refs_inner(expr, definitions, &mut Vec::new())
+}
+
+fn resolve_refs_inner<'a>(
+    expr: &mut MatchExpression,
+    definitions: &'a BTreeMap<String, MatchExpression>,
+    // Names of the definitions that are being resolved, to detect cycles.
+    stack: &mut Vec<&'a str>,
+) -> Result<()> {
+    match expr {
+        MatchExpression::Ref(name) => {
+            let Some((name, definition)) = definitions.get_key_value(name.as_str()) else {
+                bail!("Match expression refers to unknown definition '{name}'");
+            };
+            if stack.contains(&name.as_str()) {
+                bail!(
+                    "Definition '{name}' refers to itself: {} -> {name}",
+                    stack.join(" -> ")
+                );
+            }
+            let mut resolved = definition.clone();
+            stack.push(name);
+            resolve_refs_inner(&mut resolved, definitions, stack)?;
+            stack.pop();
+            *expr = resolved;
+        }
+        MatchExpression::Not(inner) => resolve_refs_inner(inner, definitions, stack)?,
+        MatchExpression::Or(inner) | MatchExpression::And(inner) => {
+            for inner in inner {
+                resolve_refs_inner(inner, definitions, stack)?;
+            }
+        }
+        MatchExpression::Platform(alternatives) => {
+            for inner in alternatives.all_mut() {
+                resolve_refs_inner(inner, definitions, stack)?;
+            }
+        }
+        MatchExpression::Glob(_)
+        | MatchExpression::Regex(_)
+        | MatchExpression::Extension(_)
+        | MatchExpression::Type(_)
+        | MatchExpression::ShebangRegex(_)
+        | MatchExpression::Bool(_)
+        | MatchExpression::GeneratedMarker => {}
+    }
+    Ok(())
 }
 
 /// Returns true if `file` matches `expr`.
@@ -33,17 +152,108 @@ fn file_matches(file: &FileInfo, expr: &MatchExpression) -> bool {
         MatchExpression::Glob(glob_pattern) => file
             .path
             .to_str()
-            .map_or(false, |path| glob_pattern.matches(path)),
-        MatchExpression::Regex(re) => file.path.to_str().map_or(false, |path| re.is_match(path)),
+            .is_some_and(|path| glob_pattern.matches(path)),
+        MatchExpression::Regex(re) => file.path.to_str().is_some_and(|path| re.is_match(path)),
+        MatchExpression::Extension(extensions) => has_extension(&file.path, extensions),
         MatchExpression::Type(ty) => ty == &file.ty,
         MatchExpression::ShebangRegex(re) => file
             .shebang
             .as_ref()
-            .map_or(false, |shebang| re.is_match(shebang)),
+            .is_some_and(|shebang| re.is_match(shebang)),
         MatchExpression::Not(inner) => !file_matches(file, inner),
         MatchExpression::Or(inner) => inner.iter().any(|inner| file_matches(file, inner)),
         MatchExpression::And(inner) => inner.iter().all(|inner| file_matches(file, inner)),
         MatchExpression::Bool(b) => *b,
+        MatchExpression::GeneratedMarker => file.generated,
+        // Unresolved, so it must be from linter metadata.
+        MatchExpression::Ref(name) => {
+            warn!("Ignoring reference to definition '{name}' outside the config");
+            false
+        }
+        MatchExpression::Platform(alternatives) => alternatives
+            .for_host()
+            .is_some_and(|inner| file_matches(file, inner)),
+    }
+}
+
+/// Whether the extension of `path` is one of `extensions`, ignoring case.
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    let Some(extension) = path.extension().and_then(|extension| extension.to_str()) else {
+        return false;
+    };
+    extensions.iter().any(|expected| {
+        expected
+            .strip_prefix('.')
+            .unwrap_or(expected)
+            .eq_ignore_ascii_case(extension)
+    })
+}
+
+/// Explain why `file` doesn't match `expr`, for debugging match expressions.
+/// Returns `None` if it does match.
+pub fn mismatch_reason(file: &FileInfo, expr: &MatchExpression) -> Option<String> {
+    if file_matches(file, expr) {
+        return None;
+    }
+    Some(match expr {
+        MatchExpression::Glob(glob_pattern) => format!("doesn't match glob '{glob_pattern}'"),
+        MatchExpression::Regex(re) => format!("doesn't match regex '{re}'"),
+        MatchExpression::Extension(extensions) => {
+            format!("doesn't have any of the extensions {extensions:?}")
+        }
+        MatchExpression::Type(ty) => format!("is {:?}, not {ty:?}", file.ty),
+        MatchExpression::ShebangRegex(re) => match &file.shebang {
+            Some(shebang) => format!("shebang '{shebang}' doesn't match regex '{re}'"),
+            None => "has no shebang".to_owned(),
+        },
+        MatchExpression::Not(inner) => match inner.as_ref() {
+            MatchExpression::GeneratedMarker => "is generated".to_owned(),
+            _ => "matches a 'not' expression".to_owned(),
+        },
+        MatchExpression::Or(inner) if inner.is_empty() => "empty 'or' expression".to_owned(),
+        MatchExpression::Or(inner) => inner
+            .iter()
+            .filter_map(|inner| mismatch_reason(file, inner))
+            .collect::<Vec<_>>()
+            .join(" and "),
+        // Report the first reason; the rest are often irrelevant.
+        MatchExpression::And(inner) => inner
+            .iter()
+            .find_map(|inner| mismatch_reason(file, inner))
+            .unwrap_or_default(),
+        MatchExpression::Bool(_) => "'bool: false' matches nothing".to_owned(),
+        MatchExpression::GeneratedMarker => "isn't generated".to_owned(),
+        MatchExpression::Ref(name) => format!("refers to unknown definition '{name}'"),
+        MatchExpression::Platform(alternatives) => match alternatives.for_host() {
+            Some(inner) => mismatch_reason(file, inner).unwrap_or_default(),
+            None => format!(
+                "no expression for {} or any platform",
+                PlatformAlternatives::host_name()
+            ),
+        },
+    })
+}
+
+/// Returns true if `expr` explicitly asks for symlinks, i.e. it contains
+/// `type: "symlink"` outside of a `not`. Symlinks are rarely what a linter
+/// wants, so they are only passed to linters that ask for them.
+pub fn includes_symlinks(expr: &MatchExpression) -> bool {
+    match expr {
+        MatchExpression::Type(ty) => *ty == FileType::Symlink,
+        MatchExpression::Or(inner) | MatchExpression::And(inner) => {
+            inner.iter().any(includes_symlinks)
+        }
+        MatchExpression::Platform(alternatives) => {
+            alternatives.for_host().is_some_and(includes_symlinks)
+        }
+        MatchExpression::Glob(_)
+        | MatchExpression::Regex(_)
+        | MatchExpression::Extension(_)
+        | MatchExpression::ShebangRegex(_)
+        | MatchExpression::Not(_)
+        | MatchExpression::Bool(_)
+        | MatchExpression::GeneratedMarker
+        | MatchExpression::Ref(_) => false,
     }
 }
 
@@ -53,25 +263,169 @@ pub fn matching_files<'a>(files: &'a [FileInfo], expr: &MatchExpression) -> Vec<
 }
 
 /// Filter `files` according to the match `expr` (in-place version).
-pub fn retain_matching_files<'a>(files: &mut Vec<FileInfo>, expr: &MatchExpression) {
+pub fn retain_matching_files(files: &mut Vec<FileInfo>, expr: &MatchExpression) {
     files.retain(|f| file_matches(f, expr))
 }
 
+/// Remove files larger than `max_size` bytes, with a warning for each one.
+pub fn retain_files_up_to_size(files: &mut Vec<FileInfo>, max_size: u64) {
+    files.retain(|f| {
+        let keep = f.size <= max_size;
+        if !keep {
+            warn!(
+                "Skipping {} because it is {} bytes, which is more than the maximum of {max_size}",
+                f.path.display(),
+                f.size
+            );
+        }
+        keep
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::git::FileType;
+    use crate::git::{FileType, text_file as file};
 
     #[test]
     fn test_matching_files() {
-        let files = vec![FileInfo {
-            path: "foo.rs".into(),
-            ty: FileType::Text,
-            shebang: None,
-        }];
+        let files = vec![file("foo.rs")];
 
         let expr = MatchExpression::Glob(glob::Pattern::new("*.rs").unwrap());
         let matches = matching_files(&files, &expr);
         assert_eq!(matches.len(), 1);
     }
+
+    #[test]
+    fn test_mismatch_reason() {
+        let file = file("foo.rs");
+        let reason = |json: &str| {
+            mismatch_reason(
+                &file,
+                &serde_json5::from_str::<MatchExpression>(json).unwrap(),
+            )
+        };
+
+        assert_eq!(reason(r#"{ glob: "*.rs" }"#), None);
+        assert_eq!(
+            reason(r#"{ and: [{ glob: "*.rs" }, { type: "binary" }, { glob: "*.c" }] }"#)
+                .as_deref(),
+            Some("is Text, not Binary")
+        );
+        assert_eq!(
+            reason(r#"{ or: [{ glob: "*.c" }, { shebang_regex: "python" }] }"#).as_deref(),
+            Some("doesn't match glob '*.c' and has no shebang")
+        );
+        assert_eq!(
+            reason(r#"{ not: { glob: "*.rs" } }"#).as_deref(),
+            Some("matches a 'not' expression")
+        );
+    }
+
+    #[test]
+    fn test_extension() {
+        let expr: MatchExpression =
+            serde_json5::from_str(r#"{ extension: ["rs", ".TOML", "gz"] }"#).unwrap();
+        let matches = |path: &str| file_matches(&file(path), &expr);
+
+        assert!(matches("src/main.rs"));
+        assert!(matches("Cargo.toml"));
+        assert!(matches("README.RS"));
+        assert!(matches("foo.tar.gz"));
+        assert!(!matches("foo.rs.orig"));
+        assert!(!matches("Makefile"));
+        assert!(!matches("rs"));
+        // Dotfiles don't have an extension.
+        assert!(!matches(".rs"));
+        assert!(!matches("src.rs/main"));
+        assert_eq!(
+            mismatch_reason(&file("a.c"), &expr).as_deref(),
+            Some(r#"doesn't have any of the extensions ["rs", ".TOML", "gz"]"#)
+        );
+
+        let expr: MatchExpression = serde_json5::from_str(r#"{ extension: ["tar"] }"#).unwrap();
+        assert!(!file_matches(&file("foo.tar.gz"), &expr));
+    }
+
+    #[test]
+    fn test_resolve_refs() {
+        let parse = |json: &str| serde_json5::from_str::<MatchExpression>(json).unwrap();
+        let definitions = BTreeMap::from([
+            ("rust".to_owned(), parse(r#"{ glob: "**/*.rs" }"#)),
+            (
+                "code".to_owned(),
+                parse(r#"{ or: [{ ref: "rust" }, { glob: "*.c" }] }"#),
+            ),
+            ("a".to_owned(), parse(r#"{ not: { ref: "b" } }"#)),
+            ("b".to_owned(), parse(r#"{ ref: "a" }"#)),
+        ]);
+
+        let mut expr = parse(r#"{ and: [{ ref: "code" }, { not: "generated_marker" }] }"#);
+        resolve_refs(&mut expr, &definitions).unwrap();
+        assert_eq!(
+            serde_json::to_string(&expr).unwrap(),
+            r#"{"and":[{"or":[{"glob":"**/*.rs"},{"glob":"*.c"}]},{"not":"generated_marker"}]}"#
+        );
+
+        let err = resolve_refs(&mut parse(r#"{ ref: "a" }"#), &definitions).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Definition 'a' refers to itself: a -> b -> a"
+        );
+        assert!(resolve_refs(&mut parse(r#"{ ref: "c" }"#), &definitions).is_err());
+    }
+
+    #[test]
+    fn test_max_file_size() {
+        let file = |path: &str, size| FileInfo { size, ..file(path) };
+        let mut files = vec![file("small.js", 100), file("huge.min.js", 101)];
+        retain_files_up_to_size(&mut files, 100);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path.to_str(), Some("small.js"));
+    }
+
+    #[test]
+    fn test_platform() {
+        let file = file("build.sh");
+        let parse = |json: &str| serde_json5::from_str::<MatchExpression>(json).unwrap();
+
+        let host = if cfg!(windows) { "windows" } else { "unix" };
+        let other = if cfg!(windows) { "unix" } else { "windows" };
+
+        let expr = parse(&format!(
+            r#"{{ platform: {{ {host}: {{ glob: "*.sh" }}, {other}: {{ glob: "*.bat" }} }} }}"#
+        ));
+        assert!(file_matches(&file, &expr));
+        let expr = parse(&format!(
+            r#"{{ platform: {{ {other}: {{ glob: "*.sh" }}, any: {{ glob: "*.bat" }} }} }}"#
+        ));
+        assert!(!file_matches(&file, &expr));
+        let expr = parse(r#"{ platform: { any: { glob: "*.sh" } } }"#);
+        assert!(file_matches(&file, &expr));
+        let expr = parse(&format!(
+            r#"{{ platform: {{ {other}: {{ bool: true }} }} }}"#
+        ));
+        assert!(!file_matches(&file, &expr));
+        assert!(
+            serde_json5::from_str::<MatchExpression>(r#"{ platform: { macos: { bool: true } } }"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_includes_symlinks() {
+        let symlink = || MatchExpression::Type(FileType::Symlink);
+        let glob = || MatchExpression::Glob(glob::Pattern::new("*.rs").unwrap());
+
+        assert!(!includes_symlinks(&MatchExpression::Bool(true)));
+        assert!(!includes_symlinks(&glob()));
+        assert!(includes_symlinks(&symlink()));
+        assert!(includes_symlinks(&MatchExpression::And(vec![
+            glob(),
+            MatchExpression::Or(vec![MatchExpression::Type(FileType::Text), symlink()]),
+        ])));
+        assert!(!includes_symlinks(&MatchExpression::Not(Box::new(
+            symlink()
+        ))));
+    }
 }
@@ -1,4 +1,6 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
     io::BufRead as _,
     path::{Path, PathBuf},
     process::Command,
@@ -6,13 +8,23 @@ use std::{
 
 use anyhow::{Context as _, Result, anyhow, bail};
 use itertools::Itertools as _;
-use serde::Deserialize;
+use rayon::iter::{IntoParallelIterator as _, ParallelIterator as _};
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+
+use crate::{profile, unique_filename::unique_filename};
 
 pub fn git_top_level() -> Result<PathBuf> {
     let output = std::process::Command::new("git")
-        .args(&["rev-parse", "--show-toplevel"])
+        .args(["rev-parse", "--show-toplevel"])
         .output()
         .context("Failed to run git rev-parse --show-toplevel")?;
+    if !output.status.success() {
+        bail!(
+            "Not in a Git repository: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
     let path = std::str::from_utf8(&output.stdout)
         .with_context(|| anyhow!("Path is not UTF-8: {:?}", output.stdout))?;
     Ok(PathBuf::from(path.trim()))
@@ -20,7 +32,7 @@ pub fn git_top_level() -> Result<PathBuf> {
 
 pub fn git_hooks_dir() -> Result<PathBuf> {
     let output = std::process::Command::new("git")
-        .args(&["rev-parse", "--git-path", "hooks"])
+        .args(["rev-parse", "--git-path", "hooks"])
         .output()
         .context("Failed to run git rev-parse --git-path hooks")?;
     let path = std::str::from_utf8(&output.stdout)
@@ -28,7 +40,87 @@ pub fn git_hooks_dir() -> Result<PathBuf> {
     Ok(PathBuf::from(path.trim()))
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+/// Get the path of a file in the `.git` directory, e.g. `hooks`. This
+/// handles worktrees and `$GIT_DIR`.
+pub fn git_path(top_level: &Path, name: &str) -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", name])
+        .current_dir(top_level)
+        .output()
+        .with_context(|| anyhow!("Failed to run git rev-parse --git-path {name}"))?;
+    if !output.status.success() {
+        bail!(
+            "git rev-parse --git-path {name} failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let path = std::str::from_utf8(&output.stdout)
+        .with_context(|| anyhow!("Path is not UTF-8: {:?}", output.stdout))?;
+    // This is relative to the current directory unless it's absolute.
+    Ok(top_level.join(path.trim()))
+}
+
+/// Get a value from the Git config, or `None` if it isn't set.
+pub fn git_config_get(top_level: &Path, key: &str) -> Result<Option<String>> {
+    let output = Command::new("git")
+        .args(["config", "--get", key])
+        .current_dir(top_level)
+        .output()
+        .with_context(|| anyhow!("Failed to run git config --get {key}"))?;
+    // Exit code 1 means the key isn't set.
+    match output.status.code() {
+        Some(0) => Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_owned(),
+        )),
+        Some(1) => Ok(None),
+        _ => bail!(
+            "git config --get {key} failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        ),
+    }
+}
+
+/// Set a value in the repo's local Git config, or unset it if `value` is
+/// `None`. Unsetting a key that isn't set is fine.
+pub fn git_config_set(top_level: &Path, key: &str, value: Option<&str>) -> Result<()> {
+    let mut command = Command::new("git");
+    command.args(["config", "--local"]);
+    match value {
+        Some(value) => command.args([key, value]),
+        None => command.args(["--unset", key]),
+    };
+    let output = command
+        .current_dir(top_level)
+        .output()
+        .with_context(|| anyhow!("Failed to run git config {key}"))?;
+    // Exit code 5 means we tried to unset a key that isn't set.
+    let not_set = value.is_none() && output.status.code() == Some(5);
+    if !output.status.success() && !not_set {
+        bail!(
+            "git config {key} failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+/// Get the name of the current branch, or `None` in detached HEAD state.
+pub fn git_current_branch(top_level: &Path) -> Result<Option<String>> {
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git rev-parse --abbrev-ref HEAD")?;
+    if !output.status.success() {
+        bail!("git rev-parse --abbrev-ref HEAD failed");
+    }
+    let branch = std::str::from_utf8(&output.stdout)
+        .with_context(|| anyhow!("Branch name is not UTF-8: {:?}", output.stdout))?
+        .trim();
+    Ok((branch != "HEAD").then(|| branch.to_owned()))
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum FileType {
     Symlink,
@@ -40,13 +132,55 @@ pub enum FileType {
     Text,
     /// Binary file not marked as executable in Git.
     Binary,
+    /// Zero-length file, whether or not it's marked as executable. These
+    /// don't match `text` so most linters skip them.
+    Empty,
 }
 
-#[derive(Eq, PartialEq, Ord, PartialOrd)]
+/// Text encoding of a file, detected from its BOM or contents.
+#[derive(Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+pub enum Encoding {
+    /// UTF-8 (including ASCII), with or without a BOM.
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// UTF-16 little endian, with a BOM.
+    #[serde(rename = "utf-16le")]
+    Utf16Le,
+    /// UTF-16 big endian, with a BOM.
+    #[serde(rename = "utf-16be")]
+    Utf16Be,
+    /// Anything else, e.g. Latin-1 or binary data.
+    #[serde(rename = "other")]
+    Other,
+}
+
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone)]
 pub struct FileInfo {
     pub path: PathBuf,
     pub ty: FileType,
     pub shebang: Option<String>,
+    pub encoding: Encoding,
+    /// Whether the file matched one of the generated file markers.
+    pub generated: bool,
+    /// Size in bytes, according to Git.
+    pub size: u64,
+    /// Git's object ID for the contents, if the file on disk is known to
+    /// match it (e.g. it doesn't have unstaged changes).
+    pub object_id: Option<String>,
+}
+
+/// A UTF-8 text file at `path`, for tests.
+#[cfg(test)]
+pub fn text_file(path: &str) -> FileInfo {
+    FileInfo {
+        path: path.into(),
+        ty: FileType::Text,
+        shebang: None,
+        encoding: Encoding::Utf8,
+        generated: false,
+        size: 0,
+        object_id: None,
+    }
 }
 
 #[derive(Eq, PartialEq)]
@@ -58,36 +192,329 @@ enum GitFileType {
 
 /// Get info on all of the files in a tree (i.e. a commit). This doesn't work
 /// for the index or working directory.
-pub fn git_tree_files(top_level: &Path, treeish: &str) -> Result<Vec<FileInfo>> {
+pub fn git_tree_files(
+    top_level: &Path,
+    treeish: &str,
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
     // pre-commit uses git ls-files to get the list of all files.
     // It uses git diff --names-only for changed files but I'm not sure exactly how it gets the from/to refs if you don't specify them.
+    let listing = git_ls_tree(top_level, treeish, None)?;
+    let modified = git_modified_paths(top_level, Some(treeish))?;
+    process_file_info(top_level, &listing, &modified, generated_markers)
+}
+
+/// List the files in a tree, in the same format as `git_ls_files_cached()`.
+fn git_ls_tree(top_level: &Path, treeish: &str, paths: Option<&[PathBuf]>) -> Result<Vec<u8>> {
+    let _span = profile::span("list files");
+    let batches = match paths {
+        Some(paths) => pathspec_batches(paths),
+        None => vec![&[][..]],
+    };
+    let mut stdout = Vec::new();
+    for batch in batches {
+        let command = Command::new("git")
+            // Don't interpret `*` etc. in the paths.
+            .arg("--literal-pathspecs")
+            .arg("ls-tree")
+            // Recursive.
+            .arg("-r")
+            // Null terminated lines.
+            .arg("-z")
+            // Show all files (not just in the CWD), and show paths relative to
+            // the top level (instead of the CWD). Doesn't really matter since
+            // we set the CWD to the top level, but belt an braces.
+            .arg("--full-tree")
+            .arg("--format=%(objectmode)%x00%(objectname)%x00%(objectsize)%x00%(path)")
+            .arg(treeish)
+            .arg("--")
+            .args(batch)
+            // Set the working directory to the root anyway just in case.
+            .current_dir(top_level)
+            .output()
+            .context("Failed to run git ls-tree")?;
+
+        if !command.status.success() {
+            bail!("git ls-tree command failed");
+        }
+        stdout.extend(command.stdout);
+    }
+
+    Ok(stdout)
+}
+
+/// Get info on all of the staged files.
+pub fn git_staged_files(top_level: &Path, generated_markers: &RegexSet) -> Result<Vec<FileInfo>> {
+    process_file_info(
+        top_level,
+        &git_ls_files_cached(top_level)?,
+        &git_modified_paths(top_level, None)?,
+        generated_markers,
+    )
+}
+
+/// Paths of the files in the working directory whose contents may differ
+/// from `treeish`, or the index if it's `None`, so their object IDs can't be
+/// used. Files whose stat data changed may be included even if their
+/// contents didn't.
+fn git_modified_paths(top_level: &Path, treeish: Option<&str>) -> Result<BTreeSet<PathBuf>> {
+    let mut command = Command::new("git");
+    match treeish {
+        Some(treeish) => command.args(["diff-index", "--name-only", "-z", treeish]),
+        None => command.args(["diff-files", "--name-only", "-z"]),
+    };
+    let output = command
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git diff")?;
+    if !output.status.success() {
+        bail!(
+            "git diff command failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(parse_paths(&output.stdout)?.into_iter().collect())
+}
+
+/// Check out the index into `dest` and get info on the checked out files. This
+/// lets linters see exactly what is staged, regardless of unstaged changes.
+/// `dest` should be an empty directory outside the repo.
+pub fn git_checkout_index(
+    top_level: &Path,
+    dest: &Path,
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    // The prefix is prepended to paths as a string so it needs a trailing slash.
+    let mut prefix = OsString::from("--prefix=");
+    prefix.push(dest);
+    prefix.push("/");
 
     let command = Command::new("git")
-        .arg("ls-tree")
-        // Recursive.
-        .arg("-r")
-        // Null terminated lines.
-        .arg("-z")
-        // Show all files (not just in the CWD), and show paths relative to
-        // the top level (instead of the CWD). Doesn't really matter since
-        // we set the CWD to the top level, but belt an braces.
-        .arg("--full-tree")
-        .arg("--format=%(objectmode)%x00%(objectname)%x00%(objectsize)%x00%(path)")
-        .arg(treeish)
-        // Set the working directory to the root anyway just in case.
+        .arg("checkout-index")
+        .arg("--all")
+        .arg(prefix)
         .current_dir(top_level)
         .output()
-        .context("Failed to run git ls-tree")?;
+        .context("Failed to run git checkout-index")?;
 
     if !command.status.success() {
-        bail!("git ls-tree command failed");
+        bail!(
+            "git checkout-index command failed {}",
+            String::from_utf8_lossy(&command.stderr)
+        );
     }
 
-    process_file_info(top_level, &command.stdout)
+    process_file_info(
+        dest,
+        &git_ls_files_cached(top_level)?,
+        &BTreeSet::new(),
+        generated_markers,
+    )
 }
 
-/// Get info on all of the staged files.
-pub fn git_staged_files(top_level: &Path) -> Result<Vec<FileInfo>> {
+/// Check out `paths` (relative to `top_level`) from `commit` into `dest` and
+/// get info on them, like `git_checkout_index()`. A temporary index is used
+/// so the real one isn't affected.
+pub fn git_checkout_commit(
+    top_level: &Path,
+    commit: &str,
+    paths: &[PathBuf],
+    dest: &Path,
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    // No paths would mean all files.
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let index_file = std::env::temp_dir().join(unique_filename("nit-index-", ""));
+    let result = (|| {
+        let status = Command::new("git")
+            .arg("read-tree")
+            .arg(commit)
+            .env("GIT_INDEX_FILE", &index_file)
+            .current_dir(top_level)
+            .status()
+            .context("Failed to run git read-tree")?;
+        if !status.success() {
+            bail!("git read-tree {commit} failed");
+        }
+
+        let mut prefix = OsString::from("--prefix=");
+        prefix.push(dest);
+        prefix.push("/");
+        for batch in pathspec_batches(paths) {
+            let command = Command::new("git")
+                .arg("checkout-index")
+                .arg(&prefix)
+                .arg("--")
+                .args(batch)
+                .env("GIT_INDEX_FILE", &index_file)
+                .current_dir(top_level)
+                .output()
+                .context("Failed to run git checkout-index")?;
+            if !command.status.success() {
+                bail!(
+                    "git checkout-index command failed {}",
+                    String::from_utf8_lossy(&command.stderr)
+                );
+            }
+        }
+        Ok(())
+    })();
+    let _ = std::fs::remove_file(&index_file);
+    result?;
+
+    process_file_info(
+        dest,
+        &git_ls_tree(top_level, commit, Some(paths))?,
+        &BTreeSet::new(),
+        generated_markers,
+    )
+}
+
+/// Stage the contents of `file` from `contents_dir` (e.g. a directory created
+/// by `git_checkout_index()`), without touching the working directory. If the
+/// file doesn't exist it is removed from the index. `staged_mode` is its
+/// current mode in the index (see `git_staged_modes()`), if it has one.
+pub fn git_update_index(
+    top_level: &Path,
+    contents_dir: &Path,
+    file: &FileInfo,
+    staged_mode: Option<u32>,
+) -> Result<()> {
+    let full_path = contents_dir.join(&file.path);
+
+    if !full_path.exists() {
+        let status = Command::new("git")
+            .arg("update-index")
+            .arg("--force-remove")
+            .arg("--")
+            .arg(&file.path)
+            .current_dir(top_level)
+            .status()
+            .context("Failed to run git update-index")?;
+        if !status.success() {
+            bail!("git update-index command failed");
+        }
+        return Ok(());
+    }
+
+    let output = Command::new("git")
+        .arg("hash-object")
+        .arg("-w")
+        // Apply the same filters (e.g. line endings) as `git add` would.
+        .arg("--path")
+        .arg(&file.path)
+        .arg(&full_path)
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git hash-object")?;
+    if !output.status.success() {
+        bail!(
+            "git hash-object command failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let hash = std::str::from_utf8(&output.stdout)
+        .context("git hash-object output is not UTF-8")?
+        .trim();
+
+    // Keep the staged mode so the executable bit is preserved even on
+    // filesystems that don't have one (e.g. on Windows).
+    let mode = match staged_mode {
+        Some(mode) => format!("{mode:o}"),
+        None => match file.ty {
+            FileType::ExecutableText | FileType::ExecutableBinary => "100755",
+            FileType::Symlink => "120000",
+            FileType::Text | FileType::Binary | FileType::Empty => "100644",
+        }
+        .to_owned(),
+    };
+
+    let status = Command::new("git")
+        .arg("update-index")
+        .arg("--cacheinfo")
+        .arg(format!("{mode},{hash},{}", path_str(&file.path)?))
+        .current_dir(top_level)
+        .status()
+        .context("Failed to run git update-index")?;
+    if !status.success() {
+        bail!("git update-index command failed");
+    }
+    Ok(())
+}
+
+/// Get the modes of `paths` in the index, e.g. `0o100755` for executable
+/// files. Paths that aren't staged are left out. Git tracks the executable
+/// bit itself, so unlike filesystem permissions this works on Windows.
+pub fn git_staged_modes<P: AsRef<Path>>(
+    top_level: &Path,
+    paths: &[P],
+) -> Result<BTreeMap<PathBuf, u32>> {
+    let mut modes = BTreeMap::new();
+    for batch in pathspec_batches(paths) {
+        let output = Command::new("git")
+            .arg("--literal-pathspecs")
+            .arg("ls-files")
+            .arg("--stage")
+            .arg("-z")
+            .arg("--full-name")
+            .arg("--")
+            .args(batch.iter().map(AsRef::as_ref))
+            .current_dir(top_level)
+            .output()
+            .context("Failed to run git ls-files")?;
+        if !output.status.success() {
+            bail!(
+                "git ls-files command failed {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        // <mode> SP <hash> SP <stage> TAB <path> NUL
+        for entry in output.stdout.split(|&b| b == 0).filter(|e| !e.is_empty()) {
+            let entry = std::str::from_utf8(entry).context("git ls-files output is not UTF-8")?;
+            let (Some((mode, _)), Some((_, path))) =
+                (entry.split_once(' '), entry.split_once('\t'))
+            else {
+                bail!("Invalid entry from git ls-files: {entry:?}");
+            };
+            let mode = u32::from_str_radix(mode, 8)
+                .with_context(|| anyhow!("Invalid mode from git ls-files: {mode:?}"))?;
+            modes.insert(PathBuf::from(path), mode);
+        }
+    }
+    Ok(modes)
+}
+
+/// Mark `paths` executable in the index with `git update-index --chmod=+x`,
+/// which works on Windows too. The working tree isn't changed. Paths that
+/// aren't staged are ignored.
+pub fn git_set_executable_bit<P: AsRef<Path>>(top_level: &Path, paths: &[P]) -> Result<()> {
+    let staged: Vec<PathBuf> = git_staged_modes(top_level, paths)?.into_keys().collect();
+    for batch in pathspec_batches(&staged) {
+        let status = Command::new("git")
+            .arg("update-index")
+            .arg("--chmod=+x")
+            .arg("--")
+            .args(batch)
+            .current_dir(top_level)
+            .status()
+            .context("Failed to run git update-index")?;
+        if !status.success() {
+            bail!("git update-index command failed");
+        }
+    }
+    Ok(())
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("Couldn't convert path to UTF-8: {:?}", path))
+}
+
+/// Run `git ls-files` on the index, in the format `process_file_info()` expects.
+fn git_ls_files_cached(top_level: &Path) -> Result<Vec<u8>> {
+    let _span = profile::span("list files");
     let command = Command::new("git")
         .arg("ls-files")
         // Show staged files (technically the default option but let's be explicit).
@@ -106,98 +533,756 @@ pub fn git_staged_files(top_level: &Path) -> Result<Vec<FileInfo>> {
         bail!("git ls-files command failed");
     }
 
-    process_file_info(top_level, &command.stdout)
+    Ok(command.stdout)
 }
 
-/// List of files changed in the working directory (not staged).
-pub fn git_diff_unstaged(top_level: &Path) -> Result<Vec<u8>> {
-    let output = std::process::Command::new("git")
-        .args(&[
-            "diff",
-            "--no-ext-diff",
-            "--no-textconv",
-            "--ignore-submodules",
-        ])
+/// Get info on specific staged files. `paths` must be absolute or relative to
+/// `top_level`, and can be directories. Paths that aren't in the index are
+/// ignored.
+pub fn git_files(
+    top_level: &Path,
+    paths: &[PathBuf],
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    let listing = profile::span("list files");
+    let mut stdout = Vec::new();
+    for batch in pathspec_batches(paths) {
+        let command = Command::new("git")
+            // Don't interpret `*` etc. in the paths.
+            .arg("--literal-pathspecs")
+            .arg("ls-files")
+            .arg("--cached")
+            // Null terminated lines.
+            .arg("-z")
+            // Show paths relative to top level.
+            .arg("--full-name")
+            .arg("--format=%(objectmode)%x00%(objectname)%x00%(objectsize)%x00%(path)")
+            .arg("--")
+            .args(batch)
+            // Set the working directory to the root anyway just in case.
+            .current_dir(top_level)
+            .output()
+            .context("Failed to run git ls-files")?;
+
+        if !command.status.success() {
+            bail!(
+                "git ls-files command failed {}",
+                String::from_utf8_lossy(&command.stderr)
+            );
+        }
+        stdout.extend_from_slice(&command.stdout);
+    }
+    drop(listing);
+
+    let modified = git_modified_paths(top_level, None)?;
+    let mut files = process_file_info(top_level, &stdout, &modified, generated_markers)?;
+    // Directories in different batches can overlap.
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files.dedup_by(|a, b| a.path == b.path);
+    Ok(files)
+}
+
+/// Maximum total length of the paths passed to one Git command. Windows
+/// limits the whole command line to 32K characters, and Linux limits the
+/// total size of the arguments.
+const MAX_PATHSPEC_BYTES: usize = 16 * 1024;
+
+/// Split `paths` into batches that are short enough to pass to Git as
+/// arguments.
+fn pathspec_batches<P: AsRef<Path>>(paths: &[P]) -> Vec<&[P]> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut bytes = 0;
+    for (i, path) in paths.iter().enumerate() {
+        // Plus a separator.
+        let len = path.as_ref().as_os_str().len() + 1;
+        if i > start && bytes + len > MAX_PATHSPEC_BYTES {
+            batches.push(&paths[start..i]);
+            start = i;
+            bytes = 0;
+        }
+        bytes += len;
+    }
+    if start < paths.len() {
+        batches.push(&paths[start..]);
+    }
+    batches
+}
+
+/// Get info on the staged versions of the files that `commit` added or
+/// modified. Files that were deleted by the commit or aren't in the index
+/// any more are ignored.
+pub fn git_commit_files(
+    top_level: &Path,
+    commit: &str,
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    let paths = git_commit_paths(top_level, commit)?;
+    git_paths_files(top_level, &paths, generated_markers)
+}
+
+/// Get info on the staged versions of `paths` (relative to `top_level`).
+fn git_paths_files(
+    top_level: &Path,
+    paths: &[PathBuf],
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    // No paths would mean all files.
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let paths: Vec<PathBuf> = paths.iter().map(|path| top_level.join(path)).collect();
+    git_files(top_level, &paths, generated_markers)
+}
+
+/// The files that the last merge changed.
+#[derive(Debug, PartialEq, Eq)]
+enum Merge {
+    /// A merge is in progress (e.g. it stopped for conflicts), so the
+    /// result is only in the working tree.
+    InProgress(Vec<PathBuf>),
+    /// The merge was committed (or fast-forwarded).
+    Done(Vec<PathBuf>),
+}
+
+/// Find the paths that the current or last merge (or pull) added or
+/// modified, relative to `top_level`. Returns `None` if there isn't one.
+fn git_last_merge(top_level: &Path) -> Result<Option<Merge>> {
+    // Three dots, so this is what the other branch brings in since the
+    // merge base.
+    if git_commit_exists(top_level, "MERGE_HEAD")? {
+        let paths = git_changed_paths(top_level, Some("HEAD"), "MERGE_HEAD")?;
+        return Ok(Some(Merge::InProgress(paths)));
+    }
+    if git_commit_exists(top_level, "HEAD^2")? {
+        return git_commit_paths(top_level, "HEAD").map(|paths| Some(Merge::Done(paths)));
+    }
+    // A fast-forward. `ORIG_HEAD` is also set by e.g. `reset` and `rebase`,
+    // which leave it somewhere other than an ancestor of `HEAD`.
+    if git_commit_exists(top_level, "ORIG_HEAD")?
+        && git_is_ancestor(top_level, "ORIG_HEAD", "HEAD")?
+    {
+        let paths = git_changed_paths(top_level, Some("ORIG_HEAD"), "HEAD")?;
+        return Ok(Some(Merge::Done(paths)));
+    }
+    Ok(None)
+}
+
+/// Get info on the files that the current or last merge (or pull) added or
+/// modified. During a merge (e.g. one that stopped for conflicts) these are
+/// the working tree versions of the files that the other branch changed,
+/// otherwise the staged versions of the files the merge commit (or
+/// fast-forward) changed. Returns `None` if there is no merge to lint.
+pub fn git_merge_files(
+    top_level: &Path,
+    generated_markers: &RegexSet,
+) -> Result<Option<Vec<FileInfo>>> {
+    match git_last_merge(top_level)? {
+        Some(Merge::InProgress(paths)) => {
+            // Deleted while resolving a conflict.
+            let paths: Vec<PathBuf> = paths
+                .into_iter()
+                .filter(|path| top_level.join(path).symlink_metadata().is_ok())
+                .collect();
+            if paths.is_empty() {
+                return Ok(Some(Vec::new()));
+            }
+            working_tree_files(top_level, &paths, generated_markers).map(Some)
+        }
+        Some(Merge::Done(paths)) => git_paths_files(top_level, &paths, generated_markers).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Returns true if `ancestor` is an ancestor of (or the same as) `commit`.
+fn git_is_ancestor(top_level: &Path, ancestor: &str, commit: &str) -> Result<bool> {
+    let status = Command::new("git")
+        .args(["merge-base", "--is-ancestor", ancestor, commit])
         .current_dir(top_level)
-        .output()?;
-    if !output.status.success() {
-        bail!("git diff command failed");
+        .status()
+        .context("Failed to run git merge-base")?;
+    match status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => bail!("git merge-base --is-ancestor {ancestor} {commit} failed"),
     }
-    Ok(output.stdout)
 }
 
-fn process_file_info(top_level: &Path, ls_files_stdout: &[u8]) -> Result<Vec<FileInfo>> {
-    ls_files_stdout
+/// Get info on the files that the last commit added or modified. See
+/// `git_commit_paths()`.
+pub fn git_last_commit_files(
+    top_level: &Path,
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    git_commit_files(top_level, "HEAD", generated_markers)
+}
+
+/// Parse the null terminated paths output by e.g. `git diff --name-only -z`.
+fn parse_paths(stdout: &[u8]) -> Result<Vec<PathBuf>> {
+    stdout
         .split(|&b| b == 0)
-        .tuples()
-        .map(|(mode, _hash, _size, path)| {
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            std::str::from_utf8(path)
+                .map(PathBuf::from)
+                .with_context(|| anyhow!("Failed to parse path"))
+        })
+        .collect()
+}
+
+/// Get the paths of the files that `commit` added or modified, relative to
+/// `top_level`, i.e. those that differ from its parent (the first parent for
+/// merge commits, so this is what the merge brought in). For the root commit
+/// this is all of its files. Fails if the parent is missing because this is
+/// a shallow clone, rather than treating every file as changed.
+pub fn git_commit_paths(top_level: &Path, commit: &str) -> Result<Vec<PathBuf>> {
+    let parent = format!("{commit}^");
+    if git_commit_exists(top_level, &parent)? {
+        return git_changed_paths(top_level, Some(&parent), commit);
+    }
+    if git_is_shallow_boundary(top_level, commit)? {
+        bail!(
+            "Can't tell which files {commit} changed because its parent isn't in this shallow clone. Fetch it with e.g. `git fetch --deepen=1`."
+        );
+    }
+    git_changed_paths(top_level, None, commit)
+}
+
+/// Returns true if the history of this shallow clone is cut off at `commit`,
+/// so its parents are missing.
+fn git_is_shallow_boundary(top_level: &Path, commit: &str) -> Result<bool> {
+    let shallow = git_path(top_level, "shallow")?;
+    let boundaries = match std::fs::read_to_string(&shallow) {
+        Ok(boundaries) => boundaries,
+        // Not a shallow clone.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => {
+            return Err(e).with_context(|| anyhow!("Failed to read {}", shallow.display()));
+        }
+    };
+    let hash = git_rev_parse(top_level, &format!("{commit}^{{commit}}"))?;
+    Ok(boundaries.lines().any(|line| line.trim() == hash))
+}
+
+/// Get info on the untracked files in the working directory that aren't
+/// ignored, i.e. new files that might be committed.
+pub fn git_untracked_files(
+    top_level: &Path,
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    let output = Command::new("git")
+        .arg("ls-files")
+        .arg("--others")
+        .arg("--exclude-standard")
+        .arg("-z")
+        .arg("--full-name")
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git ls-files")?;
+    if !output.status.success() {
+        bail!(
+            "git ls-files command failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let paths = parse_paths(&output.stdout)?;
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    working_tree_files(top_level, &paths, generated_markers)
+}
+
+/// Get info on specific files in the working directory, whether or not Git
+/// tracks them. This is for linting files that were explicitly requested,
+/// so ignored and untracked files are included. `paths` must be absolute or
+/// relative to `top_level`. Directories are expanded to the tracked and
+/// untracked (but not ignored) files in them.
+pub fn working_tree_files(
+    top_level: &Path,
+    paths: &[PathBuf],
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    explicit_files(top_level, paths, generated_markers, true)
+}
+
+/// Like `working_tree_files()` but without using Git, for linting files
+/// outside a repo. `root` is used instead of the repo root, and directories
+/// are expanded to all of the files in them. File types come from the
+/// filesystem, so nothing is executable on Windows.
+pub fn files_outside_repo(
+    root: &Path,
+    paths: &[PathBuf],
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    explicit_files(root, paths, generated_markers, false)
+}
+
+/// Add the paths of the files in `dir` (relative to `root`, with `/`
+/// separators) to `files`, recursively. Symlinks aren't followed.
+fn walk_dir(root: &Path, dir: &str, files: &mut Vec<String>) -> Result<()> {
+    let full_dir = root.join(dir);
+    let entries = std::fs::read_dir(&full_dir)
+        .with_context(|| anyhow!("Failed to read {}", full_dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| anyhow!("Failed to read {}", full_dir.display()))?;
+        let name = entry.file_name();
+        let name = path_str(Path::new(&name))?;
+        let path = if dir.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{dir}/{name}")
+        };
+        if entry.file_type()?.is_dir() {
+            walk_dir(root, &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn explicit_files(
+    top_level: &Path,
+    paths: &[PathBuf],
+    generated_markers: &RegexSet,
+    git: bool,
+) -> Result<Vec<FileInfo>> {
+    let top_level_canonical = top_level
+        .canonicalize()
+        .with_context(|| anyhow!("Failed to canonicalize {}", top_level.display()))?;
+    // Paths relative to `top_level`, with `/` separators like Git uses.
+    let relative = |path: &Path| -> Result<String> {
+        let full_path = top_level.join(path);
+        let canonical = match (full_path.parent(), full_path.file_name()) {
+            // Don't follow symlinks.
+            (Some(parent), Some(name)) if !full_path.is_dir() => {
+                parent.canonicalize().map(|parent| parent.join(name))
+            }
+            _ => full_path.canonicalize(),
+        }
+        .with_context(|| anyhow!("{} doesn't exist", path.display()))?;
+        let relative = canonical.strip_prefix(&top_level_canonical).map_err(|_| {
+            anyhow!(
+                "{} is outside the {} ({})",
+                path.display(),
+                if git { "repository" } else { "directory" },
+                top_level.display()
+            )
+        })?;
+        relative
+            .components()
+            .map(|component| path_str(Path::new(component.as_os_str())))
+            .collect::<Result<Vec<_>>>()
+            .map(|components| components.join("/"))
+    };
+
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for path in paths {
+        if top_level.join(path).is_dir() {
+            dirs.push(relative(path)?);
+        } else {
+            files.push(relative(path)?);
+        }
+    }
+
+    if !git {
+        for dir in &dirs {
+            walk_dir(top_level, dir, &mut files)?;
+        }
+    } else if !dirs.is_empty() {
+        let output = Command::new("git")
+            .arg("--literal-pathspecs")
+            .arg("ls-files")
+            .arg("--cached")
+            .arg("--others")
+            .arg("--exclude-standard")
+            .arg("-z")
+            .arg("--full-name")
+            .arg("--")
+            .args(
+                dirs.iter()
+                    .map(|dir| if dir.is_empty() { "." } else { dir }),
+            )
+            .current_dir(top_level)
+            .output()
+            .context("Failed to run git ls-files")?;
+        if !output.status.success() {
+            bail!(
+                "git ls-files command failed {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        for path in parse_paths(&output.stdout)? {
+            // Deleted files are still listed if they are in the index.
+            if top_level.join(&path).symlink_metadata().is_ok() {
+                files.push(path_str(&path)?.to_owned());
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    // Use the staged mode where there is one, because the filesystem
+    // doesn't have an executable bit on Windows.
+    let staged_modes = if git {
+        git_staged_modes(top_level, &files)?
+    } else {
+        BTreeMap::new()
+    };
+
+    files
+        .into_par_iter()
+        .map(|path| {
+            let full_path = top_level.join(&path);
+            let metadata = full_path
+                .symlink_metadata()
+                .with_context(|| anyhow!("Failed to read metadata of {path}"))?;
+            if metadata.is_symlink() {
+                return Ok(FileInfo {
+                    path: path.into(),
+                    ty: FileType::Symlink,
+                    shebang: None,
+                    encoding: Encoding::Other,
+                    generated: false,
+                    size: metadata.len(),
+                    object_id: None,
+                });
+            }
+            let executable = match staged_modes.get(Path::new(&path)) {
+                Some(&mode) => mode == 0o100755,
+                None => is_executable(&metadata),
+            };
+            let mut file = std::fs::File::open(&full_path)
+                .with_context(|| anyhow!("Failed to open {path}"))?;
+            let mut buf = [0; 8000];
+            let len = read_up_to(&mut file, &mut buf)?;
+            Ok(classify_contents(
+                path.into(),
+                &buf[..len],
+                executable,
+                metadata.len(),
+                generated_markers,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt as _;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+/// Get the full hash of a revision, e.g. `HEAD`.
+pub fn git_rev_parse(top_level: &Path, rev: &str) -> Result<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--verify"])
+        .arg(rev)
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git rev-parse")?;
+    if !output.status.success() {
+        bail!(
+            "git rev-parse --verify {rev} failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Returns true if `commit` exists in the local repo.
+pub fn git_commit_exists(top_level: &Path, commit: &str) -> Result<bool> {
+    let status = Command::new("git")
+        .args(["cat-file", "-e"])
+        .arg(format!("{commit}^{{commit}}"))
+        .current_dir(top_level)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .context("Failed to run git cat-file")?;
+    Ok(status.success())
+}
+
+/// Get the commits in `range` (e.g. `origin/main..HEAD`), oldest first.
+pub fn git_rev_list(top_level: &Path, range: &str) -> Result<Vec<String>> {
+    let output = Command::new("git")
+        .args(["rev-list", "--topo-order", "--reverse"])
+        .arg(range)
+        .arg("--")
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git rev-list")?;
+    if !output.status.success() {
+        bail!(
+            "git rev-list {range} failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+/// Get the commits reachable from `commit` that aren't on any of `remote`'s
+/// remote-tracking branches, oldest first. If `remote` is `None` all
+/// remote-tracking branches are used.
+pub fn git_commits_not_on_remote(
+    top_level: &Path,
+    commit: &str,
+    remote: Option<&str>,
+) -> Result<Vec<String>> {
+    let output = Command::new("git")
+        .args(["rev-list", "--topo-order", "--reverse"])
+        .arg(commit)
+        .arg("--not")
+        .arg(match remote {
+            Some(remote) => format!("--remotes={remote}"),
+            None => "--remotes".to_owned(),
+        })
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git rev-list")?;
+    if !output.status.success() {
+        bail!(
+            "git rev-list command failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+/// Get the names and URLs of the configured remotes.
+pub fn git_remotes(top_level: &Path) -> Result<Vec<(String, String)>> {
+    let output = Command::new("git")
+        .args(["config", "-z", "--get-regexp", r"^remote\..*\.url$"])
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git config")?;
+    // Exit code 1 means there are no remotes.
+    if output.status.code() == Some(1) {
+        return Ok(Vec::new());
+    }
+    if !output.status.success() {
+        bail!(
+            "git config command failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    // <key> LF <value> NUL
+    let output = String::from_utf8_lossy(&output.stdout);
+    Ok(output
+        .split('\0')
+        .filter_map(|entry| {
+            let (key, url) = entry.split_once('\n')?;
+            let name = key.strip_prefix("remote.")?.strip_suffix(".url")?;
+            Some((name.to_owned(), url.to_owned()))
+        })
+        .collect())
+}
+
+/// Get the paths of files that were added or modified between `from` and
+/// `to`. If `from` is `None` all files in `to` are returned.
+pub fn git_changed_paths(top_level: &Path, from: Option<&str>, to: &str) -> Result<Vec<PathBuf>> {
+    let mut command = Command::new("git");
+    match from {
+        Some(from) => command
+            .args([
+                "diff",
+                "--name-only",
+                "-z",
+                "--no-renames",
+                "--diff-filter=d",
+            ])
+            // Three dots compares to the merge base, in case `from` isn't
+            // an ancestor (e.g. when force pushing).
+            .arg(format!("{from}...{to}")),
+        None => command
+            .args(["ls-tree", "-r", "-z", "--name-only", "--full-tree"])
+            .arg(to),
+    };
+    let output = command
+        .current_dir(top_level)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git command failed {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    parse_paths(&output.stdout)
+}
+
+/// Diff of the unstaged changes to `paths` (relative to `top_level`), so
+/// changes to other files don't affect it.
+pub fn git_diff_unstaged(top_level: &Path, paths: &[&Path]) -> Result<Vec<u8>> {
+    let _span = profile::span("git diff");
+    // With no paths `git diff` would diff everything, so this must not run
+    // it when `paths` is empty.
+    let mut diff = Vec::new();
+    for batch in pathspec_batches(paths) {
+        let output = std::process::Command::new("git")
+            .arg("--literal-pathspecs")
+            .args([
+                "diff",
+                "--no-ext-diff",
+                "--no-textconv",
+                "--ignore-submodules",
+                "--",
+            ])
+            .args(batch)
+            .current_dir(top_level)
+            .output()?;
+        if !output.status.success() {
+            bail!("git diff command failed");
+        }
+        diff.extend_from_slice(&output.stdout);
+    }
+    Ok(diff)
+}
+
+/// Get info on the files listed by `git ls-files` or `git ls-tree`, which are
+/// in `top_level`. The object IDs of `modified` files aren't used.
+fn process_file_info(
+    top_level: &Path,
+    ls_files_stdout: &[u8],
+    modified: &BTreeSet<PathBuf>,
+    generated_markers: &RegexSet,
+) -> Result<Vec<FileInfo>> {
+    let _span = profile::span("classify files");
+    let entries: Vec<_> = ls_files_stdout.split(|&b| b == 0).tuples().collect();
+
+    // Reading the start of every file dominates the time for large repos, so
+    // do it in parallel. `collect()` preserves the order.
+    entries
+        .into_par_iter()
+        .map(|(mode, hash, size, path)| {
             // mode:   octal permission bits, e.g. 100644.
-            // _hash:  object hash
-            // _size:  size in bytes
+            // hash:   object hash
+            // size:   size in bytes
             // path:   file path
 
             let path = Path::new(
                 std::str::from_utf8(path).with_context(|| anyhow!("Failed to parse path"))?,
             );
+            let size = std::str::from_utf8(size)
+                .ok()
+                .and_then(|size| size.parse().ok())
+                .with_context(|| anyhow!("Failed to parse size of {}", path.display()))?;
+            let object_id =
+                (!modified.contains(path)).then(|| String::from_utf8_lossy(hash).into_owned());
             let git_ty = match mode {
                 b"120000" => GitFileType::Symlink,
                 b"100755" => GitFileType::Executable,
                 _ => GitFileType::File,
             };
 
-            let (ty, shebang) = if git_ty == GitFileType::Symlink {
-                (FileType::Symlink, None)
-            } else {
-                // Read the first 8000 bytes and look for a null byte. This is how
-                // Git decides if it's binary.
-                let full_path = top_level.join(path);
-                let mut file = std::fs::File::open(&full_path)?;
-                let mut buf = [0; 8000];
-                let len = read_up_to(&mut file, &mut buf)?;
-                let contents = &buf[..len];
-
-                let is_binary = memchr::memchr(0, contents).is_some();
-
-                let shebang = (git_ty == GitFileType::Executable)
-                    .then(|| {
-                        let reader = std::io::BufReader::new(contents);
-                        reader.lines().next().and_then(|maybe_first_line| {
-                            maybe_first_line.ok().and_then(|first_line| {
-                                first_line.strip_prefix("#!").map(ToOwned::to_owned)
-                            })
-                        })
-                    })
-                    .flatten();
-
-                let ty = match git_ty {
-                    GitFileType::Executable => {
-                        if is_binary {
-                            FileType::ExecutableBinary
-                        } else {
-                            FileType::ExecutableText
-                        }
-                    }
-                    GitFileType::File => {
-                        if is_binary {
-                            FileType::Binary
-                        } else {
-                            FileType::Text
-                        }
-                    }
-                    _ => unreachable!(),
-                };
-                (ty, shebang)
-            };
+            if git_ty == GitFileType::Symlink {
+                return Ok(FileInfo {
+                    path: path.to_owned(),
+                    ty: FileType::Symlink,
+                    shebang: None,
+                    encoding: Encoding::Other,
+                    generated: false,
+                    size,
+                    object_id,
+                });
+            }
 
+            // Read the first 8000 bytes and look for a null byte. This is how
+            // Git decides if it's binary.
+            let full_path = top_level.join(path);
+            let mut file = std::fs::File::open(&full_path)?;
+            let mut buf = [0; 8000];
+            let len = read_up_to(&mut file, &mut buf)?;
             Ok(FileInfo {
-                path: path.to_owned(),
-                ty,
-                shebang,
+                object_id,
+                ..classify_contents(
+                    path.to_owned(),
+                    &buf[..len],
+                    git_ty == GitFileType::Executable,
+                    size,
+                    generated_markers,
+                )
             })
         })
         .collect::<Result<Vec<_>, _>>()
 }
 
+/// Determine the type, shebang, encoding and whether a (non-symlink) file is
+/// generated from the start of its contents. Only the first 8000 bytes are
+/// needed. `size` is the full size of the file.
+pub fn classify_contents(
+    path: PathBuf,
+    contents: &[u8],
+    executable: bool,
+    size: u64,
+    generated_markers: &RegexSet,
+) -> FileInfo {
+    let contents = &contents[..contents.len().min(8000)];
+
+    let is_binary = memchr::memchr(0, contents).is_some();
+
+    let shebang = executable
+        .then(|| {
+            let reader = std::io::BufReader::new(contents);
+            reader.lines().next().and_then(|maybe_first_line| {
+                maybe_first_line
+                    .ok()
+                    .and_then(|first_line| first_line.strip_prefix("#!").map(ToOwned::to_owned))
+            })
+        })
+        .flatten();
+
+    let ty = match (executable, is_binary) {
+        _ if contents.is_empty() => FileType::Empty,
+        (true, true) => FileType::ExecutableBinary,
+        (true, false) => FileType::ExecutableText,
+        (false, true) => FileType::Binary,
+        (false, false) => FileType::Text,
+    };
+
+    let generated = !is_binary && generated_markers.is_match(&String::from_utf8_lossy(contents));
+
+    FileInfo {
+        path,
+        ty,
+        shebang,
+        encoding: detect_encoding(contents),
+        generated,
+        size,
+        object_id: None,
+    }
+}
+
+/// Detect the encoding of a file from the start of its contents. `contents`
+/// may be truncated.
+fn detect_encoding(contents: &[u8]) -> Encoding {
+    if contents.starts_with(b"\xEF\xBB\xBF") {
+        Encoding::Utf8
+    } else if contents.starts_with(b"\xFF\xFE") {
+        Encoding::Utf16Le
+    } else if contents.starts_with(b"\xFE\xFF") {
+        Encoding::Utf16Be
+    } else {
+        match std::str::from_utf8(contents) {
+            Ok(_) => Encoding::Utf8,
+            // The contents may have been truncated in the middle of a character.
+            Err(e) if e.error_len().is_none() => Encoding::Utf8,
+            Err(_) => Encoding::Other,
+        }
+    }
+}
+
 /// This is the same as read_exact, except if it reaches EOF it doesn't return
 /// an error, and it returns the number of bytes read.
 fn read_up_to(file: &mut impl std::io::Read, mut buf: &mut [u8]) -> Result<usize, std::io::Error> {
@@ -222,6 +1307,428 @@ mod test {
     use super::*;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_detect_encoding() {
+        assert_eq!(detect_encoding(b"hello"), Encoding::Utf8);
+        assert_eq!(detect_encoding("caf\u{e9}".as_bytes()), Encoding::Utf8);
+        // Truncated in the middle of a character.
+        assert_eq!(
+            detect_encoding(&"caf\u{e9}".as_bytes()[..4]),
+            Encoding::Utf8
+        );
+        assert_eq!(detect_encoding(b"\xEF\xBB\xBFhello"), Encoding::Utf8);
+        assert_eq!(detect_encoding(b"\xFF\xFEh\x00"), Encoding::Utf16Le);
+        assert_eq!(detect_encoding(b"\xFE\xFF\x00h"), Encoding::Utf16Be);
+        // Latin-1 é.
+        assert_eq!(detect_encoding(b"caf\xE9!"), Encoding::Other);
+    }
+
+    /// Compare serial and parallel classification on a synthetic tree. Run
+    /// with `cargo test --release -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore]
+    fn bench_process_file_info() {
+        let dir = tempdir().expect("Failed to create temp dir");
+
+        let mut ls_files_stdout = Vec::new();
+        for i in 0..20_000 {
+            let path = format!("dir{}/file{i}.txt", i % 100);
+            let full_path = dir.path().join(&path);
+            std::fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            std::fs::write(&full_path, "Hello, world!\n".repeat(1000)).unwrap();
+            ls_files_stdout.extend_from_slice(b"100644\0hash\0");
+            ls_files_stdout.extend_from_slice(b"14000\0");
+            ls_files_stdout.extend_from_slice(path.as_bytes());
+            ls_files_stdout.push(0);
+        }
+
+        let markers = RegexSet::empty();
+        let time = |num_threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            let start = std::time::Instant::now();
+            let files = pool
+                .install(|| {
+                    process_file_info(dir.path(), &ls_files_stdout, &BTreeSet::new(), &markers)
+                })
+                .unwrap();
+            let elapsed = start.elapsed();
+            assert_eq!(files.len(), 20_000);
+            assert_eq!(files[1].path, Path::new("dir1/file1.txt"));
+            elapsed
+        };
+
+        // Warm the page cache.
+        time(0);
+        let serial = time(1);
+        let parallel = time(0);
+        println!("Serial: {serial:?}, parallel: {parallel:?}");
+    }
+
+    #[test]
+    fn test_staged_modes() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        for name in ["script.sh", "other.sh", "untracked.sh"] {
+            std::fs::write(dir.path().join(name), "#!/bin/sh\n").unwrap();
+        }
+        git(&["add", "script.sh", "other.sh"]);
+
+        let paths = ["script.sh", "other.sh", "untracked.sh", "missing"];
+        let modes = |paths: &[&str]| git_staged_modes(dir.path(), paths).unwrap();
+        assert_eq!(
+            modes(&paths),
+            BTreeMap::from([
+                (PathBuf::from("other.sh"), 0o100644),
+                (PathBuf::from("script.sh"), 0o100644),
+            ])
+        );
+
+        git_set_executable_bit(dir.path(), &["script.sh", "untracked.sh"]).unwrap();
+        assert_eq!(
+            modes(&paths),
+            BTreeMap::from([
+                (PathBuf::from("other.sh"), 0o100644),
+                (PathBuf::from("script.sh"), 0o100755),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_working_tree_files_includes_ignored() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let status = Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::write(dir.path().join(".gitignore"), "ignored.txt\n").unwrap();
+        std::fs::write(dir.path().join("ignored.txt"), "hello\n").unwrap();
+
+        let markers = RegexSet::empty();
+        let files =
+            working_tree_files(dir.path(), &[dir.path().join("ignored.txt")], &markers).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, Path::new("ignored.txt"));
+        assert_eq!(files[0].ty, FileType::Text);
+        assert_eq!(files[0].size, 6);
+
+        // Directories only include files that aren't ignored.
+        let files = working_tree_files(dir.path(), &[dir.path().to_owned()], &markers).unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(paths, [Path::new(".gitignore")]);
+
+        assert!(working_tree_files(dir.path(), &["missing.txt".into()], &markers).is_err());
+    }
+
+    #[test]
+    fn test_files_outside_repo() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        std::fs::create_dir_all(dir.path().join("src/sub")).unwrap();
+        std::fs::write(dir.path().join("src/a.rs"), "a\n").unwrap();
+        std::fs::write(dir.path().join("src/sub/b.rs"), "b\n").unwrap();
+        std::fs::write(dir.path().join("c.bin"), b"\x00").unwrap();
+
+        let markers = RegexSet::empty();
+        let files = files_outside_repo(
+            dir.path(),
+            &[dir.path().join("src"), "c.bin".into()],
+            &markers,
+        )
+        .unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("c.bin"),
+                Path::new("src/a.rs"),
+                Path::new("src/sub/b.rs")
+            ]
+        );
+        assert_eq!(files[0].ty, FileType::Binary);
+        assert_eq!(files[1].ty, FileType::Text);
+
+        let outside = tempdir().expect("Failed to create temp dir");
+        std::fs::write(outside.path().join("d.rs"), "d\n").unwrap();
+        assert!(files_outside_repo(dir.path(), &[outside.path().join("d.rs")], &markers).is_err());
+    }
+
+    #[test]
+    fn test_untracked_files() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=nit", "-c", "user.email=nit@example.com"])
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        std::fs::write(dir.path().join(".gitignore"), "ignored.txt\n").unwrap();
+        git(&["add", ".gitignore"]);
+        git(&["commit", "-q", "-m", "init"]);
+        std::fs::write(dir.path().join("ignored.txt"), "").unwrap();
+        std::fs::create_dir(dir.path().join("new")).unwrap();
+        std::fs::write(dir.path().join("new/file.txt"), "hello\n").unwrap();
+
+        let files = git_untracked_files(dir.path(), &RegexSet::empty()).unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(paths, [Path::new("new/file.txt")]);
+        assert_eq!(files[0].ty, FileType::Text);
+    }
+
+    #[test]
+    fn test_commit_paths() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let repo = dir.path().join("repo");
+        std::fs::create_dir(&repo).unwrap();
+        let git = |cwd: &Path, args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=nit", "-c", "user.email=nit@example.com"])
+                .args(args)
+                .current_dir(cwd)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&repo, &["init", "-q"]);
+        std::fs::write(repo.join("a.txt"), "a\n").unwrap();
+        std::fs::write(repo.join("b.txt"), "b\n").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", "root"]);
+        // The root commit has no parent, so all of its files are included.
+        assert_eq!(
+            git_commit_paths(&repo, "HEAD").unwrap(),
+            [Path::new("a.txt"), Path::new("b.txt")]
+        );
+
+        std::fs::write(repo.join("b.txt"), "changed\n").unwrap();
+        std::fs::write(repo.join("c.txt"), "c\n").unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["rm", "-q", "a.txt"]);
+        git(&repo, &["commit", "-q", "-m", "second"]);
+        assert_eq!(
+            git_commit_paths(&repo, "HEAD").unwrap(),
+            [Path::new("b.txt"), Path::new("c.txt")]
+        );
+
+        // In a shallow clone the parent is missing, which isn't the same as
+        // being the root commit.
+        let url = format!("file://{}", repo.display());
+        git(dir.path(), &["clone", "-q", "--depth=1", &url, "shallow"]);
+        let shallow = dir.path().join("shallow");
+        assert!(git_commit_paths(&shallow, "HEAD").is_err());
+        git(&shallow, &["fetch", "-q", "--deepen=1"]);
+        assert_eq!(
+            git_commit_paths(&shallow, "HEAD").unwrap(),
+            [Path::new("b.txt"), Path::new("c.txt")]
+        );
+    }
+
+    #[test]
+    fn test_merge_commit_paths() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=nit", "-c", "user.email=nit@example.com"])
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q", "-b", "main"]);
+        std::fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "root"]);
+        git(&["checkout", "-q", "-b", "feature"]);
+        std::fs::write(dir.path().join("b.txt"), "b\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "feature"]);
+        git(&["checkout", "-q", "main"]);
+        std::fs::write(dir.path().join("a.txt"), "changed\n").unwrap();
+        git(&["commit", "-q", "-am", "main"]);
+        git(&["merge", "-q", "--no-edit", "feature"]);
+
+        // Compared to the first parent, so only the feature branch's files.
+        assert_eq!(
+            git_commit_paths(dir.path(), "HEAD").unwrap(),
+            [Path::new("b.txt")]
+        );
+    }
+
+    #[test]
+    fn test_last_merge() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(["-c", "user.name=nit", "-c", "user.email=nit@example.com"])
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .unwrap()
+                .status
+                .success()
+        };
+        assert!(git(&["init", "-q", "-b", "main"]));
+        std::fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+        std::fs::write(dir.path().join("other.txt"), "other\n").unwrap();
+        assert!(git(&["add", "."]));
+        assert!(git(&["commit", "-q", "-m", "root"]));
+        assert_eq!(git_last_merge(dir.path()).unwrap(), None);
+
+        assert!(git(&["checkout", "-q", "-b", "feature"]));
+        std::fs::write(dir.path().join("a.txt"), "feature\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "b\n").unwrap();
+        assert!(git(&["add", "."]));
+        assert!(git(&["commit", "-q", "-m", "feature"]));
+        assert!(git(&["checkout", "-q", "main"]));
+        std::fs::write(dir.path().join("a.txt"), "main\n").unwrap();
+        std::fs::write(dir.path().join("other.txt"), "changed\n").unwrap();
+        assert!(git(&["commit", "-q", "-am", "main"]));
+
+        // Stopped for the conflict in a.txt. Files only changed on this
+        // branch aren't part of the merge.
+        assert!(!git(&["merge", "-q", "--no-edit", "feature"]));
+        let expected = vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+        assert_eq!(
+            git_last_merge(dir.path()).unwrap(),
+            Some(Merge::InProgress(expected.clone()))
+        );
+        let files = git_merge_files(dir.path(), &RegexSet::empty())
+            .unwrap()
+            .unwrap();
+        let paths: Vec<&Path> = files.iter().map(|file| file.path.as_path()).collect();
+        assert_eq!(paths, expected);
+
+        std::fs::write(dir.path().join("a.txt"), "resolved\n").unwrap();
+        assert!(git(&["commit", "-q", "-am", "merge"]));
+        assert_eq!(
+            git_last_merge(dir.path()).unwrap(),
+            Some(Merge::Done(expected))
+        );
+
+        // `ORIG_HEAD` is left pointing at the merge, which isn't a merge
+        // that produced `HEAD`.
+        assert!(git(&["reset", "-q", "--hard", "HEAD^1"]));
+        assert_eq!(git_last_merge(dir.path()).unwrap(), None);
+
+        // A fast-forward.
+        assert!(git(&["checkout", "-q", "-b", "ahead"]));
+        std::fs::write(dir.path().join("c.txt"), "c\n").unwrap();
+        assert!(git(&["add", "."]));
+        assert!(git(&["commit", "-q", "-m", "ahead"]));
+        assert!(git(&["checkout", "-q", "main"]));
+        assert!(git(&["merge", "-q", "--ff-only", "ahead"]));
+        assert_eq!(
+            git_last_merge(dir.path()).unwrap(),
+            Some(Merge::Done(vec![PathBuf::from("c.txt")]))
+        );
+    }
+
+    #[test]
+    fn test_checkout_commit_paths() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let repo = dir.path().join("repo");
+        let dest = dir.path().join("dest");
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        std::fs::create_dir(&dest).unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=nit", "-c", "user.email=nit@example.com"])
+                .args(args)
+                .current_dir(&repo)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        for name in ["a.txt", "b.txt", "src/c.txt"] {
+            std::fs::write(repo.join(name), name).unwrap();
+        }
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "init"]);
+
+        let paths = [PathBuf::from("a.txt"), PathBuf::from("src/c.txt")];
+        let files = git_checkout_commit(&repo, "HEAD", &paths, &dest, &RegexSet::empty()).unwrap();
+        let checked_out: Vec<&Path> = files.iter().map(|file| file.path.as_path()).collect();
+        assert_eq!(checked_out, paths);
+        assert_eq!(
+            std::fs::read_to_string(dest.join("src/c.txt")).unwrap(),
+            "src/c.txt"
+        );
+        assert!(!dest.join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_pathspec_batches() {
+        let paths: Vec<String> = (0..3000).map(|i| format!("src/file_{i:04}.rs")).collect();
+        let batches = pathspec_batches(&paths);
+        assert!(batches.len() > 1);
+        assert_eq!(batches.concat(), paths);
+        for batch in batches {
+            let bytes: usize = batch.iter().map(|path| path.len() + 1).sum();
+            assert!(bytes <= MAX_PATHSPEC_BYTES);
+        }
+        assert!(pathspec_batches::<PathBuf>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_git_diff_unstaged_scoped() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=nit", "-c", "user.email=nit@example.com"])
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        std::fs::write(dir.path().join("linted.txt"), "a\n").unwrap();
+        std::fs::write(dir.path().join("dirty.txt"), "b\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "init"]);
+
+        // An unrelated file has unstaged changes before and during the run.
+        std::fs::write(dir.path().join("dirty.txt"), "changed\n").unwrap();
+        let linted = [Path::new("linted.txt")];
+        let before = git_diff_unstaged(dir.path(), &linted).unwrap();
+        assert!(before.is_empty());
+        std::fs::write(dir.path().join("dirty.txt"), "changed again\n").unwrap();
+        assert_eq!(git_diff_unstaged(dir.path(), &linted).unwrap(), before);
+
+        std::fs::write(dir.path().join("linted.txt"), "fixed\n").unwrap();
+        assert_ne!(git_diff_unstaged(dir.path(), &linted).unwrap(), before);
+        assert!(git_diff_unstaged(dir.path(), &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_generated_marker() {
+        let markers = RegexSet::new([r"@generated\b"]).unwrap();
+        let classify = |contents: &[u8]| {
+            classify_contents("a.rs".into(), contents, false, 0, &markers).generated
+        };
+        assert!(classify(b"// @generated by protoc\nfn main() {}"));
+        assert!(!classify(b"fn main() {}"));
+        // Binary files are never considered generated.
+        assert!(!classify(b"@generated\x00"));
+    }
+
     #[test]
     fn test_process_file_info() {
         let dir = tempdir().expect("Failed to create temp dir");
@@ -230,6 +1737,8 @@ mod test {
         std::fs::write(&text_path, "Hello, world!").expect("Failed to write test text file");
         let bin_path = dir.path().join("test.bin");
         std::fs::write(&bin_path, b"Hello \x00!").expect("Failed to write test binary file");
+        let empty_path = dir.path().join("test.empty");
+        std::fs::write(&empty_path, "").expect("Failed to write test empty file");
 
         let status = Command::new("git")
             .arg("init")
@@ -261,6 +1770,7 @@ mod test {
             .arg("add")
             .arg(&text_path)
             .arg(&bin_path)
+            .arg(&empty_path)
             .current_dir(dir.path())
             .status()
             .expect("Failed to run git add");
@@ -275,10 +1785,26 @@ mod test {
             .expect("Failed to run git commit");
         assert!(status.success());
 
-        let mut files = git_tree_files(dir.path(), "HEAD").expect("Failed to get git tree files");
+        let mut files = git_tree_files(dir.path(), "HEAD", &RegexSet::empty())
+            .expect("Failed to get git tree files");
         files.sort();
-        assert_eq!(files.len(), 2);
+        assert_eq!(files.len(), 3);
         assert_eq!(files[0].ty, FileType::Binary);
-        assert_eq!(files[1].ty, FileType::Text);
+        assert_eq!(files[1].ty, FileType::Empty);
+        assert_eq!(files[1].size, 0);
+        assert_eq!(files[2].ty, FileType::Text);
+        assert_eq!(files[2].size, 13);
+        assert!(files.iter().all(|file| file.object_id.is_some()));
+
+        // Files that differ from the tree don't get its object IDs.
+        std::fs::write(&text_path, "Hello, nit!").unwrap();
+        let mut files = git_tree_files(dir.path(), "HEAD", &RegexSet::empty())
+            .expect("Failed to get git tree files");
+        files.sort();
+        assert!(files[0].object_id.is_some());
+        assert_eq!(files[2].object_id, None);
+
+        let branch = git_current_branch(dir.path()).expect("Failed to get branch");
+        assert_eq!(branch.as_deref(), Some("master"));
     }
 }
@@ -0,0 +1,82 @@
+use std::path::{Component, Path, PathBuf};
+
+use log::warn;
+use serde::Deserialize;
+
+/// Directory that linters with `git_requests` can write their requests to.
+/// It's a separate preopen from the repo.
+pub const GIT_REQUESTS_DIR: &str = "/.nit-git";
+
+/// File in `GIT_REQUESTS_DIR` that the requests are written to, as JSON
+/// lines. Its full path is passed to linters in `NIT_GIT_REQUESTS_FILE`.
+pub const GIT_REQUESTS_FILENAME: &str = "requests.jsonl";
+
+/// A change that a linter can't make itself because it can't run Git.
+/// Linters that set `git_requests` in their metadata write one of these per
+/// line, e.g.
+///
+///     {"set_executable":"scripts/build.sh"}
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum GitRequest {
+    /// Mark a file executable, in the index as well as the filesystem so it
+    /// works on Windows.
+    SetExecutable(PathBuf),
+}
+
+impl GitRequest {
+    /// The file the request is for, relative to the work dir. Linters with
+    /// an absolute `path_style` see the work dir at `/`, so a leading `/` is
+    /// removed. Paths that could be outside the work dir give `None`.
+    pub fn path(&self) -> Option<&Path> {
+        let GitRequest::SetExecutable(path) = self;
+        let path = path.strip_prefix("/").unwrap_or(path);
+        let normal = path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        (normal && !path.as_os_str().is_empty()).then_some(path)
+    }
+}
+
+/// Parse JSON lines of requests. Invalid lines are skipped with a warning.
+pub fn parse_git_requests(data: &[u8], linter_name: &str) -> Vec<GitRequest> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            serde_json::from_str(line)
+                .inspect_err(|e| {
+                    warn!("Ignoring invalid Git request from linter {linter_name}: {e}: {line}")
+                })
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_requests() {
+        let data = br#"{"set_executable":"a.sh"}
+{"set_executable":"/b/c.sh"}
+{"set_executable":"../d.sh"}
+{"chmod":"e.sh"}
+{"set_executable":""}
+
+"#;
+        let requests = parse_git_requests(data, "test");
+        assert_eq!(requests.len(), 4);
+        let paths: Vec<Option<&Path>> = requests.iter().map(GitRequest::path).collect();
+        assert_eq!(
+            paths,
+            [
+                Some(Path::new("a.sh")),
+                Some(Path::new("b/c.sh")),
+                None,
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::BTreeMap,
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::LazyLock,
+};
+
+use anyhow::{Context as _, Result, bail};
+use regex::Regex;
+
+/// Lines that have been added or changed in each file, compared to `HEAD`.
+/// Line numbers start at 1.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ChangedLines(BTreeMap<PathBuf, Vec<RangeInclusive<u64>>>);
+
+impl ChangedLines {
+    /// Get the changed lines from `git diff`. If `cached` is true this
+    /// compares the index to `HEAD`, otherwise the working directory.
+    pub fn from_git(top_level: &Path, cached: bool) -> Result<Self> {
+        let output = Command::new("git")
+            .args([
+                "diff",
+                "--no-ext-diff",
+                "--no-textconv",
+                "--no-color",
+                "--ignore-submodules",
+                "--no-renames",
+                "--unified=0",
+            ])
+            .args(cached.then_some("--cached"))
+            .arg("HEAD")
+            .current_dir(top_level)
+            .output()
+            .context("Failed to run git diff")?;
+        if !output.status.success() {
+            bail!(
+                "git diff command failed {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Parse the output of `git diff --unified=0`.
+    fn parse(diff: &str) -> Self {
+        let mut changed: BTreeMap<PathBuf, Vec<RangeInclusive<u64>>> = BTreeMap::new();
+        let mut path = None;
+        for line in diff.lines() {
+            if let Some(new_path) = line.strip_prefix("+++ ") {
+                // Deleted files are `/dev/null`.
+                path = new_path.strip_prefix("b/").map(PathBuf::from);
+            } else if let (Some(path), Some(hunk)) = (&path, line.strip_prefix("@@ ")) {
+                // @@ -<start>[,<count>] +<start>[,<count>] @@
+                let Some(new_range) = hunk.split(' ').nth(1).and_then(|r| r.strip_prefix('+'))
+                else {
+                    continue;
+                };
+                let (start, count) = new_range.split_once(',').unwrap_or((new_range, "1"));
+                let (Ok(start), Ok(count)) = (start.parse::<u64>(), count.parse::<u64>()) else {
+                    continue;
+                };
+                // Pure deletions have a count of 0.
+                if count > 0 {
+                    changed
+                        .entry(path.clone())
+                        .or_default()
+                        .push(start..=start + count - 1);
+                }
+            }
+        }
+        Self(changed)
+    }
+
+    /// Returns true if `line` of `path` has changed.
+    pub fn contains(&self, path: &Path, line: u64) -> bool {
+        self.0
+            .get(path)
+            .is_some_and(|ranges| ranges.iter().any(|range| range.contains(&line)))
+    }
+}
+
+/// Result of filtering a linter's output to changed lines.
+pub struct FilteredOutput {
+    pub output: Vec<u8>,
+    /// Number of diagnostics that were kept.
+    pub kept: usize,
+    /// Number of diagnostics that were removed.
+    pub removed: usize,
+}
+
+/// Remove diagnostics that aren't on changed lines from a linter's output.
+/// Diagnostics are lines that start with `<path>:<line>`, which is the format
+/// most linters use. The path must be relative to the linter's working
+/// directory, or start with `/` if `absolute` is set (see
+/// `PathStyle::Absolute`), and `is_file` must return true for it, so that
+/// e.g. `localhost:8080: connection refused` isn't counted. Other lines are
+/// kept.
+pub fn filter_diagnostics(
+    output: &[u8],
+    changed: &ChangedLines,
+    absolute: bool,
+    is_file: impl Fn(&Path) -> bool,
+) -> FilteredOutput {
+    static DIAGNOSTIC: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^([^:\s][^:]*):(\d+)(?:[:\s]|$)").unwrap());
+
+    let mut filtered = FilteredOutput {
+        output: Vec::with_capacity(output.len()),
+        kept: 0,
+        removed: 0,
+    };
+    for line in String::from_utf8_lossy(output).split_inclusive('\n') {
+        if let Some(captures) = DIAGNOSTIC.captures(line)
+            && let Some(path) = diagnostic_path(&captures[1], absolute)
+            && is_file(&path)
+        {
+            let line_number = captures[2].parse().unwrap_or(0);
+            if !changed.contains(&path, line_number) {
+                filtered.removed += 1;
+                continue;
+            }
+            filtered.kept += 1;
+        }
+        filtered.output.extend_from_slice(line.as_bytes());
+    }
+    filtered
+}
+
+/// Convert the path in a diagnostic to a path relative to the linter's
+/// working directory (which is `/` for `PathStyle::Absolute`). Returns `None`
+/// for paths that could be outside it.
+fn diagnostic_path(path: &str, absolute: bool) -> Option<PathBuf> {
+    let path = match path.strip_prefix('/') {
+        Some(path) if absolute => path,
+        Some(_) => return None,
+        None => path,
+    };
+    let mut relative = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(component) => relative.push(component),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DIFF: &str = "\
+diff --git a/a.rs b/a.rs
+index 1111111..2222222 100644
+--- a/a.rs
++++ b/a.rs
+@@ -3 +3 @@ fn main() {
+-    old
++    new
+@@ -10,2 +9,0 @@
+-    deleted
+-    deleted
+@@ -20,0 +20,3 @@
++    added
++    added
++    added
+diff --git a/gone.rs b/gone.rs
+deleted file mode 100644
+--- a/gone.rs
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+";
+
+    #[test]
+    fn test_parse_diff() {
+        let changed = ChangedLines::parse(DIFF);
+        assert_eq!(
+            changed,
+            ChangedLines(BTreeMap::from([("a.rs".into(), vec![3..=3, 20..=22])]))
+        );
+        assert!(changed.contains(Path::new("a.rs"), 21));
+        assert!(!changed.contains(Path::new("a.rs"), 9));
+        assert!(!changed.contains(Path::new("gone.rs"), 1));
+    }
+
+    #[test]
+    fn test_filter_diagnostics() {
+        let changed = ChangedLines::parse(DIFF);
+        let is_file = |path: &Path| path == Path::new("a.rs");
+        let output =
+            b"a.rs:3:5: error: bad\n./a.rs:4: warning: old\n/a.rs:20: also bad\nsummary line\n";
+        let filtered = filter_diagnostics(output, &changed, true, is_file);
+        assert_eq!(
+            filtered.output,
+            b"a.rs:3:5: error: bad\n/a.rs:20: also bad\nsummary line\n"
+        );
+        assert_eq!(filtered.kept, 2);
+        assert_eq!(filtered.removed, 1);
+
+        // Not diagnostics, so the failure can't be blamed on unchanged lines.
+        for line in [
+            "localhost:8080: refused\n",
+            "../a.rs:4: outside\n",
+            "/tmp/../a.rs:4: outside\n",
+        ] {
+            let filtered = filter_diagnostics(line.as_bytes(), &changed, true, is_file);
+            assert_eq!(filtered.output, line.as_bytes());
+            assert_eq!((filtered.kept, filtered.removed), (0, 0));
+        }
+    }
+
+    #[test]
+    fn test_diagnostic_path() {
+        assert_eq!(
+            diagnostic_path("./src/a.rs", false),
+            Some("src/a.rs".into())
+        );
+        assert_eq!(diagnostic_path("/src/a.rs", true), Some("src/a.rs".into()));
+        // Without `PathStyle::Absolute` the repo isn't at `/`.
+        assert_eq!(diagnostic_path("/src/a.rs", false), None);
+        assert_eq!(diagnostic_path("src/../../a.rs", false), None);
+    }
+}
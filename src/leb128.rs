@@ -2,6 +2,7 @@
 /// I got a little bit carried away here making a version without many branches.
 /// I have exhaustively tested that it is correct using the `exhaustive_leb128_test()`
 /// test below.
+#[allow(clippy::identity_op)]
 pub fn u32_to_leb128(n: u32) -> Vec<u8> {
     let mut leb = vec![
         ((n >> 0) as u8 & 0x7F) | (if n >> 7 == 0 { 0 } else { 0x80 }),
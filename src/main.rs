@@ -1,32 +1,82 @@
 mod bash_paths;
+mod bench;
+mod cache_info;
 mod config;
+mod decompress;
+mod diagnostics;
 mod engine;
+mod explain;
 mod fetch;
+mod file_hashes;
 mod file_matching;
 mod git;
+mod git_requests;
 mod hash_adapter;
+mod hunks;
 mod leb128;
+mod manifest;
 mod metadata;
+mod output;
+mod policy;
+mod pre_push;
+mod profile;
+mod results_cache;
+mod run_lock;
 mod serde_glob;
 mod serde_regex;
+mod temp_checkout;
+mod text_normalization;
+mod throughput;
 mod unique_filename;
 mod wasi_cache;
 mod wasm;
 
-use anyhow::{Result, anyhow, bail};
+use anyhow::{Context as _, Result, anyhow, bail};
 use bash_paths::path_to_bash_string;
+use bench::{BenchResult, print_bench_results};
+use cache_info::cache_info;
 use clap::{Parser, Subcommand, ValueEnum};
-use config::{Config, read_config};
-use engine::{get_cache_dir, run_single_linter};
-use env_logger::{Builder, Env};
-use fetch::fetch_linters;
-use file_matching::retain_matching_files;
+use config::{Config, ConfigLinter, TextNormalization, read_config};
+use engine::{
+    GitRequestsTarget, LinterStatus, PeakMemory, Recorded, RunContext, get_cache_dir,
+    get_linter_path, linter_matching_files, linter_stage, make_engine, matches_no_files,
+    run_single_linter, run_single_linter_with_retries,
+};
+use env_logger::{Builder, Env, Target, WriteStyle};
+use explain::explain_linter;
+use fetch::{fetch_linters, missing_linters};
+use file_hashes::{FileHash, hash_files, rehash_files};
+use file_matching::{retain_files_up_to_size, retain_matching_files};
 use git::git_diff_unstaged;
-use log::info;
-use metadata::read_metadata;
-use owo_colors::OwoColorize;
-use std::path::{Path, PathBuf};
-use tokio::fs;
+use hunks::ChangedLines;
+use log::{debug, info, warn};
+use manifest::Manifest;
+use metadata::{Stage, parse_metadata, read_metadata};
+use output::Output;
+use owo_colors::{AnsiColors, OwoColorize, Stream};
+use pre_push::{parse_pushed_refs, pushed_files, remote_name};
+use results_cache::ResultsCache;
+use run_lock::RunLock;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::IsTerminal as _,
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+    sync::{
+        Mutex, Once,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+use temp_checkout::TempCheckout;
+use text_normalization::normalize_files;
+use throughput::Throughput;
+use tokio::{
+    fs,
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    sync::Notify,
+};
 use wasm::{find_custom_sections, make_custom_section};
 
 #[derive(Parser)]
@@ -42,19 +92,96 @@ struct Cli {
     #[arg(long)]
     quiet: bool,
 
+    /// Print how long each phase took (listing files, fetching, compiling and
+    /// running each linter etc.) at the end.
+    #[arg(long)]
+    profile: bool,
+
+    /// Show debug messages, e.g. why files didn't match each linter.
+    #[arg(short, long, conflicts_with_all = ["quiet", "quiet_on_success"])]
+    verbose: bool,
+
+    /// Only show output if linting fails. This also hides log messages
+    /// below warnings, like `--quiet`.
+    #[arg(long)]
+    quiet_on_success: bool,
+
+    /// Write Nit's log messages to this file instead of stderr, e.g. to keep
+    /// them separate from linter output in CI.
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Don't modify anything; just report what would be done. `run` lints a
+    /// temporary copy of the files so fixes are discarded (though linters are
+    /// still downloaded).
+    #[arg(long)]
+    dry_run: bool,
+
     #[arg(short, long)]
     config: Option<PathBuf>,
 
+    /// Fail if the config has keys that Nit doesn't know about, e.g. typos
+    /// like `overide_args`. By default they are ignored so configs for newer
+    /// versions of Nit still work. `validate-config` always does this.
+    #[arg(long)]
+    strict_config: bool,
+
+    /// Directory to store downloaded and compiled linters in. Overrides
+    /// `cache_dir` in the config and `NIT_CACHE_DIR`.
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Fail instead of waiting if another instance of nit is linting the
+    /// same repo.
+    #[arg(long)]
+    no_wait: bool,
+
+    /// Give each run of a linter a temporary copy of only the files it is
+    /// linting, rather than access to the whole repo. This protects against
+    /// untrusted linters but copying the files makes it slower, and linters
+    /// that need other files (e.g. their own config) won't find them.
+    #[arg(long)]
+    sandbox_strict: bool,
+
+    /// Maximum number of linters to download at once. Overrides
+    /// `download_parallelism` in the config. Defaults to 4.
+    #[arg(long)]
+    download_jobs: Option<NonZeroUsize>,
+
     #[command(subcommand)]
     command: SubCommand,
 }
 
+impl Cli {
+    /// Directory to store downloaded and compiled linters in. This is
+    /// `--cache-dir`, or `cache_dir` from the config (relative to the root of
+    /// the repo), or the global cache directory.
+    fn cache_dir(&self, top_level: &Path, config: &Config) -> Result<PathBuf> {
+        if let Some(cache_dir) = &self.cache_dir {
+            Ok(cache_dir.clone())
+        } else if let Some(cache_dir) = &config.cache_dir {
+            Ok(top_level.join(cache_dir))
+        } else {
+            get_cache_dir().ok_or(anyhow!("Could not determine cache directory"))
+        }
+    }
+
+    /// Maximum number of linters to download at once.
+    fn download_jobs(&self, config: &Config) -> NonZeroUsize {
+        self.download_jobs
+            .or(config.download_parallelism)
+            .unwrap_or(NonZeroUsize::new(4).unwrap())
+    }
+}
+
 #[derive(Subcommand)]
 enum SubCommand {
     /// Remove downloaded linters.
     Clean,
+    /// Inspect the cache of downloaded and compiled linters.
+    Cache(CacheArgs),
     /// Download linters (this will be done automatically but it's useful for Docker images)
-    Fetch,
+    Fetch(FetchArgs),
     /// Install git hooks so this will run automatically
     Install(InstallArgs),
     /// Remove git hooks
@@ -63,8 +190,21 @@ enum SubCommand {
     Run(RunArgs),
     /// Print a sample config file.
     SampleConfig,
-    /// Validate the supplied config.
+    /// Create a config file in the repo root.
+    Init(InitArgs),
+    /// Validate the supplied config. Unknown keys are errors.
     ValidateConfig,
+    /// Print the config as it will be used, with all defaults filled in.
+    PrintConfig,
+    /// Print how a linter is configured and how it will be run, including
+    /// which settings come from the config and which from its metadata.
+    /// The linter is downloaded if necessary, but not run.
+    Explain(ExplainArgs),
+    /// Print a starter metadata file for linter authors, for use with
+    /// `set-metadata`.
+    MetadataTemplate,
+    /// Create a minimal linter crate to start writing a new linter.
+    NewLinter(NewLinterArgs),
     /// Show metadata for a linter WASM file.
     ShowMetadata(ShowMetadataArgs),
     /// Set metadata for a linter WASM file.
@@ -73,12 +213,65 @@ enum SubCommand {
     PreCommit,
     /// Run the pre-push hook.
     PrePush(PrePushArgs),
+    /// Run the commit-msg hook, which runs the commit message linters on
+    /// the message.
+    CommitMsg(CommitMsgArgs),
+    /// Lint file contents read from stdin, and write the fixed contents to
+    /// stdout. This is intended for editor integration.
+    CheckStdin(CheckStdinArgs),
+    /// Time each linter over all files. Note that this will fix files like `run`.
+    Bench(BenchArgs),
+    /// Check which commits in a range (e.g. `origin/main..HEAD`) pass the
+    /// current linters. Each commit is checked out to a temporary directory
+    /// and the files it changed are linted. Useful in CI to catch commits
+    /// made with `git commit --no-verify`.
+    VerifyHistory(VerifyHistoryArgs),
 }
 
 #[derive(Parser)]
 struct InstallArgs {
     #[arg(long)]
     hook_type: Option<HookType>,
+
+    /// Write the hook into this directory (e.g. one that is committed to the
+    /// repo) and point `core.hooksPath` at it, instead of using `.git/hooks`.
+    /// The hook runs `nit` from the `PATH` so it works for everyone.
+    #[arg(long, value_name = "DIR")]
+    hooks_path: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct CacheArgs {
+    #[command(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List the downloaded linters with their sizes, compiled versions, and
+    /// which configured linters use them. Compiled versions are stale if
+    /// they were made with a different version of nit or engine settings,
+    /// so they are recompiled.
+    Info,
+}
+
+#[derive(Parser)]
+struct FetchArgs {
+    /// Also compile the linters into the cache, so the first run doesn't
+    /// have to, e.g. when building a Docker image for CI.
+    #[arg(long)]
+    precompile: bool,
+}
+
+#[derive(Parser)]
+struct InitArgs {
+    /// Overwrite any existing config.
+    #[arg(long)]
+    force: bool,
+
+    /// Include some common linters in the config.
+    #[arg(long)]
+    common_linters: bool,
 }
 
 #[derive(Parser)]
@@ -87,11 +280,118 @@ struct RunArgs {
     #[arg(short, long)]
     all: bool,
 
-    #[arg(long)]
+    /// With `--all`, also lint untracked files that aren't ignored. By
+    /// default `--all` only lints the files in `HEAD`, for reproducibility.
+    #[arg(long, requires = "all")]
+    include_untracked: bool,
+
+    /// Run over these files instead. Unlike the other modes these don't
+    /// need to be tracked by Git, so ignored files can be linted too. They
+    /// are still filtered by the config's `include` and each linter's match
+    /// expression.
+    #[arg(long, num_args = 1.., conflicts_with = "all")]
     files: Vec<PathBuf>,
 
+    /// Like `--files`, but read the paths from this file (or stdin for `-`),
+    /// separated by NUL characters, e.g. from `git ls-files -z`. This works
+    /// with any filenames, including ones containing newlines.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["all", "files", "staged_only", "changed_in_commit", "merge", "since_last_commit"])]
+    files0_from: Option<PathBuf>,
+
+    /// Before linting, check that each linter with a `self_test` file in its
+    /// metadata fails on (or fixes) that file, and warn if it doesn't. This
+    /// catches linters that ignore the filenames they are given.
+    #[arg(long)]
+    verify_linters: bool,
+
     #[arg(long)]
     show_diff_on_failure: bool,
+
+    /// Run the linters on a temporary checkout of the index, so unstaged
+    /// changes can't affect the result. Fixes are staged, and also applied
+    /// to files that don't have unstaged changes.
+    #[arg(long, conflicts_with_all = ["all", "files"])]
+    staged_only: bool,
+
+    /// Run over the files that this commit added or modified (e.g. `HEAD`
+    /// when amending), compared to its first parent for merge commits. The
+    /// staged versions of the files are linted.
+    #[arg(long, value_name = "COMMIT", conflicts_with_all = ["all", "files", "staged_only"])]
+    changed_in_commit: Option<String>,
+
+    /// Run over the files that the current or last merge (or pull) changed.
+    /// During a merge (e.g. while resolving conflicts) these are the files
+    /// that `MERGE_HEAD` changed, as they are in the working tree. Otherwise
+    /// they are the files that the merge commit, or a fast-forward from
+    /// `ORIG_HEAD`, changed. This catches mistakes made while resolving
+    /// conflicts, e.g. from a `post-merge` hook.
+    #[arg(long, conflicts_with_all = ["all", "files", "staged_only", "changed_in_commit"])]
+    merge: bool,
+
+    /// Run over the files that the last commit added or modified, i.e.
+    /// those that differ between `HEAD~1` and `HEAD`, e.g. in CI. For the
+    /// first commit in a repo this is all of its files. Shallow clones need
+    /// at least two commits.
+    #[arg(long, conflicts_with_all = ["all", "files", "staged_only", "changed_in_commit", "merge"])]
+    since_last_commit: bool,
+
+    /// Only report diagnostics on lines that have changed compared to `HEAD`.
+    /// If a linter fails but all of its diagnostics are on unchanged lines it
+    /// passes. This only works for diagnostics in the common `<path>:<line>`
+    /// format, and doesn't limit the fixes that linters make.
+    #[arg(long)]
+    only_changed_hunks: bool,
+
+    /// Skip files larger than this many bytes. Overrides `max_file_size` in
+    /// the config.
+    #[arg(long)]
+    max_file_size: Option<u64>,
+
+    /// How to detect whether a linter modified files.
+    #[arg(long, default_value_t = ChangeDetection::GitDiff)]
+    change_detection: ChangeDetection,
+
+    /// Also run this linter, which doesn't need to be in the config. The
+    /// format is `<path-or-url>[:<args>]`. URLs need the hash after a `#`.
+    /// The args are separated by spaces and replace the linter's `extra`
+    /// args. Can be given more than once.
+    #[arg(long, value_name = "SPEC")]
+    extra_linter: Vec<String>,
+
+    /// If any linter fixes files, run all of the linters again and fail if
+    /// they change anything the second time. This catches linters whose fixes
+    /// aren't stable, which make CI flaky.
+    #[arg(long)]
+    check_idempotent: bool,
+
+    /// Fail if none of the linters match any files, rather than just warning.
+    /// This catches a config that accidentally excludes everything, so CI
+    /// passes without checking anything.
+    #[arg(long)]
+    error_on_empty: bool,
+
+    /// Also print a report of each linter's result and the files it fixed to
+    /// stdout in this format, for other tools. The normal output is still
+    /// written to stderr.
+    #[arg(long, default_value_t = ReportFormat::Human)]
+    format: ReportFormat,
+
+    /// Run all of the linters, even if they passed last time with the same
+    /// inputs. Their results are still recorded for later runs.
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Only run the `--extra-linter`s, not the ones in the config.
+    #[arg(long, requires = "extra_linter")]
+    only: bool,
+
+    /// Only run the configured linters that have one of these tags.
+    #[arg(long, conflicts_with = "only")]
+    tag: Vec<String>,
+
+    /// Don't run the configured linters that have any of these tags.
+    #[arg(long, conflicts_with = "only")]
+    not_tag: Vec<String>,
     // TODO (2.0): Add an option not to fix the files. Hooks will always fix files
     // but we can write a VFS layer for WASI that doesn't write the files back
     // to disk if this option is set.
@@ -99,6 +399,43 @@ struct RunArgs {
     // no_fix: bool,
 }
 
+#[derive(Parser)]
+struct CheckStdinArgs {
+    /// Path of the file that the contents belong to. This is used to decide
+    /// which linters to run. It doesn't need to exist.
+    #[arg(long)]
+    path: PathBuf,
+}
+
+#[derive(Parser)]
+struct BenchArgs {
+    /// Number of timed runs of each linter.
+    #[arg(short = 'n', long, default_value_t = 5)]
+    iterations: u32,
+}
+
+#[derive(Parser)]
+struct NewLinterArgs {
+    /// Name of the linter crate, e.g. `lint_foo`.
+    name: String,
+
+    /// Directory to create the crate in. Defaults to `./<name>`.
+    #[arg(long)]
+    dir: Option<PathBuf>,
+}
+
+#[derive(Parser)]
+struct ExplainArgs {
+    /// Name of the linter in the config.
+    linter: String,
+}
+
+#[derive(Parser)]
+struct VerifyHistoryArgs {
+    /// Commits to check, in any form `git rev-list` accepts.
+    range: String,
+}
+
 #[derive(Parser)]
 struct ShowMetadataArgs {
     /// WASM file to show the metadata for.
@@ -124,6 +461,12 @@ struct PrePushArgs {
     url: String,
 }
 
+#[derive(Parser)]
+struct CommitMsgArgs {
+    /// File containing the commit message.
+    file: PathBuf,
+}
+
 #[derive(ValueEnum, Clone)]
 enum ColorOutput {
     Auto,
@@ -131,6 +474,76 @@ enum ColorOutput {
     Never,
 }
 
+impl ColorOutput {
+    /// Resolve `Auto` to `Always` or `Never` depending on whether stderr is
+    /// a terminal (which is where all of our coloured output goes) and
+    /// whether `NO_COLOR` is set.
+    fn resolve(&self) -> ColorOutput {
+        match self {
+            ColorOutput::Auto => {
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                if !no_color && std::io::stderr().is_terminal() {
+                    ColorOutput::Always
+                } else {
+                    ColorOutput::Never
+                }
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// Whether Nit's output is coloured. This should be resolved first.
+    fn enabled(&self) -> bool {
+        matches!(self, ColorOutput::Always)
+    }
+
+    /// Style for Nit's log messages, so they are coloured like the rest of
+    /// its output. This should be resolved first.
+    fn log_style(&self) -> WriteStyle {
+        match self {
+            ColorOutput::Auto => WriteStyle::Auto,
+            ColorOutput::Always => WriteStyle::Always,
+            ColorOutput::Never => WriteStyle::Never,
+        }
+    }
+
+    /// Environment variables to pass to linters so that they colourise their
+    /// output consistently with Nit. Linters can't see Nit's terminal so they
+    /// can't make this decision themselves.
+    fn linter_env(&self) -> Vec<(String, String)> {
+        let vars: &[(&str, &str)] = match self {
+            ColorOutput::Auto => &[("NIT_COLOR", "auto")],
+            ColorOutput::Always => &[
+                ("NIT_COLOR", "always"),
+                ("CLICOLOR", "1"),
+                ("CLICOLOR_FORCE", "1"),
+            ],
+            ColorOutput::Never => &[("NIT_COLOR", "never"), ("CLICOLOR", "0"), ("NO_COLOR", "1")],
+        };
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+}
+
+/// Environment variables to pass to linters: Nit's colour settings, plus
+/// the host's values of the variables in `inherit_env` (unless Nit sets them
+/// itself).
+fn linter_env_with_inherited(color: &ColorOutput, inherit_env: &[String]) -> Vec<(String, String)> {
+    let mut env = color.linter_env();
+    for name in inherit_env {
+        if env.iter().any(|(k, _)| k == name) {
+            warn!("Not inheriting {name} because Nit sets it for linters");
+            continue;
+        }
+        match std::env::var(name) {
+            Ok(value) => env.push((name.clone(), value)),
+            Err(e) => debug!("Not inheriting {name}: {e}"),
+        }
+    }
+    env
+}
+
 impl std::fmt::Display for ColorOutput {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -141,11 +554,50 @@ impl std::fmt::Display for ColorOutput {
     }
 }
 
+#[derive(ValueEnum, Clone, Default)]
+enum ChangeDetection {
+    /// Compare `git diff` of the files each linter was given before and
+    /// after it runs.
+    #[default]
+    GitDiff,
+    /// Compare hashes of the files each linter was given. This doesn't need
+    /// Git, and also catches changes to files that aren't tracked.
+    FileHashes,
+}
+
+impl std::fmt::Display for ChangeDetection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeDetection::GitDiff => write!(f, "git-diff"),
+            ChangeDetection::FileHashes => write!(f, "file-hashes"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+enum ReportFormat {
+    /// Only the normal output on stderr.
+    #[default]
+    Human,
+    /// A JSON report on stdout.
+    Json,
+}
+
+impl std::fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportFormat::Human => write!(f, "human"),
+            ReportFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
 #[derive(ValueEnum, Clone, Default)]
 enum HookType {
     #[default]
     PreCommit,
     PrePush,
+    CommitMsg,
 }
 
 impl HookType {
@@ -153,70 +605,260 @@ impl HookType {
         match self {
             HookType::PreCommit => "pre-commit",
             HookType::PrePush => "pre-push",
+            HookType::CommitMsg => "commit-msg",
         }
     }
 }
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+
+    cli.color = cli.color.resolve();
+    owo_colors::set_override(cli.color.enabled());
 
-    let default_level = if cli.quiet { "warn" } else { "info" };
+    let default_level = if cli.quiet || cli.quiet_on_success {
+        "warn"
+    } else if cli.verbose {
+        // Dependencies' debug messages (e.g. Wasmtime's) would drown out ours.
+        "info,nit=debug"
+    } else {
+        "info"
+    };
     let env = Env::new()
         .filter_or("NIT_LOG", default_level)
         .write_style("NIT_LOG_STYLE");
-    Builder::from_env(env)
-        .format_timestamp(None)
-        .format_target(false)
-        .init();
+    let mut builder = Builder::from_env(env);
+    builder.format_timestamp(None).format_target(false);
+    // `NIT_LOG_STYLE` takes priority for log messages.
+    if std::env::var_os("NIT_LOG_STYLE").is_none() {
+        builder.write_style(cli.color.log_style());
+    }
+    if let Some(log_file) = &cli.log_file {
+        let file = std::fs::File::create(log_file)
+            .with_context(|| anyhow!("Failed to create log file {}", log_file.display()))?;
+        builder
+            .target(Target::Pipe(Box::new(file)))
+            .write_style(WriteStyle::Never);
+    }
+    builder.init();
+
+    if cli.profile {
+        profile::enable();
+    }
 
-    match &cli.command {
+    let result = match &cli.command {
         SubCommand::Clean => subcommand_clean(&cli).await,
-        SubCommand::Fetch => subcommand_fetch(&cli).await,
+        SubCommand::Cache(args) => subcommand_cache(&cli, args).await,
+        SubCommand::Fetch(args) => subcommand_fetch(&cli, args).await,
         SubCommand::Install(args) => subcommand_install(&cli, args).await,
         SubCommand::Uninstall => subcommand_uninstall(&cli).await,
         SubCommand::Run(args) => subcommand_run(&cli, args).await,
         SubCommand::SampleConfig => subcommand_sample_config(&cli).await,
+        SubCommand::Init(args) => subcommand_init(&cli, args).await,
         SubCommand::ValidateConfig => subcommand_validate_config(&cli).await,
+        SubCommand::PrintConfig => subcommand_print_config(&cli).await,
+        SubCommand::Explain(args) => subcommand_explain(&cli, args).await,
+        SubCommand::MetadataTemplate => subcommand_metadata_template(&cli).await,
+        SubCommand::NewLinter(args) => subcommand_new_linter(&cli, args).await,
         SubCommand::ShowMetadata(args) => subcommand_show_metadata(&cli, args).await,
         SubCommand::SetMetadata(args) => subcommand_set_metadata(&cli, args).await,
         SubCommand::PreCommit => subcommand_pre_commit(&cli).await,
         SubCommand::PrePush(args) => subcommand_pre_push(&cli, args).await,
+        SubCommand::CommitMsg(args) => subcommand_commit_msg(&cli, args).await,
+        SubCommand::CheckStdin(args) => subcommand_check_stdin(&cli, args).await,
+        SubCommand::Bench(args) => subcommand_bench(&cli, args).await,
+        SubCommand::VerifyHistory(args) => subcommand_verify_history(&cli, args).await,
+    };
+    profile::print_report();
+    if result.as_ref().is_err_and(|e| e.is::<Interrupted>()) {
+        eprintln!(
+            "{}",
+            "Interrupted".if_supports_color(Stream::Stderr, |t| t.red())
+        );
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    if let Err(e) = &result
+        && e.is::<ConfigError>()
+    {
+        // Like the default, but with a different exit code.
+        eprintln!("Error: {e:?}");
+        std::process::exit(CONFIG_ERROR_EXIT_CODE);
+    }
+    result
+}
+
+/// Config filenames that are found automatically, in order of priority.
+const CONFIG_FILENAMES: &[&str] = &[
+    ".nit.json5",
+    ".nit.jsonc",
+    ".nit.json",
+    ".nit.yaml",
+    ".nit.yml",
+    ".nit.toml",
+];
+
+/// Error for when the config is missing or invalid. It's added as context to
+/// the underlying error.
+#[derive(Debug)]
+struct ConfigError;
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Config error")
     }
 }
 
-fn find_and_read_config(top_level: &Path, config: &Option<PathBuf>) -> Result<Config> {
-    if let Some(path) = config {
-        read_config(path)
+/// Exit code for config errors, so scripts that run Nit over many repos can
+/// tell them apart from linting failures (1).
+const CONFIG_ERROR_EXIT_CODE: i32 = 3;
+
+fn find_and_read_config(
+    top_level: &Path,
+    config: &Option<PathBuf>,
+    strict: bool,
+) -> Result<Config> {
+    let result = if let Some(path) = config {
+        read_config(path, strict)
     } else {
-        for filename in &[".nit.json5", ".nit.jsonc", ".nit.json"] {
-            let path = top_level.join(filename);
-            if path.exists() {
-                return read_config(&path);
-            }
+        CONFIG_FILENAMES
+            .iter()
+            .map(|filename| top_level.join(filename))
+            .find(|path| path.exists())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No config file found (.nit.json5/jsonc/json/yaml/yml/toml) in the repository"
+                )
+            })
+            .and_then(|path| read_config(&path, strict))
+    };
+    result.context(ConfigError)
+}
+
+/// Find the cache directory, using the config's if we're in a repo that has
+/// one. This allows working on the cache outside a repo too, in which case
+/// there's no config. A config that can't be read is an error, rather than
+/// silently using a different cache directory.
+fn repo_cache_dir(cli: &Cli) -> Result<(PathBuf, Option<(PathBuf, Config)>)> {
+    let top_level = git::git_top_level()
+        .inspect_err(|e| debug!("Not using a config for the cache directory: {e:#}"))
+        .ok()
+        .filter(|top_level| {
+            cli.config.is_some()
+                || CONFIG_FILENAMES
+                    .iter()
+                    .any(|filename| top_level.join(filename).exists())
+        });
+    match top_level {
+        Some(top_level) => {
+            let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+            Ok((
+                cli.cache_dir(&top_level, &config)?,
+                Some((top_level, config)),
+            ))
+        }
+        None => {
+            let cache_dir = match &cli.cache_dir {
+                Some(cache_dir) => cache_dir.clone(),
+                None => get_cache_dir().ok_or(anyhow!("Could not determine cache directory"))?,
+            };
+            Ok((cache_dir, None))
         }
-        bail!("No config file found (.nit.json5/jsonc/json) in the repository");
     }
 }
 
-async fn subcommand_clean(_cli: &Cli) -> Result<()> {
-    let cache_dir = get_cache_dir().ok_or(anyhow!("Could not determine cache directory"))?;
+async fn subcommand_clean(cli: &Cli) -> Result<()> {
+    let (cache_dir, _) = repo_cache_dir(cli)?;
+    if cli.dry_run {
+        println!("Would delete {}", cache_dir.display());
+        if fs::try_exists(&cache_dir).await? {
+            let mut entries = fs::read_dir(&cache_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                println!("  {}", entry.path().display());
+            }
+        }
+        return Ok(());
+    }
     fs::remove_dir_all(cache_dir).await?;
     info!("Cache directory cleaned");
     Ok(())
 }
 
-async fn subcommand_fetch(cli: &Cli) -> Result<()> {
+async fn subcommand_cache(cli: &Cli, args: &CacheArgs) -> Result<()> {
+    match args.command {
+        CacheCommand::Info => {
+            let (cache_dir, repo) = repo_cache_dir(cli)?;
+            let (engine, linters) = match &repo {
+                Some((top_level, config)) => {
+                    let mut linters = BTreeMap::<PathBuf, Vec<String>>::new();
+                    for linter in &config.linters {
+                        linters
+                            .entry(get_linter_path(top_level, &cache_dir, linter))
+                            .or_default()
+                            .push(linter.name.clone());
+                    }
+                    (make_engine(&config.engine)?, linters)
+                }
+                None => (make_engine(&Default::default())?, BTreeMap::new()),
+            };
+            print!("{}", cache_info(&cache_dir, &engine, &linters)?);
+        }
+    }
+    Ok(())
+}
+
+async fn subcommand_fetch(cli: &Cli, args: &FetchArgs) -> Result<()> {
     let top_level = git::git_top_level()?;
-    let config = find_and_read_config(&top_level, &cli.config)?;
-    let cache_dir = get_cache_dir().ok_or(anyhow!("Could not determine cache directory"))?;
-    fetch_linters(&config.linters, &cache_dir).await
+    let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+    let cache_dir = cli.cache_dir(&top_level, &config)?;
+
+    if cli.dry_run {
+        for url in missing_linters(&config.linters, &cache_dir).await? {
+            println!("Would download {url}");
+        }
+        if args.precompile {
+            println!("Would compile the linters into {}", cache_dir.display());
+        }
+        return Ok(());
+    }
+    let manifest = Mutex::new(Manifest::load(&cache_dir));
+    fetch_linters(
+        &config.linters,
+        &cache_dir,
+        &manifest,
+        cli.download_jobs(&config),
+    )
+    .await?;
+    manifest.into_inner().unwrap().save(&cache_dir);
+
+    if args.precompile {
+        let engine = make_engine(&config.engine)?;
+        let linter_paths: BTreeSet<PathBuf> = config
+            .linters
+            .iter()
+            .map(|linter| get_linter_path(&top_level, &cache_dir, linter))
+            .collect();
+        for linter_path in linter_paths {
+            wasi_cache::load_component_cached(&engine, &linter_path, &cache_dir).await?;
+            info!("Compiled {}", linter_path.display());
+        }
+    }
+    Ok(())
 }
 
+/// Git config key that records the `core.hooksPath` that `nit install
+/// --hooks-path` set, so `nit uninstall` only unsets it if it's still ours.
+const NIT_HOOKS_PATH_KEY: &str = "nit.hooksPath";
+
 async fn subcommand_install(cli: &Cli, args: &InstallArgs) -> Result<()> {
-    let current_exe = std::env::current_exe()?;
-    let hooks_dir = git::git_hooks_dir()?;
-    fs::create_dir_all(&hooks_dir).await?;
+    let (hooks_dir, exe_path) = match &args.hooks_path {
+        // The hook may be shared, so it can't use this machine's path to nit.
+        Some(hooks_path) => (std::env::current_dir()?.join(hooks_path), "nit".to_owned()),
+        None => (
+            git::git_hooks_dir()?,
+            bash_paths::path_to_bash_string(&std::env::current_exe()?)?,
+        ),
+    };
     let hook_type = args.hook_type.clone().unwrap_or_default();
     let hook_path = hooks_dir.join(hook_type.as_str());
     if fs::try_exists(&hook_path).await? {
@@ -228,32 +870,85 @@ async fn subcommand_install(cli: &Cli, args: &InstallArgs) -> Result<()> {
             );
         }
     }
-    let exe_path = bash_paths::path_to_bash_string(&current_exe)?;
-
     let config_arg = if let Some(config) = &cli.config {
         format!("--config {}", path_to_bash_string(config)?)
     } else {
         String::new()
     };
 
-    fs::write(
-        &hook_path,
-        format!(
-            "#!/bin/bash\n\nset -e\n\n{exe_path} {config_arg} {} \"$@\"\n",
-            hook_type.as_str()
-        ),
-    )
-    .await?;
+    let hook = format!(
+        "#!/bin/bash\n\nset -e\n\n{exe_path} {config_arg} {} \"$@\"\n",
+        hook_type.as_str()
+    );
+
+    let hooks_path = args
+        .hooks_path
+        .as_ref()
+        .map(|_| hooks_path_config_value(&hooks_dir))
+        .transpose()?;
+
+    if cli.dry_run {
+        println!("Would write {}:\n{hook}", hook_path.display());
+        if let Some(hooks_path) = &hooks_path {
+            println!("Would set core.hooksPath to {hooks_path}");
+        }
+        return Ok(());
+    }
+
+    fs::create_dir_all(&hooks_dir).await?;
+    fs::write(&hook_path, hook).await?;
 
     // TODO (0.1): Confirm if we actually need to make it executable on Unix. I think
     // Git might just parse it and run it itself.
     #[cfg(unix)]
     set_executable(&hook_path).await?;
 
-    log::info!("Installed pre-commit hook");
+    if let Some(hooks_path) = &hooks_path {
+        let top_level = git::git_top_level()?;
+        match git::git_config_get(&top_level, "core.hooksPath")? {
+            Some(existing) if existing != *hooks_path => warn!(
+                "core.hooksPath was set to '{existing}'. Hooks in that directory won't run any more"
+            ),
+            _ => {}
+        }
+        git::git_config_set(&top_level, "core.hooksPath", Some(hooks_path))?;
+        git::git_config_set(&top_level, NIT_HOOKS_PATH_KEY, Some(hooks_path))?;
+        info!("Set core.hooksPath to {hooks_path}");
+    }
+
+    log::info!("Installed {} hook", hook_type.as_str());
     Ok(())
 }
 
+/// Get the value for `core.hooksPath`. This is relative to the repo root if
+/// possible, so it still works if the repo is moved or cloned elsewhere.
+fn hooks_path_config_value(hooks_dir: &Path) -> Result<String> {
+    let top_level = git::git_top_level()?;
+    // Remove `..` so it can be made relative to the root. The directory may
+    // not exist yet so it can't be canonicalised.
+    let normalised = normalise_lexically(&std::path::absolute(hooks_dir)?);
+    let path = normalised.strip_prefix(&top_level).unwrap_or(&normalised);
+    path.to_str()
+        .map(ToOwned::to_owned)
+        .with_context(|| anyhow!("Hooks path is not UTF-8: {}", path.display()))
+}
+
+/// Remove `.` and `..` components from an absolute path without touching
+/// the filesystem, so symlinks aren't resolved.
+fn normalise_lexically(path: &Path) -> PathBuf {
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                normalised.pop();
+            }
+            std::path::Component::CurDir => {}
+            component => normalised.push(component),
+        }
+    }
+    normalised
+}
+
 #[cfg(unix)]
 async fn set_executable(path: &Path) -> Result<()> {
     let metadata = fs::metadata(path).await?;
@@ -267,18 +962,41 @@ async fn set_executable(path: &Path) -> Result<()> {
     Ok(())
 }
 
-async fn subcommand_uninstall(_cli: &Cli) -> Result<()> {
+async fn subcommand_uninstall(cli: &Cli) -> Result<()> {
     let hooks_dir = git::git_hooks_dir()?;
-    for hook_type in &[HookType::PreCommit, HookType::PrePush] {
+    for hook_type in &[HookType::PreCommit, HookType::PrePush, HookType::CommitMsg] {
         let hook_path = hooks_dir.join(hook_type.as_str());
+        if !fs::try_exists(&hook_path).await? {
+            continue;
+        }
         let content = fs::read(&hook_path).await?;
         if memchr::memmem::find(&content, b"nit").is_some() {
+            if cli.dry_run {
+                println!("Would remove {}", hook_path.display());
+                continue;
+            }
             fs::remove_file(&hook_path).await?;
             info!("Uninstalled hook '{}'", hook_type.as_str());
         } else {
             info!("Hook '{}' is not a Nit hook.", hook_type.as_str());
         }
     }
+
+    // Only unset `core.hooksPath` if `nit install --hooks-path` set it.
+    let top_level = git::git_top_level()?;
+    if let Some(hooks_path) = git::git_config_get(&top_level, NIT_HOOKS_PATH_KEY)? {
+        if git::git_config_get(&top_level, "core.hooksPath")?.as_ref() == Some(&hooks_path) {
+            if cli.dry_run {
+                println!("Would unset core.hooksPath");
+                return Ok(());
+            }
+            git::git_config_set(&top_level, "core.hooksPath", None)?;
+            info!("Unset core.hooksPath");
+        }
+        if !cli.dry_run {
+            git::git_config_set(&top_level, NIT_HOOKS_PATH_KEY, None)?;
+        }
+    }
     Ok(())
 }
 
@@ -288,81 +1006,1430 @@ async fn subcommand_sample_config(_cli: &Cli) -> Result<()> {
     Ok(())
 }
 
-async fn subcommand_validate_config(cli: &Cli) -> Result<()> {
-    let top_level = git::git_top_level()?;
-    let _config = find_and_read_config(&top_level, &cli.config)?;
-    info!("Config validated");
+async fn subcommand_metadata_template(_cli: &Cli) -> Result<()> {
+    let template = include_str!("../metadata_template.json5");
+    println!("{}", template);
     Ok(())
 }
 
-async fn subcommand_run(cli: &Cli, args: &RunArgs) -> Result<()> {
-    let top_level = git::git_top_level()?;
-    let config = find_and_read_config(&top_level, &cli.config)?;
-
-    let files = if args.all {
-        git::git_tree_files(&top_level, "HEAD")?
-    } else {
-        git::git_staged_files(&top_level)?
-    };
+/// Name used in the linter templates, which is replaced with the real name.
+const TEMPLATE_LINTER_NAME: &str = "lint_example";
 
-    run(top_level, config, files).await
+/// Returns true if `name` can be used as a crate name.
+fn is_valid_crate_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
 }
 
-async fn run(
-    top_level: PathBuf,
-    config: Config,
-    mut files: Vec<git::FileInfo>,
-) -> std::result::Result<(), anyhow::Error> {
-    let cache_dir = get_cache_dir().ok_or(anyhow!("Could not determine cache directory"))?;
-
-    // Only lint files in `include`.
-    retain_matching_files(&mut files, &config.include);
-
-    // 0. Determine the changed files (or find all files).
-    // 1. Download the wasm binary (if required).
-    // 2. Load it.
-    // 3. Run it with `--config` to determine how we should feed it files.
-    //      - chunked filenames (chunk length = 0 for all)
-    //      - don't feed it anything (e.g. for cargo fmt)
-    // 4. Run it over the changed files.
+async fn subcommand_new_linter(cli: &Cli, args: &NewLinterArgs) -> Result<()> {
+    if !is_valid_crate_name(&args.name) {
+        bail!(
+            "'{}' isn't a valid crate name. Use letters, numbers, '_' and '-'.",
+            args.name
+        );
+    }
+    let dir = args
+        .dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(&args.name));
+    if fs::try_exists(&dir).await? {
+        bail!("{} already exists", dir.display());
+    }
 
-    fetch_linters(&config.linters, &cache_dir).await?;
+    let files = [
+        (
+            "Cargo.toml",
+            include_str!("../linter_template/Cargo.toml.template"),
+        ),
+        (
+            ".cargo/config.toml",
+            include_str!("../linter_template/config.toml.template"),
+        ),
+        (
+            "src/main.rs",
+            include_str!("../linter_template/main.rs.template"),
+        ),
+        ("metadata.json5", include_str!("../metadata_template.json5")),
+    ];
+    for (path, template) in files {
+        let path = dir.join(path);
+        let contents = template.replace(TEMPLATE_LINTER_NAME, &args.name);
+        if cli.dry_run {
+            println!("Would write {}:\n{contents}", path.display());
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, contents).await?;
+    }
+    if cli.dry_run {
+        return Ok(());
+    }
 
-    let mut diff = git_diff_unstaged(&top_level)?;
+    info!("Created {}. To build it:", dir.display());
+    info!("  cd {}", dir.display());
+    info!("  rustup target add wasm32-wasip2");
+    info!("  cargo build --release");
+    info!(
+        "  nit set-metadata --metadata metadata.json5 target/wasm32-wasip2/release/{}.wasm",
+        args.name
+    );
+    Ok(())
+}
 
-    let mut failed = false;
+async fn subcommand_init(cli: &Cli, args: &InitArgs) -> Result<()> {
+    let top_level = git::git_top_level()?;
 
-    // Run the linters.
-    for linter in config.linters {
-        eprintln!("Running linter: {}", linter.name.blue());
-        let status = run_single_linter(&files, &cache_dir, &top_level, linter).await?;
-        let new_diff = git_diff_unstaged(&top_level)?;
+    let config_path = cli
+        .config
+        .clone()
+        .unwrap_or_else(|| top_level.join(CONFIG_FILENAMES[0]));
 
-        if !status || diff != new_diff {
-            failed = true;
-            eprintln!("Linter {}", "failed".red());
-        } else {
-            eprintln!("Linter {}", "passed".green());
+    if !args.force {
+        let existing = cli.config.iter().cloned().chain(
+            CONFIG_FILENAMES
+                .iter()
+                .map(|filename| top_level.join(filename)),
+        );
+        for path in existing {
+            if fs::try_exists(&path).await? {
+                bail!(
+                    "Config '{}' already exists. Use --force to overwrite it.",
+                    path.display()
+                );
+            }
         }
-        diff = new_diff;
     }
 
-    if failed {
-        bail!("Linting failed");
+    let config = if args.common_linters {
+        include_str!("../starter_config.json5")
+    } else {
+        include_str!("../sample_config.json5")
+    };
+
+    if cli.dry_run {
+        println!("Would write {}:\n{config}", config_path.display());
+        return Ok(());
     }
 
+    fs::write(&config_path, config).await?;
+
+    info!("Created {}", config_path.display());
+    info!("Run `nit run --all` to lint all files, and `nit install` to lint on every commit.");
     Ok(())
 }
 
-async fn subcommand_show_metadata(_cli: &Cli, args: &ShowMetadataArgs) -> Result<()> {
-    let metadata = read_metadata(&args.file)?;
-    println!("{metadata:?}");
+async fn subcommand_validate_config(cli: &Cli) -> Result<()> {
+    let top_level = git::git_top_level()?;
+    let _config = find_and_read_config(&top_level, &cli.config, true)?;
+    info!("Config validated");
     Ok(())
 }
 
-async fn subcommand_set_metadata(_cli: &Cli, args: &SetMetadataArgs) -> Result<()> {
+async fn subcommand_print_config(cli: &Cli) -> Result<()> {
+    let top_level = git::git_top_level()?;
+    let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+    // JSON is valid JSON5, and serde_json5 can't pretty print.
+    println!("{}", serde_json::to_string_pretty(&config)?);
+    Ok(())
+}
+
+async fn subcommand_explain(cli: &Cli, args: &ExplainArgs) -> Result<()> {
+    let top_level = git::git_top_level()?;
+    let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+    let cache_dir = cli.cache_dir(&top_level, &config)?;
+    let Some(linter) = config.linters.iter().find(|l| l.name == args.linter) else {
+        let names: Vec<&str> = config.linters.iter().map(|l| l.name.as_str()).collect();
+        bail!(
+            "No linter named '{}' in the config. Linters are: {}",
+            args.linter,
+            names.join(", ")
+        );
+    };
+
+    let manifest = Mutex::new(Manifest::load(&cache_dir));
+    fetch_linters(
+        std::slice::from_ref(linter),
+        &cache_dir,
+        &manifest,
+        cli.download_jobs(&config),
+    )
+    .await?;
+    let linter_path = get_linter_path(&top_level, &cache_dir, linter);
+    let metadata = manifest.lock().unwrap().metadata(&linter_path)?;
+    manifest.into_inner().unwrap().save(&cache_dir);
+
+    print!("{}", explain_linter(linter, &metadata, &linter_path)?);
+    Ok(())
+}
+
+async fn subcommand_run(cli: &Cli, args: &RunArgs) -> Result<()> {
+    let cwd = std::env::current_dir()?;
+    let top_level = match git::git_top_level() {
+        Ok(top_level) => top_level,
+        Err(e) => {
+            let explicit = !args.files.is_empty() || args.files0_from.is_some();
+            let Some(config_path) = cli.config.as_ref().filter(|_| explicit) else {
+                return Err(e.context(
+                    "Outside a Git repository, only --files or --files0-from with --config are supported",
+                ));
+            };
+            debug!("{e:#}");
+            return run_outside_repo(cli, args, &cwd, config_path).await;
+        }
+    };
+    let _lock = RunLock::acquire(&top_level, !cli.no_wait)?;
+    let config = run_config(cli, args, &top_level, &cwd).await?;
+
+    // Staged-only mode lints the index so we need to diff that.
+    let changed_lines = args
+        .only_changed_hunks
+        .then(|| ChangedLines::from_git(&top_level, args.staged_only))
+        .transpose()?;
+    let options = RunOptions {
+        changed_lines: changed_lines.as_ref(),
+        check_idempotent: args.check_idempotent,
+        error_on_empty: args.error_on_empty,
+        outside_repo: false,
+        format: args.format,
+        no_cache: args.no_cache,
+        stage: Stage::Files,
+        git_requests: GitRequestsTarget::Report,
+    };
+
+    if args.staged_only {
+        let checkout = TempCheckout::of_index(&top_level, &config.generated_markers)?;
+        return run_in_checkout(cli, top_level, config, checkout, !cli.dry_run, options).await;
+    }
+
+    let files = if let Some(paths) = explicit_paths(args, &cwd).await? {
+        if paths.is_empty() {
+            info!("No files to lint");
+            return Ok(());
+        }
+        git::working_tree_files(&top_level, &paths, &config.generated_markers)?
+    } else if let Some(commit) = &args.changed_in_commit {
+        git::git_commit_files(&top_level, commit, &config.generated_markers)?
+    } else if args.merge {
+        let Some(files) = git::git_merge_files(&top_level, &config.generated_markers)? else {
+            warn!(
+                "There is no merge in progress or merge that produced HEAD, so there is nothing to lint"
+            );
+            return Ok(());
+        };
+        files
+    } else if args.since_last_commit {
+        git::git_last_commit_files(&top_level, &config.generated_markers)?
+    } else if args.all {
+        let mut files = git::git_tree_files(&top_level, "HEAD", &config.generated_markers)?;
+        if args.include_untracked {
+            // Files removed with `git rm --cached` are in `HEAD` too.
+            let in_head: BTreeSet<PathBuf> = files.iter().map(|f| f.path.clone()).collect();
+            let untracked = git::git_untracked_files(&top_level, &config.generated_markers)?;
+            files.extend(untracked.into_iter().filter(|f| !in_head.contains(&f.path)));
+        }
+        files
+    } else {
+        git::git_staged_files(&top_level, &config.generated_markers)?
+    };
+
+    if cli.dry_run {
+        let checkout = TempCheckout::of_files(&top_level, files)?;
+        return run_in_checkout(cli, top_level, config, checkout, false, options).await;
+    }
+
+    run(
+        cli,
+        &top_level,
+        &top_level,
+        config,
+        files,
+        &args.change_detection,
+        RunOptions {
+            git_requests: GitRequestsTarget::Repo(&top_level),
+            ..options
+        },
+    )
+    .await
+}
+
+/// Read the config for `nit run` and apply the options that change it.
+/// `top_level` is the repo root, or where the config is outside a repo.
+async fn run_config(cli: &Cli, args: &RunArgs, top_level: &Path, cwd: &Path) -> Result<Config> {
+    let mut config = find_and_read_config(top_level, &cli.config, cli.strict_config)?;
+    if args.max_file_size.is_some() {
+        config.max_file_size = args.max_file_size;
+    }
+    if args.only {
+        config.linters.clear();
+    }
+    filter_by_tags(&mut config.linters, &args.tag, &args.not_tag)?;
+    for spec in &args.extra_linter {
+        let linter = ConfigLinter::from_spec(spec, cwd)
+            .with_context(|| anyhow!("Invalid --extra-linter '{spec}'"))?;
+        config.linters.push(linter);
+    }
+
+    if args.verify_linters {
+        verify_linters(cli, top_level, &config).await?;
+    }
+    Ok(config)
+}
+
+/// The paths given with `--files` or `--files0-from`, relative to the
+/// current directory `cwd`, if either was used.
+async fn explicit_paths(args: &RunArgs, cwd: &Path) -> Result<Option<Vec<PathBuf>>> {
+    let paths = if !args.files.is_empty() {
+        args.files.clone()
+    } else if let Some(source) = &args.files0_from {
+        let input = if source.as_os_str() == "-" {
+            let mut input = Vec::new();
+            tokio::io::stdin().read_to_end(&mut input).await?;
+            input
+        } else {
+            fs::read(source)
+                .await
+                .with_context(|| anyhow!("Failed to read {}", source.display()))?
+        };
+        parse_files0(&input)?
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(paths.into_iter().map(|f| cwd.join(f)).collect()))
+}
+
+/// `nit run` outside a Git repo, with an explicit list of files and
+/// `--config`. Linters are given the deepest directory that contains all of
+/// the files, and local linter paths (and `cache_dir`) are relative to the
+/// config's directory.
+async fn run_outside_repo(cli: &Cli, args: &RunArgs, cwd: &Path, config_path: &Path) -> Result<()> {
+    if args.only_changed_hunks {
+        bail!("--only-changed-hunks needs a Git repository");
+    }
+    let config_dir = cwd
+        .join(config_path)
+        .parent()
+        .map(Path::to_owned)
+        .unwrap_or_else(|| cwd.to_owned());
+    let config = run_config(cli, args, &config_dir, cwd).await?;
+
+    let paths = explicit_paths(args, cwd).await?.unwrap_or_default();
+    if paths.is_empty() {
+        info!("No files to lint");
+        return Ok(());
+    }
+    // Linters can read and write everything in this directory, so don't let
+    // it be e.g. `/` because the files are in unrelated places.
+    let work_dir = common_dir(&paths)?;
+    let cwd_canonical = cwd
+        .canonicalize()
+        .with_context(|| anyhow!("Failed to resolve {}", cwd.display()))?;
+    if !work_dir.starts_with(&cwd_canonical) {
+        bail!(
+            "Outside a Git repository the files must be in the current directory, because linters are given access to the directory containing them all ({})",
+            work_dir.display()
+        );
+    }
+    let files = git::files_outside_repo(&work_dir, &paths, &config.generated_markers)?;
+
+    let options = RunOptions {
+        changed_lines: None,
+        check_idempotent: args.check_idempotent,
+        error_on_empty: args.error_on_empty,
+        outside_repo: true,
+        format: args.format,
+        no_cache: args.no_cache,
+        stage: Stage::Files,
+        git_requests: GitRequestsTarget::Report,
+    };
+    if cli.dry_run {
+        let checkout = TempCheckout::of_files(&work_dir, files)?;
+        return run_in_checkout(cli, config_dir, config, checkout, false, options).await;
+    }
+    // There's no Git to diff with.
+    run(
+        cli,
+        &config_dir,
+        &work_dir,
+        config,
+        files,
+        &ChangeDetection::FileHashes,
+        RunOptions {
+            git_requests: GitRequestsTarget::Directory(&work_dir),
+            ..options
+        },
+    )
+    .await
+}
+
+/// The deepest directory that contains all of `paths`, which must exist and
+/// not be empty.
+fn common_dir(paths: &[PathBuf]) -> Result<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+    for path in paths {
+        // Don't follow symlinks to files.
+        let dir = if path.is_dir() {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or(path)
+        };
+        let dir = dir
+            .canonicalize()
+            .with_context(|| anyhow!("{} doesn't exist", path.display()))?;
+        common = Some(match common {
+            None => dir,
+            Some(common) => common
+                .components()
+                .zip(dir.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+    common.ok_or_else(|| anyhow!("No files given"))
+}
+
+/// Run each linter that has a `self_test` in its metadata on that file, in a
+/// temporary directory, and warn if it passes without changing it.
+async fn verify_linters(cli: &Cli, top_level: &Path, config: &Config) -> Result<()> {
+    let cache_dir = cli.cache_dir(top_level, config)?;
+    let manifest = Mutex::new(Manifest::load(&cache_dir));
+    fetch_linters(
+        &config.linters,
+        &cache_dir,
+        &manifest,
+        cli.download_jobs(config),
+    )
+    .await?;
+
+    let linter_env = linter_env_with_inherited(&cli.color, &config.inherit_env);
+    let engine = make_engine(&config.engine)?;
+
+    for linter in &config.linters {
+        let linter_path = get_linter_path(top_level, &cache_dir, linter);
+        let metadata = manifest.lock().unwrap().metadata(&linter_path)?;
+        let Some(self_test) = &metadata.self_test else {
+            debug!("Linter {} has no self test", linter.name);
+            continue;
+        };
+
+        // The path comes from the linter, so it mustn't escape the temporary
+        // directory.
+        let path_is_safe = self_test.path.components().next().is_some()
+            && self_test
+                .path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !path_is_safe {
+            warn!(
+                "Couldn't self test linter {}: its self test path {} isn't a relative path inside the repo",
+                linter.name,
+                self_test.path.display()
+            );
+            continue;
+        }
+
+        // Removed when it's dropped, even on error.
+        let temp_dir = tempfile::Builder::new()
+            .prefix("nit-self-test-")
+            .tempdir()
+            .context("Failed to create a temporary directory")?;
+        let work_dir = temp_dir.path();
+        let file_path = work_dir.join(&self_test.path);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&file_path, &self_test.contents).await?;
+        let files = vec![git::classify_contents(
+            self_test.path.clone(),
+            self_test.contents.as_bytes(),
+            false,
+            self_test.contents.len() as u64,
+            &config.generated_markers,
+        )];
+
+        // Its output isn't interesting; we expect it to fail.
+        let output = Output::buffered(cli.color.enabled());
+        let context = RunContext {
+            top_level,
+            work_dir,
+            cache_dir: &cache_dir,
+            env: &linter_env,
+            engine: &engine,
+            manifest: &manifest,
+            changed_lines: None,
+            output: &output,
+            sandbox_strict: cli.sandbox_strict,
+            results_cache: None,
+            peak_memory: None,
+            file_hashes: None,
+            git_requests: GitRequestsTarget::Report,
+        };
+        let result = if linter_matching_files(&context, &files, linter)?.is_empty() {
+            Err(anyhow!(
+                "its self test file {} doesn't match the files it runs on",
+                self_test.path.display()
+            ))
+        } else {
+            run_single_linter(&context, &files, linter)
+                .await
+                .map(LinterStatus::passed)
+        };
+        output.discard();
+        let fixed = fs::read(&file_path).await.ok();
+        drop(temp_dir);
+
+        match result {
+            Ok(true) if fixed.as_deref() == Some(self_test.contents.as_bytes()) => warn!(
+                "Linter {} passed on its self test file {} without changing it. It may be ignoring the filenames it's given",
+                linter.name,
+                self_test.path.display()
+            ),
+            Ok(_) => info!(
+                "Linter {} caught the problem in its self test file",
+                linter.name
+            ),
+            Err(e) => warn!("Couldn't self test linter {}: {e:#}", linter.name),
+        }
+    }
+
+    manifest.into_inner().unwrap().save(&cache_dir);
+    Ok(())
+}
+
+/// Parse a list of NUL separated paths, for `--files0-from`. The last one
+/// doesn't need to be terminated.
+fn parse_files0(input: &[u8]) -> Result<Vec<PathBuf>> {
+    input
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            std::str::from_utf8(path)
+                .map(PathBuf::from)
+                .with_context(|| anyhow!("Path isn't valid UTF-8: {}", path.escape_ascii()))
+        })
+        .collect()
+}
+
+/// Options for `run()` from `nit run`'s arguments. The hooks use the
+/// defaults.
+#[derive(Default, Clone, Copy)]
+struct RunOptions<'a> {
+    /// Only fail for diagnostics on these lines.
+    changed_lines: Option<&'a ChangedLines>,
+    /// Run the linters again to check that their fixes are stable.
+    check_idempotent: bool,
+    /// Fail if none of the linters match any files.
+    error_on_empty: bool,
+    /// The files aren't in a Git repository, so there is no branch.
+    outside_repo: bool,
+    /// Format of the report printed to stdout.
+    format: ReportFormat,
+    /// Run linters that passed last time with the same inputs too. Their
+    /// results are still recorded.
+    no_cache: bool,
+    /// Only run the linters for this stage.
+    stage: Stage,
+    /// Where linters' Git requests are made. Only report them by default,
+    /// since the files may be a temporary copy.
+    git_requests: GitRequestsTarget<'a>,
+}
+
+/// Run the linters on a temporary checkout. If `stage_fixes` is true (and it
+/// is a checkout of the index) fixes are staged, otherwise they are discarded.
+async fn run_in_checkout(
+    cli: &Cli,
+    top_level: PathBuf,
+    config: Config,
+    checkout: TempCheckout,
+    stage_fixes: bool,
+    options: RunOptions<'_>,
+) -> Result<()> {
+    let files = checkout.files().to_vec();
+
+    // Git requests are made to the index along with the fixes.
+    let git_requests = if stage_fixes {
+        GitRequestsTarget::Repo(&top_level)
+    } else {
+        GitRequestsTarget::Report
+    };
+
+    // The checkout isn't a Git repo, so we can't use `git diff`.
+    let result = run(
+        cli,
+        &top_level,
+        checkout.dir(),
+        config,
+        files,
+        &ChangeDetection::FileHashes,
+        RunOptions {
+            git_requests,
+            ..options
+        },
+    )
+    .await;
+
+    // Don't stage partial fixes. The checkout is deleted when it's dropped.
+    if result.as_ref().is_err_and(|e| e.is::<Interrupted>()) {
+        return result;
+    }
+
+    if stage_fixes {
+        for file in checkout.apply_fixes_to_index(&top_level)? {
+            info!("Staged fixes to {}", file.path.display());
+        }
+    } else {
+        for file in checkout.modified_files()? {
+            info!("Would fix {}", file.path.display());
+        }
+    }
+    result
+}
+
+/// Run the linters over `files`, which are relative to `work_dir`. This is
+/// normally `top_level` but may be a temporary checkout.
+async fn run(
+    cli: &Cli,
+    top_level: &Path,
+    work_dir: &Path,
+    mut config: Config,
+    mut files: Vec<git::FileInfo>,
+    change_detection: &ChangeDetection,
+    options: RunOptions<'_>,
+) -> std::result::Result<(), anyhow::Error> {
+    let cache_dir = cli.cache_dir(top_level, &config)?;
+    let listed = files.len();
+
+    // Only lint files in `include`.
+    retain_matching_files(&mut files, &config.include);
+    if let Some(max_file_size) = config.max_file_size {
+        retain_files_up_to_size(&mut files, max_file_size);
+    }
+
+    // Skip linters that can't have anything to do before fetching, so they
+    // aren't downloaded. Others need their metadata to tell.
+    let manifest = Manifest::load(&cache_dir);
+    config.linters.retain(|linter| {
+        let skip = matches_no_files(&files, linter, &manifest);
+        if skip {
+            info!(
+                "Skipping linter {} because it doesn't match any files",
+                linter.name
+            );
+        }
+        !skip
+    });
+
+    // 0. Determine the changed files (or find all files).
+    // 1. Download the wasm binary (if required).
+    // 2. Load it.
+    // 3. Run it with `--config` to determine how we should feed it files.
+    //      - chunked filenames (chunk length = 0 for all)
+    //      - don't feed it anything (e.g. for cargo fmt)
+    // 4. Run it over the changed files.
+
+    let manifest = Mutex::new(manifest);
+    fetch_linters(
+        &config.linters,
+        &cache_dir,
+        &manifest,
+        cli.download_jobs(&config),
+    )
+    .await?;
+
+    let mut linters = Vec::with_capacity(config.linters.len());
+    for linter in std::mem::take(&mut config.linters) {
+        if linter_stage(top_level, &cache_dir, &manifest, &linter)? == options.stage {
+            linters.push(linter);
+        }
+    }
+    config.linters = linters;
+
+    let linter_env = linter_env_with_inherited(&cli.color, &config.inherit_env);
+
+    let engine = make_engine(&config.engine)?;
+
+    let output = if cli.quiet_on_success {
+        Output::buffered(cli.color.enabled())
+    } else {
+        Output::stderr(cli.color.enabled())
+    };
+
+    let results_cache = config
+        .cache_results
+        .then(|| ResultsCache::new(cache_dir.join("results"), !options.no_cache));
+
+    let context = RunContext {
+        top_level,
+        work_dir,
+        cache_dir: &cache_dir,
+        env: &linter_env,
+        engine: &engine,
+        manifest: &manifest,
+        changed_lines: options.changed_lines,
+        output: &output,
+        sandbox_strict: cli.sandbox_strict,
+        results_cache: results_cache.as_ref(),
+        peak_memory: None,
+        file_hashes: None,
+        git_requests: options.git_requests,
+    };
+
+    check_any_files_match(
+        &context,
+        &config.linters,
+        &files,
+        listed,
+        options.error_on_empty,
+    )?;
+
+    // Empty in detached HEAD state, or outside a repo.
+    let branch = if options.outside_repo {
+        String::new()
+    } else {
+        git::git_current_branch(top_level)?.unwrap_or_default()
+    };
+
+    let pass = until_interrupted(run_linters(
+        &context,
+        &config.linters,
+        &files,
+        change_detection,
+        &branch,
+        config.text_normalization.as_ref(),
+    ))
+    .await?;
+    let mut failed = pass.failed;
+    let fixed_by = &pass.fixed_by;
+
+    // Files that several linters fixed may be being fought over.
+    let contested: Vec<git::FileInfo> = files
+        .iter()
+        .filter(|file| {
+            fixed_by
+                .get(&file.path)
+                .is_some_and(|names| names.len() > 1)
+        })
+        .cloned()
+        .collect();
+    if !contested.is_empty() {
+        until_interrupted(report_conflicts(
+            &context,
+            &config.linters,
+            &contested,
+            fixed_by,
+            config.text_normalization.as_ref(),
+        ))
+        .await?;
+    }
+
+    // If the fixes are stable, running the linters again changes nothing.
+    if options.check_idempotent && !fixed_by.is_empty() {
+        output.line("Running the linters again to check that their fixes are idempotent")?;
+        let second_pass = until_interrupted(run_linters(
+            &context,
+            &config.linters,
+            &files,
+            change_detection,
+            &branch,
+            config.text_normalization.as_ref(),
+        ))
+        .await?;
+        if !second_pass.fixed_by.is_empty() {
+            failed = true;
+            output.line(format_args!(
+                "Linter fixes are {}. These files changed again:",
+                output.paint("not idempotent", AnsiColors::Red)
+            ))?;
+            for (path, linters) in &second_pass.fixed_by {
+                output.line(format_args!("  {}: {}", path.display(), linters.join(", ")))?;
+            }
+        }
+    }
+
+    manifest.into_inner().unwrap().save(&cache_dir);
+    if let Some(results_cache) = &results_cache
+        && let Err(e) = results_cache.evict()
+    {
+        warn!("Failed to evict old results: {e:#}");
+    }
+
+    if options.format == ReportFormat::Json {
+        let report = Report {
+            passed: !failed,
+            linters: pass.results,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+
+    if failed {
+        bail!("Linting failed");
+    }
+
+    output.discard();
+    Ok(())
+}
+
+/// Warn if none of `linters` match any of `files`, since that probably means
+/// the config is wrong and nothing is being checked. If `error_on_empty` is
+/// set it's an error instead. `listed` is the number of files before the
+/// config's `include` was applied.
+fn check_any_files_match(
+    context: &RunContext<'_>,
+    linters: &[ConfigLinter],
+    files: &[git::FileInfo],
+    listed: usize,
+    error_on_empty: bool,
+) -> Result<()> {
+    for linter in linters {
+        if !linter_matching_files(context, files, linter)?.is_empty() {
+            return Ok(());
+        }
+    }
+    let reason = if listed == 0 {
+        "there are no files to lint".to_owned()
+    } else if files.is_empty() {
+        format!("the config's `include` (or `max_file_size`) excludes all {listed} files")
+    } else {
+        format!(
+            "none of their match expressions match any of the {} files",
+            files.len()
+        )
+    };
+    if error_on_empty {
+        bail!("No linters match any files: {reason}");
+    }
+    if listed == 0 {
+        info!("No files to lint");
+    } else {
+        warn!("No linters match any files: {reason}");
+    }
+    Ok(())
+}
+
+/// Error for when the user presses Ctrl-C.
+#[derive(Debug)]
+struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Exit code when interrupted, like shells use for SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Whether `until_interrupted()` is running, so the first Ctrl-C should stop
+/// the linters cleanly instead of exiting.
+static STOP_ON_INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+/// Notified when Ctrl-C is pressed while `STOP_ON_INTERRUPT` is set.
+static INTERRUPT: Notify = Notify::const_new();
+
+/// Handle Ctrl-C for the rest of the process. Once Tokio handles SIGINT the
+/// default action (exiting) is never restored, so this exits unless
+/// `until_interrupted()` is running. A second Ctrl-C always exits, e.g. if
+/// stopping the linters hangs.
+fn handle_interrupts() {
+    static STARTED: Once = Once::new();
+    STARTED.call_once(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if STOP_ON_INTERRUPT.swap(false, Ordering::SeqCst) {
+                    INTERRUPT.notify_waiters();
+                } else {
+                    eprintln!(
+                        "{}",
+                        "Interrupted".if_supports_color(Stream::Stderr, |t| t.red())
+                    );
+                    std::process::exit(INTERRUPTED_EXIT_CODE);
+                }
+            }
+        });
+    });
+}
+
+/// Run `future`, but stop it and return `Interrupted` if Ctrl-C is pressed.
+/// Dropping the linters' futures removes their sandboxes.
+/// Running linters yield regularly
+/// (see `make_engine()`), so they are stopped even if they never make a
+/// WASI call.
+async fn until_interrupted<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+    handle_interrupts();
+    let interrupted = INTERRUPT.notified();
+    tokio::pin!(interrupted);
+    // Register before setting the flag so the notification isn't missed.
+    interrupted.as_mut().enable();
+    STOP_ON_INTERRUPT.store(true, Ordering::SeqCst);
+    let result = tokio::select! {
+        result = future => result,
+        _ = interrupted => Err(Interrupted.into()),
+    };
+    STOP_ON_INTERRUPT.store(false, Ordering::SeqCst);
+    result
+}
+
+/// Result of running all of the linters once.
+struct Pass {
+    /// Whether any linter failed (and wasn't allowed to) or fixed files.
+    failed: bool,
+    /// Names of the linters that fixed each file.
+    fixed_by: BTreeMap<PathBuf, Vec<String>>,
+    /// Result of each linter that was run, in order.
+    results: Vec<LinterResult>,
+}
+
+/// The report printed by `--format json`.
+#[derive(Serialize)]
+struct Report {
+    passed: bool,
+    linters: Vec<LinterResult>,
+}
+
+#[derive(Serialize)]
+struct LinterResult {
+    name: String,
+    result: LinterOutcome,
+    /// Files the linter modified, relative to the repo root.
+    fixed: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+enum LinterOutcome {
+    Passed,
+    /// Not run because it passed last time with the same inputs.
+    CachedPass,
+    Failed,
+    /// Failed, but `allow_failure` is set.
+    AllowedFailure,
+    /// Not run because a dependency failed.
+    Skipped,
+}
+
+/// Keep the linters that have any of `tags` (if it isn't empty) and none of
+/// `not_tags`. It's an error if a tag isn't used by any of the linters, since
+/// it's probably a typo.
+fn filter_by_tags(
+    linters: &mut Vec<ConfigLinter>,
+    tags: &[String],
+    not_tags: &[String],
+) -> Result<()> {
+    for tag in tags.iter().chain(not_tags) {
+        if !linters.iter().any(|linter| linter.tags.contains(tag)) {
+            bail!("No linters in the config have the tag '{tag}'");
+        }
+    }
+    linters.retain(|linter| {
+        (tags.is_empty() || linter.tags.iter().any(|tag| tags.contains(tag)))
+            && !linter.tags.iter().any(|tag| not_tags.contains(tag))
+    });
+    Ok(())
+}
+
+/// Run each of `linters` over `files` in turn, printing the results.
+async fn run_linters(
+    context: &RunContext<'_>,
+    linters: &[ConfigLinter],
+    files: &[git::FileInfo],
+    change_detection: &ChangeDetection,
+    branch: &str,
+    normalization: Option<&TextNormalization>,
+) -> Result<Pass> {
+    let output = context.output;
+    let work_dir = context.work_dir;
+
+    let mut failed = false;
+    // Linters that failed, or were skipped because a dependency failed.
+    let mut failed_linters = BTreeSet::new();
+    let mut total = Throughput::default();
+    // Names of the linters that fixed each file.
+    let mut fixed_by: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    let mut results = Vec::new();
+
+    // The built-in normalisation runs first, so linters see (and can undo)
+    // its fixes. Like other linters, it fails if it changes anything.
+    if let Some(normalization) = normalization {
+        output.line(format_args!(
+            "Running linter: {} (built in)",
+            output.paint(text_normalization::NAME, AnsiColors::Blue)
+        ))?;
+        let fixed = normalize_files(work_dir, files, normalization)?;
+        if fixed.is_empty() {
+            output.line(format_args!(
+                "Linter {}",
+                output.paint("passed", AnsiColors::Green)
+            ))?;
+        } else {
+            failed = true;
+            output.line(format_args!(
+                "Linter {}",
+                output.paint("failed", AnsiColors::Red)
+            ))?;
+        }
+        for path in &fixed {
+            output.line(format_args!("  fixed: {}", path.display()))?;
+            fixed_by
+                .entry(path.clone())
+                .or_default()
+                .push(text_normalization::NAME.to_owned());
+        }
+        results.push(LinterResult {
+            name: text_normalization::NAME.to_owned(),
+            result: if fixed.is_empty() {
+                LinterOutcome::Passed
+            } else {
+                LinterOutcome::Failed
+            },
+            fixed,
+        });
+    }
+
+    // Files' Git object IDs are no longer valid once they are fixed.
+    let mut files = files.to_vec();
+    clear_object_ids(&mut files, results.iter().flat_map(|result| &result.fixed));
+
+    // Hashes are used to report which files were fixed, even when the diff
+    // is used to detect changes. Only files whose stat data changed are read
+    // again after each linter.
+    let mut hashes = hash_files(work_dir, &files.iter().collect::<Vec<_>>())?;
+
+    // Run the linters.
+    for linter in linters {
+        if let Some(branches) = &linter.branches
+            && !branches.is_match(branch)
+        {
+            info!(
+                "Skipping linter {} because branch '{branch}' doesn't match",
+                linter.name
+            );
+            continue;
+        }
+
+        output.line(format_args!(
+            "Running linter: {}",
+            output.paint(&linter.name, AnsiColors::Blue)
+        ))?;
+        if let Some(dependency) = linter
+            .depends_on
+            .iter()
+            .find(|dependency| failed_linters.contains(dependency.as_str()))
+        {
+            output.line(format_args!(
+                "Linter {}: dependency {dependency} failed",
+                output.paint("skipped", AnsiColors::Yellow)
+            ))?;
+            failed_linters.insert(linter.name.clone());
+            results.push(LinterResult {
+                name: linter.name.clone(),
+                result: LinterOutcome::Skipped,
+                fixed: Vec::new(),
+            });
+            continue;
+        }
+        let linter_files = linter_matching_files(context, &files, linter)?;
+        // Only diff the linter's files, so that other unstaged changes don't
+        // affect the result.
+        let linter_paths: Vec<&Path> = linter_files
+            .iter()
+            .map(|file| file.path.as_path())
+            .collect();
+        let diff = match change_detection {
+            ChangeDetection::GitDiff => git_diff_unstaged(work_dir, &linter_paths)?,
+            ChangeDetection::FileHashes => Vec::new(),
+        };
+        let start = Instant::now();
+        let peak_memory = PeakMemory::default();
+        let known: Recorded = files
+            .iter()
+            .map(|file| file.path.clone())
+            .zip(hashes.iter().cloned())
+            .collect();
+        let linter_context = RunContext {
+            peak_memory: Some(&peak_memory),
+            file_hashes: Some(&known),
+            ..*context
+        };
+        let status = run_single_linter_with_retries(&linter_context, &files, linter).await?;
+        let throughput = Throughput {
+            files: linter_files.len(),
+            bytes: linter_files.iter().map(|file| file.size).sum(),
+            duration: start.elapsed(),
+            peak_memory: peak_memory.get(),
+        };
+        total += throughput;
+        let after = rehash_files(work_dir, &files.iter().collect::<Vec<_>>(), &hashes)?;
+        let changed: BTreeSet<PathBuf> = files
+            .iter()
+            .zip(hashes.iter().zip(&after))
+            .filter(|(_, (before, after))| before != after)
+            .map(|(file, _)| file.path.clone())
+            .collect();
+        hashes = after;
+        let fixed: Vec<PathBuf> = linter_paths
+            .iter()
+            .filter(|path| changed.contains(**path))
+            .map(|path| path.to_path_buf())
+            .collect();
+
+        let modified = match change_detection {
+            ChangeDetection::GitDiff => git_diff_unstaged(work_dir, &linter_paths)? != diff,
+            ChangeDetection::FileHashes => !fixed.is_empty(),
+        };
+
+        let linter_failed = !status.passed() || modified;
+        if linter_failed {
+            failed_linters.insert(linter.name.clone());
+        }
+        let outcome = if linter_failed && linter.allow_failure {
+            output.line(format_args!(
+                "Linter {}",
+                output.paint("failed (allowed)", AnsiColors::Yellow)
+            ))?;
+            LinterOutcome::AllowedFailure
+        } else if linter_failed {
+            failed = true;
+            output.line(format_args!(
+                "Linter {}",
+                output.paint("failed", AnsiColors::Red)
+            ))?;
+            LinterOutcome::Failed
+        } else if status == LinterStatus::CachedPass {
+            output.line(format_args!(
+                "Linter {}",
+                output.paint("cached pass", AnsiColors::Green)
+            ))?;
+            LinterOutcome::CachedPass
+        } else {
+            output.line(format_args!(
+                "Linter {}",
+                output.paint("passed", AnsiColors::Green)
+            ))?;
+            LinterOutcome::Passed
+        };
+        for path in &fixed {
+            output.line(format_args!("  fixed: {}", path.display()))?;
+            fixed_by
+                .entry(path.clone())
+                .or_default()
+                .push(linter.name.clone());
+        }
+        output.line(format_args!("  {throughput}"))?;
+        results.push(LinterResult {
+            name: linter.name.clone(),
+            result: outcome,
+            fixed,
+        });
+        clear_object_ids(&mut files, &changed);
+    }
+
+    output.line(format_args!("Total: {total}"))?;
+
+    Ok(Pass {
+        failed,
+        fixed_by,
+        results,
+    })
+}
+
+/// Forget the Git object IDs of `changed` files, since their contents no
+/// longer match.
+fn clear_object_ids<'a>(
+    files: &mut [git::FileInfo],
+    changed: impl IntoIterator<Item = &'a PathBuf>,
+) {
+    let changed: BTreeSet<&PathBuf> = changed.into_iter().collect();
+    for file in files {
+        if changed.contains(&file.path) {
+            file.object_id = None;
+        }
+    }
+}
+
+/// Run the linters (including the built-in `normalization`) that fixed
+/// `files` before another linter fixed them again, and report the files
+/// that they change again. If the linters agreed (and are idempotent) this
+/// would change nothing, so those linters conflict and running Nit again
+/// won't help.
+async fn report_conflicts(
+    context: &RunContext<'_>,
+    linters: &[ConfigLinter],
+    files: &[git::FileInfo],
+    fixed_by: &BTreeMap<PathBuf, Vec<String>>,
+    normalization: Option<&TextNormalization>,
+) -> Result<()> {
+    // The linters' output has already been shown.
+    let output = Output::buffered(context.output.color());
+    // Skipped linters wouldn't show whether they change the files again.
+    let quiet_context = RunContext {
+        output: &output,
+        results_cache: None,
+        peak_memory: None,
+        file_hashes: None,
+        ..*context
+    };
+
+    // The last linter to fix a file left it how it wants it, so only earlier
+    // ones can disagree with a later one.
+    let files_to_refix = |name: &str| -> Vec<git::FileInfo> {
+        files
+            .iter()
+            .filter(|file| {
+                let names = &fixed_by[&file.path];
+                names[..names.len() - 1].iter().any(|n| n == name)
+            })
+            .cloned()
+            .collect()
+    };
+
+    let mut refixed = BTreeSet::new();
+    if let Some(normalization) = normalization {
+        let normalized = files_to_refix(text_normalization::NAME);
+        if !normalized.is_empty() {
+            let matching: Vec<&git::FileInfo> = normalized.iter().collect();
+            let before = hash_files(context.work_dir, &matching)?;
+            normalize_files(context.work_dir, &normalized, normalization)?;
+            let after = rehash_files(context.work_dir, &matching, &before)?;
+            refixed.extend(changed_paths(&matching, &before, &after));
+        }
+    }
+    for linter in linters {
+        let linter_files = files_to_refix(&linter.name);
+        if linter_files.is_empty() {
+            continue;
+        }
+        let matching = linter_matching_files(context, &linter_files, linter)?;
+        let before = hash_files(context.work_dir, &matching)?;
+        run_single_linter(&quiet_context, &linter_files, linter).await?;
+        let after = rehash_files(context.work_dir, &matching, &before)?;
+        refixed.extend(changed_paths(&matching, &before, &after));
+    }
+    output.discard();
+
+    for path in refixed {
+        context.output.line(format_args!(
+            "Linters {}: {} keep changing {}",
+            context.output.paint("conflict", AnsiColors::Red),
+            fixed_by[&path].join(", "),
+            path.display()
+        ))?;
+    }
+    Ok(())
+}
+
+/// Paths of `files` whose hashes changed from `before` to `after`.
+fn changed_paths(
+    files: &[&git::FileInfo],
+    before: &[FileHash],
+    after: &[FileHash],
+) -> Vec<PathBuf> {
+    files
+        .iter()
+        .zip(before.iter().zip(after))
+        .filter(|(_, (before, after))| before != after)
+        .map(|(file, _)| file.path.clone())
+        .collect()
+}
+
+async fn subcommand_check_stdin(cli: &Cli, args: &CheckStdinArgs) -> Result<()> {
+    let top_level = git::git_top_level()?;
+    let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+    let cache_dir = cli.cache_dir(&top_level, &config)?;
+
+    let mut contents = Vec::new();
+    tokio::io::stdin().read_to_end(&mut contents).await?;
+
+    // Get the path relative to the repo root, so that globs etc. match.
+    // `..` is removed first so it can't point outside the repo (or the
+    // temporary directory the linters run in).
+    let path = normalise_lexically(&std::env::current_dir()?.join(&args.path));
+    let relative_path = path
+        .strip_prefix(&top_level)
+        .with_context(|| anyhow!("{} is not in the repository", args.path.display()))?
+        .to_owned();
+
+    let mut files = vec![git::classify_contents(
+        relative_path.clone(),
+        &contents,
+        false,
+        contents.len() as u64,
+        &config.generated_markers,
+    )];
+    retain_matching_files(&mut files, &config.include);
+    if let Some(max_file_size) = config.max_file_size {
+        retain_files_up_to_size(&mut files, max_file_size);
+    }
+
+    let manifest = Mutex::new(Manifest::load(&cache_dir));
+    fetch_linters(
+        &config.linters,
+        &cache_dir,
+        &manifest,
+        cli.download_jobs(&config),
+    )
+    .await?;
+
+    // Linters run in a throwaway directory containing only this file. It's
+    // removed when this returns, even on error.
+    let temp_dir = tempfile::Builder::new()
+        .prefix("nit-stdin-")
+        .tempdir()
+        .context("Failed to create a temporary directory")?;
+    let work_dir = temp_dir.path();
+    let file_path = work_dir.join(&relative_path);
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(&file_path, &contents).await?;
+
+    let linter_env = linter_env_with_inherited(&cli.color, &config.inherit_env);
+    let engine = make_engine(&config.engine)?;
+    let output = Output::stderr(cli.color.enabled());
+    let context = RunContext {
+        top_level: &top_level,
+        work_dir,
+        cache_dir: &cache_dir,
+        env: &linter_env,
+        engine: &engine,
+        manifest: &manifest,
+        changed_lines: None,
+        output: &output,
+        sandbox_strict: cli.sandbox_strict,
+        results_cache: None,
+        peak_memory: None,
+        file_hashes: None,
+        git_requests: GitRequestsTarget::Report,
+    };
+
+    let mut result = Ok(true);
+    if !files.is_empty() {
+        for linter in &config.linters {
+            if linter_matching_files(&context, &files, linter)?.is_empty() {
+                continue;
+            }
+            result = run_single_linter_with_retries(&context, &files, linter)
+                .await
+                .map(LinterStatus::passed);
+            if linter.allow_failure && matches!(result, Ok(false)) {
+                output.line(format_args!(
+                    "Linter {} {}",
+                    linter.name,
+                    output.paint("failed (allowed)", AnsiColors::Yellow)
+                ))?;
+                result = Ok(true);
+            }
+            if !matches!(result, Ok(true)) {
+                break;
+            }
+        }
+    }
+
+    manifest.into_inner().unwrap().save(&cache_dir);
+
+    // Output the file even if linting failed, so editors don't lose the
+    // contents if they replace the buffer with our output.
+    let fixed = fs::read(&file_path).await;
+    drop(temp_dir);
+    tokio::io::stdout().write_all(&fixed?).await?;
+
+    if !result? {
+        bail!("Linting failed");
+    }
+    Ok(())
+}
+
+async fn subcommand_bench(cli: &Cli, args: &BenchArgs) -> Result<()> {
+    if args.iterations == 0 {
+        bail!("--iterations must be at least 1");
+    }
+
+    let top_level = git::git_top_level()?;
+    let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+    let cache_dir = cli.cache_dir(&top_level, &config)?;
+
+    let mut files = git::git_tree_files(&top_level, "HEAD", &config.generated_markers)?;
+    retain_matching_files(&mut files, &config.include);
+    if let Some(max_file_size) = config.max_file_size {
+        retain_files_up_to_size(&mut files, max_file_size);
+    }
+
+    let manifest = Mutex::new(Manifest::load(&cache_dir));
+    fetch_linters(
+        &config.linters,
+        &cache_dir,
+        &manifest,
+        cli.download_jobs(&config),
+    )
+    .await?;
+
+    let linter_env = linter_env_with_inherited(&cli.color, &config.inherit_env);
+    let engine = make_engine(&config.engine)?;
+    let context = RunContext {
+        top_level: &top_level,
+        work_dir: &top_level,
+        cache_dir: &cache_dir,
+        env: &linter_env,
+        engine: &engine,
+        manifest: &manifest,
+        changed_lines: None,
+        output: &Output::stderr(cli.color.enabled()),
+        sandbox_strict: cli.sandbox_strict,
+        results_cache: None,
+        peak_memory: None,
+        file_hashes: None,
+        git_requests: GitRequestsTarget::Repo(&top_level),
+    };
+
+    let mut results = Vec::new();
+
+    for linter in &config.linters {
+        eprintln!(
+            "Benchmarking linter: {}",
+            linter.name.if_supports_color(Stream::Stderr, |t| t.blue())
+        );
+        let num_files = linter_matching_files(&context, &files, linter)?.len();
+
+        // The first run isn't timed because it may include compiling the
+        // linter, and fixing files. We only care about timing so failures
+        // are ignored.
+        run_single_linter(&context, &files, linter).await?;
+
+        let mut durations = Vec::new();
+        for _ in 0..args.iterations {
+            let start = Instant::now();
+            run_single_linter(&context, &files, linter).await?;
+            durations.push(start.elapsed());
+        }
+        results.push(BenchResult::new(linter.name.clone(), num_files, durations));
+    }
+
+    manifest.into_inner().unwrap().save(&cache_dir);
+
+    print_bench_results(&mut results);
+    Ok(())
+}
+
+async fn subcommand_show_metadata(_cli: &Cli, args: &ShowMetadataArgs) -> Result<()> {
+    let metadata = read_metadata(&args.file)?;
+    println!("{metadata:?}");
+    Ok(())
+}
+
+async fn subcommand_set_metadata(cli: &Cli, args: &SetMetadataArgs) -> Result<()> {
     let mut bytes = fs::read(&args.file).await?;
-    let metadata_bytes = fs::read(&args.metadata).await?;
+
+    // Accept JSON5 (e.g. from `metadata-template`) but embed plain JSON.
+    let metadata_json = fs::read_to_string(&args.metadata).await?;
+    let metadata: serde_json::Value = serde_json5::from_str(&metadata_json)
+        .with_context(|| anyhow!("Parsing {}", args.metadata.display()))?;
+    let metadata_bytes = serde_json::to_vec(&metadata)?;
+    parse_metadata(&metadata_bytes, &args.file)?;
 
     // Find the existing metadata sections.
     let (section_ranges, _) = find_custom_sections(&bytes, "nit_metadata")?;
@@ -376,6 +2443,16 @@ async fn subcommand_set_metadata(_cli: &Cli, args: &SetMetadataArgs) -> Result<(
     let metadata_section = make_custom_section("nit_metadata", &metadata_bytes);
     bytes.extend_from_slice(&metadata_section);
 
+    if cli.dry_run {
+        println!(
+            "Would write {} ({} byte metadata section, {} bytes total)",
+            args.file.display(),
+            metadata_section.len(),
+            bytes.len()
+        );
+        return Ok(());
+    }
+
     fs::write(&args.file, bytes).await?;
 
     Ok(())
@@ -383,15 +2460,22 @@ async fn subcommand_set_metadata(_cli: &Cli, args: &SetMetadataArgs) -> Result<(
 
 async fn subcommand_pre_commit(cli: &Cli) -> Result<()> {
     // pre-commit takes no arguments and is run just before commit, so we
-    // lint the staged files.
-    // TODO (0.1): We should check that these files are clean too since we
-    // are actually linting the on-disk files. Not sure what pre-commit does.
+    // lint the staged files. We use a checkout of the index so that unstaged
+    // changes don't affect the result.
     let top_level = git::git_top_level()?;
-    let config = find_and_read_config(&top_level, &cli.config)?;
+    let _lock = RunLock::acquire(&top_level, !cli.no_wait)?;
+    let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
 
-    let files = git::git_staged_files(&top_level)?;
-
-    run(top_level, config, files).await
+    let checkout = TempCheckout::of_index(&top_level, &config.generated_markers)?;
+    run_in_checkout(
+        cli,
+        top_level,
+        config,
+        checkout,
+        !cli.dry_run,
+        RunOptions::default(),
+    )
+    .await
 }
 
 async fn subcommand_pre_push(cli: &Cli, args: &PrePushArgs) -> Result<()> {
@@ -404,20 +2488,450 @@ async fn subcommand_pre_push(cli: &Cli, args: &PrePushArgs) -> Result<()> {
     //
     // Pre-commit uses this to find a list of files that have changed in the
     // push and then lints those files, assuming that we have the local
-    // ref checked out. For now (without a VFS) we will do the same but
-    // also verify we are pushing the current ref and the files are clean.
-    //
-    // TODO (0.1): Implement pre-push.
-    todo!()
+    // ref checked out. We do the same for refs that are checked out, but
+    // lint other commits in a temporary checkout of them.
+    let top_level = git::git_top_level()?;
+    let _lock = RunLock::acquire(&top_level, !cli.no_wait)?;
+
+    let mut stdin = String::new();
+    tokio::io::stdin().read_to_string(&mut stdin).await?;
+    let refs = parse_pushed_refs(&stdin);
+    let remote = remote_name(&top_level, &args.remote, &args.url)?;
+    if remote.is_none() {
+        info!(
+            "{} isn't a configured remote, so commits on any remote are treated as already pushed",
+            args.remote
+        );
+    }
+    let mut pushed = pushed_files(&top_level, remote.as_deref(), &refs)?;
+    if pushed.is_empty() {
+        info!("No changed files to lint");
+        return Ok(());
+    }
+
+    let head = git::git_rev_parse(&top_level, "HEAD")?;
+    let mut failed_commits = Vec::new();
+    if let Some(paths) = pushed.remove(&head) {
+        let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+        let mut files = git::git_tree_files(&top_level, "HEAD", &config.generated_markers)?;
+        files.retain(|file| paths.contains(&file.path));
+
+        let result = if cli.dry_run {
+            let checkout = TempCheckout::of_files(&top_level, files)?;
+            run_in_checkout(
+                cli,
+                top_level.clone(),
+                config,
+                checkout,
+                false,
+                RunOptions::default(),
+            )
+            .await
+        } else {
+            run(
+                cli,
+                &top_level,
+                &top_level,
+                config,
+                files,
+                &ChangeDetection::GitDiff,
+                RunOptions {
+                    git_requests: GitRequestsTarget::Repo(&top_level),
+                    ..RunOptions::default()
+                },
+            )
+            .await
+        };
+        match result {
+            Err(e) if e.is::<Interrupted>() => return Err(e),
+            Err(e) if pushed.is_empty() => return Err(e),
+            Err(e) => {
+                warn!("Commit {head} failed: {e:?}");
+                failed_commits.push(head.clone());
+            }
+            Ok(()) => {}
+        }
+    }
+
+    // The working directory has different contents, so these are linted
+    // as committed. Fixes can't be applied to a commit.
+    for (commit, paths) in &pushed {
+        info!("Checking pushed commit {commit}, which isn't checked out");
+        // `run()` consumes the config.
+        let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+        let paths: Vec<PathBuf> = paths.iter().cloned().collect();
+        let checkout =
+            TempCheckout::of_commit(&top_level, commit, &paths, &config.generated_markers)?;
+        let result = run_in_checkout(
+            cli,
+            top_level.clone(),
+            config,
+            checkout,
+            false,
+            RunOptions::default(),
+        )
+        .await;
+        match result {
+            Err(e) if e.is::<Interrupted>() => return Err(e),
+            Err(e) => {
+                warn!("Commit {commit} failed: {e:?}");
+                failed_commits.push(commit.clone());
+            }
+            Ok(()) => {}
+        }
+    }
+
+    if !failed_commits.is_empty() {
+        bail!(
+            "{} pushed commits failed linting:\n{}",
+            failed_commits.len(),
+            failed_commits.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Where the commit message is put for commit message linters, so their
+/// `default_match` is the same wherever Git keeps it (e.g. in a worktree).
+const COMMIT_MSG_PATH: &str = ".git/COMMIT_EDITMSG";
+
+async fn subcommand_commit_msg(cli: &Cli, args: &CommitMsgArgs) -> Result<()> {
+    // commit-msg gets one argument, the file containing the proposed commit
+    // message. The hook may edit it, so fixes are written back to it.
+    let top_level = git::git_top_level()?;
+    let _lock = RunLock::acquire(&top_level, !cli.no_wait)?;
+    let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+
+    let contents = fs::read(&args.file)
+        .await
+        .with_context(|| anyhow!("Failed to read {}", args.file.display()))?;
+    let files = vec![git::classify_contents(
+        COMMIT_MSG_PATH.into(),
+        &contents,
+        false,
+        contents.len() as u64,
+        &config.generated_markers,
+    )];
+
+    // It's removed when this returns, even on error.
+    let temp_dir = tempfile::Builder::new()
+        .prefix("nit-commit-msg-")
+        .tempdir()
+        .context("Failed to create a temporary directory")?;
+    let work_dir = temp_dir.path();
+    let message_path = work_dir.join(COMMIT_MSG_PATH);
+    fs::create_dir_all(message_path.parent().unwrap()).await?;
+    fs::write(&message_path, &contents).await?;
+
+    let result = run(
+        cli,
+        &top_level,
+        work_dir,
+        config,
+        files,
+        &ChangeDetection::FileHashes,
+        RunOptions {
+            stage: Stage::CommitMsg,
+            ..RunOptions::default()
+        },
+    )
+    .await;
+    if result.as_ref().is_err_and(|e| e.is::<Interrupted>()) {
+        return result;
+    }
+
+    let fixed = fs::read(&message_path).await?;
+    if fixed != contents {
+        if cli.dry_run {
+            info!("Would fix {}", args.file.display());
+        } else {
+            fs::write(&args.file, fixed).await?;
+            info!("Fixed {}", args.file.display());
+        }
+    }
+    result
+}
+
+async fn subcommand_verify_history(cli: &Cli, args: &VerifyHistoryArgs) -> Result<()> {
+    let top_level = git::git_top_level()?;
+    let _lock = RunLock::acquire(&top_level, !cli.no_wait)?;
+    let commits = git::git_rev_list(&top_level, &args.range)?;
+    if commits.is_empty() {
+        info!("No commits in {}", args.range);
+        return Ok(());
+    }
+
+    let mut failed_commits = Vec::new();
+    for commit in &commits {
+        // `run()` consumes the config.
+        let config = find_and_read_config(&top_level, &cli.config, cli.strict_config)?;
+        let paths = git::git_commit_paths(&top_level, commit)?;
+        if paths.is_empty() {
+            // Merges compared to their first parent can be empty, e.g. if the
+            // other branch was already merged.
+            if git::git_commit_exists(&top_level, &format!("{commit}^2"))? {
+                warn!("Skipping merge commit {commit} because it doesn't change any files");
+            } else {
+                info!("Skipping commit {commit} because it doesn't change any files");
+            }
+            continue;
+        }
+        info!("Checking commit {commit}");
+
+        // Only the changed files are checked out, so this doesn't get slower
+        // with the size of the repo.
+        let checkout =
+            TempCheckout::of_commit(&top_level, commit, &paths, &config.generated_markers)?;
+        let files = checkout.files().to_vec();
+
+        // Fixes are discarded with the checkout.
+        let result = run(
+            cli,
+            &top_level,
+            checkout.dir(),
+            config,
+            files,
+            &ChangeDetection::FileHashes,
+            RunOptions::default(),
+        )
+        .await;
+        match result {
+            Err(e) if e.is::<Interrupted>() => return Err(e),
+            Err(e) => {
+                warn!("Commit {commit} failed: {e:?}");
+                failed_commits.push(commit.as_str());
+            }
+            Ok(()) => {}
+        }
+    }
+
+    if !failed_commits.is_empty() {
+        bail!(
+            "{} of {} commits failed linting:\n{}",
+            failed_commits.len(),
+            commits.len(),
+            failed_commits.join("\n")
+        );
+    }
+    info!("All {} commits passed", commits.len());
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
+    use crate::ColorOutput;
     use crate::config::Config;
+    use crate::output::Output;
+    use crate::{LinterOutcome, LinterResult, Report};
+    use crate::{linter_env_with_inherited, normalise_lexically};
+    use owo_colors::AnsiColors;
+
+    #[test]
+    fn test_normalise_lexically() {
+        assert_eq!(
+            normalise_lexically(std::path::Path::new("/repo/sub/../x.rs")),
+            std::path::Path::new("/repo/x.rs")
+        );
+        assert_eq!(
+            normalise_lexically(std::path::Path::new("/repo/./sub/../../outside")),
+            std::path::Path::new("/outside")
+        );
+    }
+
+    #[test]
+    fn test_parse_files0() {
+        assert_eq!(
+            crate::parse_files0(b"src/a b.rs\0new\nline.rs\0last.rs").unwrap(),
+            [
+                std::path::PathBuf::from("src/a b.rs"),
+                "new\nline.rs".into(),
+                "last.rs".into()
+            ]
+        );
+        assert!(crate::parse_files0(b"").unwrap().is_empty());
+        assert!(crate::parse_files0(b"\xff\0").is_err());
+    }
+
+    #[test]
+    fn test_common_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::create_dir_all(dir.path().join("a/c")).unwrap();
+        std::fs::write(dir.path().join("a/b/x.rs"), "").unwrap();
+        std::fs::write(dir.path().join("a/c/y.rs"), "").unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        let common_dir = |paths: &[&str]| {
+            let paths: Vec<_> = paths.iter().map(|path| dir.path().join(path)).collect();
+            crate::common_dir(&paths)
+        };
+        assert_eq!(common_dir(&["a/b/x.rs"]).unwrap(), root.join("a/b"));
+        assert_eq!(
+            common_dir(&["a/b/x.rs", "a/c/y.rs"]).unwrap(),
+            root.join("a")
+        );
+        assert_eq!(common_dir(&["a/c", "a/c/y.rs"]).unwrap(), root.join("a/c"));
+        assert!(common_dir(&["missing/z.rs"]).is_err());
+        assert!(common_dir(&[]).is_err());
+    }
+
+    #[test]
+    fn test_config_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = crate::find_and_read_config(dir.path(), &None, false).unwrap_err();
+        assert!(err.is::<crate::ConfigError>());
+
+        let path = dir.path().join(".nit.json5");
+        std::fs::write(&path, "{ linters: [").unwrap();
+        let err = crate::find_and_read_config(dir.path(), &None, false).unwrap_err();
+        assert!(err.is::<crate::ConfigError>());
+    }
 
     #[test]
     fn verify_sample_config() {
         let sample_config = include_str!("../sample_config.json5");
-        let _config: Config = serde_json5::from_str(&sample_config).unwrap();
+        let _config: Config = serde_json5::from_str(sample_config).unwrap();
+    }
+
+    #[test]
+    fn verify_metadata_template() {
+        let template = include_str!("../metadata_template.json5");
+        let json: serde_json::Value = serde_json5::from_str(template).unwrap();
+        crate::metadata::parse_metadata(
+            &serde_json::to_vec(&json).unwrap(),
+            std::path::Path::new("template.wasm"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_filter_by_tags() {
+        let config = r#"{
+            linters: [
+                { name: "a", location: { local: "a.wasm" }, tags: ["fast", "format"] },
+                { name: "b", location: { local: "b.wasm" }, tags: ["slow"] },
+                { name: "c", location: { local: "c.wasm" } },
+            ],
+        }"#;
+        let filter = |tags: &[&str], not_tags: &[&str]| {
+            let mut linters = serde_json5::from_str::<Config>(config).unwrap().linters;
+            let tags: Vec<String> = tags.iter().map(|&tag| tag.to_owned()).collect();
+            let not_tags: Vec<String> = not_tags.iter().map(|&tag| tag.to_owned()).collect();
+            crate::filter_by_tags(&mut linters, &tags, &not_tags)
+                .map(|()| linters.into_iter().map(|l| l.name).collect::<Vec<_>>())
+        };
+        assert_eq!(filter(&[], &[]).unwrap(), ["a", "b", "c"]);
+        assert_eq!(filter(&["fast", "slow"], &[]).unwrap(), ["a", "b"]);
+        assert_eq!(filter(&[], &["slow"]).unwrap(), ["a", "c"]);
+        assert_eq!(
+            filter(&["format"], &["fast"]).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(filter(&["fsat"], &[]).is_err());
+    }
+
+    #[test]
+    fn test_is_valid_crate_name() {
+        assert!(crate::is_valid_crate_name("lint_foo"));
+        assert!(crate::is_valid_crate_name("lint-foo2"));
+        assert!(!crate::is_valid_crate_name(""));
+        assert!(!crate::is_valid_crate_name("2lint"));
+        assert!(!crate::is_valid_crate_name("lint/foo"));
+    }
+
+    #[test]
+    fn verify_starter_config() {
+        let starter_config = include_str!("../starter_config.json5");
+        let _config: Config = serde_json5::from_str(starter_config).unwrap();
+    }
+
+    #[test]
+    fn printed_config_round_trips() {
+        let sample_config = include_str!("../sample_config.json5");
+        let config: Config = serde_json5::from_str(sample_config).unwrap();
+        let printed = serde_json::to_string_pretty(&config).unwrap();
+        let reparsed: Config = serde_json5::from_str(&printed).unwrap();
+        assert_eq!(printed, serde_json::to_string_pretty(&reparsed).unwrap());
+    }
+
+    #[test]
+    fn always_color_is_forced() {
+        // Stderr isn't a terminal in tests, but `always` still forces colour.
+        let color = ColorOutput::Always.resolve();
+        assert!(color.enabled());
+        let styled = Output::buffered(color.enabled()).paint("failed", AnsiColors::Red);
+        assert!(styled.starts_with("\x1b["));
+        assert!(matches!(
+            ColorOutput::Always.log_style(),
+            env_logger::WriteStyle::Always
+        ));
+        let env = ColorOutput::Always.linter_env();
+        assert!(env.contains(&("CLICOLOR_FORCE".to_string(), "1".to_string())));
+    }
+
+    #[test]
+    fn test_linter_env_with_inherited() {
+        // Cargo sets this when it runs the tests.
+        let inherit_env = [
+            "NIT_COLOR".to_owned(),
+            "NIT_TEST_UNSET_VARIABLE".to_owned(),
+            "CARGO_PKG_NAME".to_owned(),
+        ];
+        let mut expected = ColorOutput::Never.linter_env();
+        expected.push((
+            "CARGO_PKG_NAME".to_owned(),
+            env!("CARGO_PKG_NAME").to_owned(),
+        ));
+        assert_eq!(
+            linter_env_with_inherited(&ColorOutput::Never, &inherit_env),
+            expected
+        );
+    }
+
+    #[test]
+    fn never_color_sets_no_color() {
+        let env = ColorOutput::Never.linter_env();
+        assert!(env.contains(&("NO_COLOR".to_string(), "1".to_string())));
+        assert!(
+            !ColorOutput::Always
+                .linter_env()
+                .iter()
+                .any(|(k, _)| k == "NO_COLOR")
+        );
+    }
+
+    #[test]
+    fn test_json_report() {
+        let report = Report {
+            passed: false,
+            linters: vec![
+                LinterResult {
+                    name: "fmt".to_owned(),
+                    result: LinterOutcome::AllowedFailure,
+                    fixed: vec!["src/a.rs".into()],
+                },
+                LinterResult {
+                    name: "check".to_owned(),
+                    result: LinterOutcome::Skipped,
+                    fixed: Vec::new(),
+                },
+                LinterResult {
+                    name: "clippy".to_owned(),
+                    result: LinterOutcome::CachedPass,
+                    fixed: Vec::new(),
+                },
+            ],
+        };
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "passed": false,
+                "linters": [
+                    { "name": "fmt", "result": "allowed_failure", "fixed": ["src/a.rs"] },
+                    { "name": "check", "result": "skipped", "fixed": [] },
+                    { "name": "clippy", "result": "cached_pass", "fixed": [] },
+                ],
+            })
+        );
     }
 }
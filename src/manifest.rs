@@ -0,0 +1,252 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metadata::{NitMetadata, parse_metadata, read_metadata_section, read_sidecar_metadata},
+    unique_filename::unique_filename,
+};
+
+/// Stat info used to tell if a file has changed since we last looked at it.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+struct FileStamp {
+    mtime: SystemTime,
+    size: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self {
+            mtime: metadata.modified().ok()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ManifestEntry {
+    stamp: FileStamp,
+    /// Hash of the file, if it has been verified.
+    hash: Option<String>,
+    /// Raw contents of the `nit_metadata` section, if it has been read.
+    metadata: Option<String>,
+}
+
+/// Cache of things we know about linter binaries (hashes and metadata), so
+/// we don't need to re-read and re-hash whole binaries on every run. Entries
+/// are invalidated if the file's mtime or size changes.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Manifest {
+    entries: BTreeMap<PathBuf, ManifestEntry>,
+    /// Raw contents of the `nit_metadata` section of verified binaries, by
+    /// their hash. Unlike `entries` this is still valid when the binary
+    /// isn't in the cache (e.g. before a linter is downloaded again), since
+    /// the hash identifies the contents.
+    #[serde(default)]
+    metadata_by_hash: BTreeMap<String, String>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Manifest {
+    fn path(cache_dir: &Path) -> PathBuf {
+        cache_dir.join("manifest.json")
+    }
+
+    /// Load the manifest from the cache directory. If it doesn't exist or
+    /// can't be read an empty manifest is returned; it's only a cache.
+    pub fn load(cache_dir: &Path) -> Self {
+        let path = Self::path(cache_dir);
+        match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                debug!("Ignoring invalid manifest {}: {e}", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Save the manifest to the cache directory if it has changed. It's only
+    /// a cache, so if that fails (e.g. because the cache directory is
+    /// read-only) a warning is logged and the next run re-reads the binaries.
+    pub fn save(&self, cache_dir: &Path) {
+        if !self.dirty {
+            return;
+        }
+        let path = Self::path(cache_dir);
+        let tmpfile = path.with_file_name(unique_filename("tmp-", ".json"));
+        let result = (|| -> Result<()> {
+            std::fs::write(&tmpfile, serde_json::to_vec(self)?)
+                .with_context(|| anyhow!("Writing manifest {}", tmpfile.display()))?;
+            std::fs::rename(&tmpfile, &path)
+                .with_context(|| anyhow!("Writing manifest {}", path.display()))?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&tmpfile);
+            warn!("Failed to save the linter manifest: {e:#}");
+        }
+    }
+
+    /// Get the entry for `path` if it is still valid, or a new empty entry.
+    fn entry(&mut self, path: &Path) -> Option<&mut ManifestEntry> {
+        let stamp = FileStamp::of(path)?;
+        let valid = self.entries.get(path).is_some_and(|e| e.stamp == stamp);
+        if !valid {
+            self.entries.insert(
+                path.to_owned(),
+                ManifestEntry {
+                    stamp,
+                    hash: None,
+                    metadata: None,
+                },
+            );
+            self.dirty = true;
+        }
+        self.entries.get_mut(path)
+    }
+
+    /// Get the verified hash of `path`, if it hasn't changed since it was
+    /// recorded with `set_hash()`.
+    pub fn hash(&mut self, path: &Path) -> Option<String> {
+        self.entry(path)?.hash.clone()
+    }
+
+    /// Record the verified hash of `path`.
+    pub fn set_hash(&mut self, path: &Path, hash: String) {
+        if let Some(entry) = self.entry(path) {
+            entry.hash = Some(hash);
+            self.dirty = true;
+        }
+    }
+
+    /// Get the hash of `path` (like `fetch::file_binary_hash()`), hashing and
+    /// recording it if it isn't known.
+    pub fn content_hash(&mut self, path: &Path) -> Result<String> {
+        if let Some(hash) = self.hash(path) {
+            return Ok(hash);
+        }
+        let contents =
+            std::fs::read(path).with_context(|| anyhow!("Failed to read {}", path.display()))?;
+        let hash = blake3::hash(&contents).to_hex().to_string();
+        self.set_hash(path, hash.clone());
+        Ok(hash)
+    }
+
+    /// Read the metadata for a linter, using the cached copy if the file
+    /// hasn't changed.
+    pub fn metadata(&mut self, wasm_path: &Path) -> Result<NitMetadata> {
+        if let Some(entry) = self.entry(wasm_path)
+            && let Some(section) = &entry.metadata
+        {
+            let metadata = parse_metadata(section.as_bytes(), wasm_path)?;
+            let section = section.clone();
+            self.record_metadata_by_hash(wasm_path, section);
+            return Ok(metadata);
+        }
+
+        let Some(section) = read_metadata_section(wasm_path)? else {
+            // Sidecar metadata isn't cached because it can change without
+            // the wasm file changing.
+            return read_sidecar_metadata(wasm_path);
+        };
+        let metadata = parse_metadata(&section, wasm_path)?;
+
+        // Metadata is JSON so it must be UTF-8.
+        if let (Some(entry), Ok(section)) = (self.entry(wasm_path), String::from_utf8(section)) {
+            entry.metadata = Some(section.clone());
+            self.dirty = true;
+            self.record_metadata_by_hash(wasm_path, section);
+        }
+        Ok(metadata)
+    }
+
+    /// Remember the metadata section of `wasm_path` by its hash, if it has
+    /// been verified.
+    fn record_metadata_by_hash(&mut self, wasm_path: &Path, section: String) {
+        let Some(hash) = self.entries.get(wasm_path).and_then(|e| e.hash.clone()) else {
+            return;
+        };
+        if self.metadata_by_hash.get(&hash) != Some(&section) {
+            self.metadata_by_hash.insert(hash, section);
+            self.dirty = true;
+        }
+    }
+
+    /// Get the metadata of the binary with `hash`, if it was read before,
+    /// without needing the binary. `wasm_path` is only used in errors.
+    pub fn metadata_for_hash(&self, hash: &str, wasm_path: &Path) -> Option<NitMetadata> {
+        let section = self.metadata_by_hash.get(hash)?;
+        parse_metadata(section.as_bytes(), wasm_path)
+            .inspect_err(|e| debug!("Ignoring cached metadata for {hash}: {e:#}"))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_manifest_invalidation() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("linter.wasm");
+        std::fs::write(&path, "a").unwrap();
+
+        let mut manifest = Manifest::default();
+        assert_eq!(manifest.hash(&path), None);
+        manifest.set_hash(&path, "hash".to_owned());
+        assert_eq!(manifest.hash(&path).as_deref(), Some("hash"));
+
+        manifest.save(dir.path());
+        let mut manifest = Manifest::load(dir.path());
+        assert_eq!(manifest.hash(&path).as_deref(), Some("hash"));
+
+        // Changing the size invalidates the entry.
+        std::fs::write(&path, "ab").unwrap();
+        assert_eq!(manifest.hash(&path), None);
+        assert_eq!(
+            manifest.content_hash(&path).unwrap(),
+            blake3::hash(b"ab").to_hex().to_string()
+        );
+        assert!(manifest.hash(&path).is_some());
+    }
+
+    #[test]
+    fn test_metadata_for_hash() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("linter.wasm");
+        std::fs::write(&path, "a").unwrap();
+        let section = r#"{
+            "argv0": "lint",
+            "max_filenames": 10,
+            "require_serial": false,
+            "args": [],
+            "default_match": { "glob": "*.rs" },
+            "repo": ""
+        }"#;
+
+        let mut manifest = Manifest::default();
+        manifest.entry(&path).unwrap().metadata = Some(section.to_owned());
+        // Not recorded until the hash has been verified.
+        manifest.metadata(&path).unwrap();
+        assert!(manifest.metadata_for_hash("hash", &path).is_none());
+        manifest.set_hash(&path, "hash".to_owned());
+        manifest.metadata(&path).unwrap();
+
+        manifest.save(dir.path());
+        std::fs::remove_file(&path).unwrap();
+        let manifest = Manifest::load(dir.path());
+        let metadata = manifest.metadata_for_hash("hash", &path).unwrap();
+        assert_eq!(metadata.argv0, "lint");
+        assert!(manifest.metadata_for_hash("other", &path).is_none());
+    }
+}
@@ -1,13 +1,70 @@
-use anyhow::{Context, Result, anyhow, bail};
+use anyhow::{Context, Result, anyhow};
 use serde::Deserialize;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::{file_matching::MatchExpression, wasm::find_custom_sections};
+use crate::{file_matching::MatchExpression, git::Encoding, wasm::find_custom_sections};
 
 #[derive(Debug, Deserialize)]
 pub struct ArgBlock {
     pub name: String,
     pub args: Vec<String>,
+    /// Whether users can replace this block with `override_args` in the
+    /// config. Set this to false for arguments the linter needs to work,
+    /// e.g. a `--` separator before the filenames. Defaults to true.
+    #[serde(default = "default_true")]
+    pub overridable: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Form of the filenames passed to a linter.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathStyle {
+    /// Relative to the root of the repo, e.g. `src/foo.rs`.
+    #[default]
+    Relative,
+    /// Absolute, e.g. `/src/foo.rs`. The root of the repo is mounted at `/`
+    /// as well as `.`. This is for linters that canonicalise their inputs.
+    Absolute,
+}
+
+/// What a linter is given to lint.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    /// The matching files.
+    #[default]
+    File,
+    /// The distinct directories containing the matching files (`.` for the
+    /// root of the repo). This is for tools that work on whole directories,
+    /// e.g. packages. `max_filenames` limits the number of directories.
+    Directory,
+}
+
+/// When a linter is run.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// On files in the repo, by `nit run` and the pre-commit and pre-push
+    /// hooks.
+    #[default]
+    Files,
+    /// On the commit message, by the commit-msg hook. It is passed as
+    /// `.git/COMMIT_EDITMSG`.
+    CommitMsg,
+}
+
+/// A known-bad file for a linter.
+#[derive(Debug, Deserialize)]
+pub struct SelfTest {
+    /// Path of the file, relative to the repo root. It must match the
+    /// linter's `default_match`, and can't contain `..`.
+    pub path: PathBuf,
+    /// Contents of the file.
+    pub contents: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,14 +84,73 @@ pub struct NitMetadata {
     /// it may be run in parallel.
     pub require_serial: bool,
 
+    /// If true, the linter can read its filenames from stdin (NUL
+    /// separated) instead of from its arguments. This is used when the
+    /// arguments would be too long to pass safely. In that case no
+    /// filenames are passed as arguments. Otherwise the filenames are split
+    /// over more runs of the linter.
+    #[serde(default)]
+    pub filenames_on_stdin: bool,
+
+    /// How filenames are passed to the linter.
+    #[serde(default)]
+    pub path_style: PathStyle,
+
+    /// Whether the linter is passed files or the directories containing them.
+    #[serde(default)]
+    pub granularity: Granularity,
+
+    /// If true, the linter may write JSON lines diagnostics (see
+    /// `Diagnostic`) to the file in `NIT_DIAGNOSTICS_FILE`, instead of or as
+    /// well as printing them. Nit shows them in the same format as other
+    /// linters' output.
+    #[serde(default)]
+    pub structured_diagnostics: bool,
+
+    /// If true, the linter only checks files and never fixes them. If it
+    /// modifies them anyway, the changes are discarded and it fails.
+    #[serde(default)]
+    pub check_only: bool,
+
+    /// If true, the linter may ask Nit to make changes it can't make itself
+    /// because it can't run Git, by writing JSON lines requests (see
+    /// `GitRequest`) to the file in `NIT_GIT_REQUESTS_FILE`. Ignored for
+    /// `check_only` linters.
+    #[serde(default)]
+    pub git_requests: bool,
+
+    /// When the linter is run. Commit message linters are only run by the
+    /// commit-msg hook, and other linters aren't.
+    #[serde(default)]
+    pub stage: Stage,
+
     /// Arguments to pass. This is an ordered list of blocks of arguments.
-    /// Each block can be overridden by the user, so you should leave
-    /// an empty `extra` block for the user to fill in.
+    /// Each block can be overridden by the user (unless it isn't
+    /// `overridable`), so you should leave an empty `extra` block for the
+    /// user to fill in.
     pub args: Vec<ArgBlock>,
 
-    /// Default expression to match files.
+    /// Default expression to match files. Use a `platform` expression to
+    /// match different files on Windows and Unix.
     pub default_match: MatchExpression,
 
+    /// If true, binary files are never passed to the linter, even if they
+    /// match `default_match` or the config's `override_match`. Defaults to
+    /// true; linters that handle binary files need to set it to false.
+    #[serde(default = "default_true")]
+    pub text_only: bool,
+
+    /// Text encodings the linter supports. Files with other encodings are
+    /// skipped with a warning. If not present, all encodings are supported.
+    #[serde(default)]
+    pub encodings: Option<Vec<Encoding>>,
+
+    /// A file that the linter should fail on or fix, used by
+    /// `nit run --verify-linters` to check that the linter actually reads
+    /// the files it is given.
+    #[serde(default)]
+    pub self_test: Option<SelfTest>,
+
     /// Repository this binary was built from. Required for
     /// commit-based integrity check.
     pub repo: String,
@@ -43,6 +159,76 @@ pub struct NitMetadata {
     // pub attestation: String,
 }
 
+/// Why a linter's metadata couldn't be read.
+#[derive(Debug)]
+pub enum MetadataError {
+    /// There is no `nit_metadata` section, and no sidecar file.
+    NoSection {
+        wasm_path: PathBuf,
+        sidecar: PathBuf,
+    },
+    /// There is more than one `nit_metadata` section.
+    MultipleSections(usize),
+    /// The metadata isn't valid JSON.
+    InvalidJson(serde_json::Error),
+    /// A required field is missing. This is probably because the linter is
+    /// older than this version of Nit.
+    MissingField(String),
+    /// A field has a value that Nit doesn't know. This is probably because
+    /// the linter is newer than this version of Nit.
+    UnknownValue(serde_json::Error),
+    /// A field has the wrong type etc.
+    InvalidField(serde_json::Error),
+}
+
+impl MetadataError {
+    fn from_json(error: serde_json::Error) -> Self {
+        if !error.is_data() {
+            return Self::InvalidJson(error);
+        }
+        let message = error.to_string();
+        if let Some(field) = message
+            .strip_prefix("missing field `")
+            .and_then(|rest| rest.split('`').next())
+        {
+            Self::MissingField(field.to_owned())
+        } else if message.starts_with("unknown variant") {
+            Self::UnknownValue(error)
+        } else {
+            Self::InvalidField(error)
+        }
+    }
+}
+
+impl std::fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSection { wasm_path, sidecar } => write!(
+                f,
+                "No nit_metadata section found in {} and no {} sidecar file",
+                wasm_path.display(),
+                sidecar.display()
+            ),
+            Self::MultipleSections(count) => write!(
+                f,
+                "Found {count} nit_metadata sections in the wasm file; there should be one"
+            ),
+            Self::InvalidJson(e) => write!(f, "Metadata is not valid JSON: {e}"),
+            Self::MissingField(field) => write!(
+                f,
+                "Metadata is missing the required field `{field}`; the linter may be too old for this version of Nit"
+            ),
+            Self::UnknownValue(e) => write!(
+                f,
+                "Metadata has a value that this version of Nit doesn't support ({e}); the linter may need a newer version of Nit"
+            ),
+            Self::InvalidField(e) => write!(f, "Invalid metadata: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
 /// Read the `nit_metadata` section from a wasm file. This is a custom
 /// section that contains a JSON file describing how to execute the module -
 /// how to feed it files, etc.
@@ -51,7 +237,19 @@ pub struct NitMetadata {
 ///
 ///     cargo install wasm-custom-section
 ///
+/// If there is no `nit_metadata` section, the metadata is read from a
+/// sidecar file instead (see `sidecar_path()`). This saves re-embedding the
+/// metadata on every rebuild while developing a linter.
 pub fn read_metadata(wasm_path: &Path) -> Result<NitMetadata> {
+    match read_metadata_section(wasm_path)? {
+        Some(section) => parse_metadata(&section, wasm_path),
+        None => read_sidecar_metadata(wasm_path),
+    }
+}
+
+/// Read the raw contents of the `nit_metadata` section from a wasm file, or
+/// `None` if there isn't one.
+pub fn read_metadata_section(wasm_path: &Path) -> Result<Option<Vec<u8>>> {
     let wasm_bytes = std::fs::read(wasm_path)?;
 
     // Ideally we wouldn't load the entire file into memory, but
@@ -60,13 +258,126 @@ pub fn read_metadata(wasm_path: &Path) -> Result<NitMetadata> {
     let (_, section_contents) = find_custom_sections(&wasm_bytes, "nit_metadata")
         .context("Finding nit_metadata section")?;
 
-    if section_contents.is_empty() {
-        bail!("No nit_metadata section found in the wasm file");
-    }
     if section_contents.len() > 1 {
-        bail!("Multiple nit_metadata sections found in the wasm file");
+        return Err(MetadataError::MultipleSections(section_contents.len()).into());
+    }
+
+    Ok(section_contents.first().map(|section| section.to_vec()))
+}
+
+/// Path of the sidecar metadata file for a wasm file, e.g.
+/// `lint_foo.wasm` -> `lint_foo.nit.json`.
+pub fn sidecar_path(wasm_path: &Path) -> PathBuf {
+    wasm_path.with_extension("nit.json")
+}
+
+/// Read metadata from the sidecar file for a wasm file that has no
+/// `nit_metadata` section.
+pub fn read_sidecar_metadata(wasm_path: &Path) -> Result<NitMetadata> {
+    let sidecar = sidecar_path(wasm_path);
+    let contents = match std::fs::read(&sidecar) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(MetadataError::NoSection {
+                wasm_path: wasm_path.to_owned(),
+                sidecar,
+            }
+            .into());
+        }
+        Err(e) => {
+            return Err(e).with_context(|| anyhow!("Reading {}", sidecar.display()));
+        }
+    };
+    parse_metadata(&contents, &sidecar)
+}
+
+/// Parse the contents of a `nit_metadata` section (or sidecar file).
+/// `wasm_path` is only used for error messages.
+pub fn parse_metadata(section: &[u8], wasm_path: &Path) -> Result<NitMetadata> {
+    serde_json::from_slice::<NitMetadata>(section)
+        .map_err(MetadataError::from_json)
+        .with_context(|| anyhow!("Reading metadata for {}", wasm_path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wasm::make_custom_section;
+    use tempfile::tempdir;
+
+    const METADATA: &str = r#"{
+        "argv0": "ARGV0",
+        "max_filenames": 10,
+        "require_serial": false,
+        "args": [],
+        "default_match": { "bool": true },
+        "repo": ""
+    }"#;
+
+    #[test]
+    fn test_sidecar_metadata() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let wasm_path = dir.path().join("lint_test.wasm");
+        // Empty component.
+        let mut wasm = b"\0asm\x0d\0\x01\0".to_vec();
+        std::fs::write(&wasm_path, &wasm).unwrap();
+
+        assert!(matches!(
+            read_metadata(&wasm_path).unwrap_err().downcast_ref(),
+            Some(MetadataError::NoSection { .. })
+        ));
+
+        std::fs::write(
+            dir.path().join("lint_test.nit.json"),
+            METADATA.replace("ARGV0", "sidecar"),
+        )
+        .unwrap();
+        assert_eq!(read_metadata(&wasm_path).unwrap().argv0, "sidecar");
+
+        // Embedded metadata takes precedence.
+        wasm.extend(make_custom_section(
+            "nit_metadata",
+            METADATA.replace("ARGV0", "embedded").as_bytes(),
+        ));
+        std::fs::write(&wasm_path, &wasm).unwrap();
+        assert_eq!(read_metadata(&wasm_path).unwrap().argv0, "embedded");
+    }
+
+    #[test]
+    fn test_stage() {
+        let metadata = parse_metadata(METADATA.as_bytes(), Path::new("lint.wasm")).unwrap();
+        assert_eq!(metadata.stage, Stage::Files);
+
+        let commit_msg = include_str!("../lints/lint_commit_msg/metadata.json");
+        let metadata = parse_metadata(commit_msg.as_bytes(), Path::new("lint.wasm")).unwrap();
+        assert_eq!(metadata.stage, Stage::CommitMsg);
     }
 
-    Ok(serde_json::from_slice::<NitMetadata>(section_contents[0])
-        .with_context(|| anyhow!("Reading metadata for {}", wasm_path.display()))?)
+    #[test]
+    fn test_metadata_errors() {
+        let error = |metadata: &str| {
+            let error = parse_metadata(metadata.as_bytes(), Path::new("lint.wasm")).unwrap_err();
+            assert!(format!("{error:#}").contains("lint.wasm"));
+            match error.downcast::<MetadataError>().unwrap() {
+                MetadataError::InvalidJson(_) => "invalid json".to_owned(),
+                MetadataError::MissingField(field) => format!("missing {field}"),
+                MetadataError::UnknownValue(_) => "unknown value".to_owned(),
+                MetadataError::InvalidField(_) => "invalid field".to_owned(),
+                other => panic!("Unexpected error {other}"),
+            }
+        };
+        assert_eq!(error("{ argv0: "), "invalid json");
+        assert_eq!(
+            error(&METADATA.replace(r#""max_filenames": 10,"#, "")),
+            "missing max_filenames"
+        );
+        assert_eq!(
+            error(&METADATA.replace(r#""repo": """#, r#""repo": "", "granularity": "crate""#)),
+            "unknown value"
+        );
+        assert_eq!(
+            error(&METADATA.replace(r#""max_filenames": 10"#, r#""max_filenames": "10""#)),
+            "invalid field"
+        );
+    }
 }
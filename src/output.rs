@@ -0,0 +1,106 @@
+use std::{
+    fmt::Display,
+    io::{self, Write as _},
+    sync::Mutex,
+};
+
+use owo_colors::{AnsiColors, OwoColorize as _};
+
+/// Progress and linter output. Normally this is written to stderr straight
+/// away, but it can be buffered so that it's only shown if linting fails.
+/// Buffered output is written to stderr when this is dropped unless it was
+/// discarded, so it isn't lost if Nit exits with an error.
+pub struct Output {
+    buffer: Option<Mutex<Vec<u8>>>,
+    /// Whether `paint()` adds colours (see `--color`).
+    color: bool,
+}
+
+impl Output {
+    /// Write output to stderr immediately.
+    pub fn stderr(color: bool) -> Self {
+        Self {
+            buffer: None,
+            color,
+        }
+    }
+
+    /// Buffer output until this is dropped.
+    pub fn buffered(color: bool) -> Self {
+        Self {
+            buffer: Some(Mutex::new(Vec::new())),
+            color,
+        }
+    }
+
+    /// Whether this output is coloured.
+    pub fn color(&self) -> bool {
+        self.color
+    }
+
+    /// `text` in `color`, if this output is coloured.
+    pub fn paint(&self, text: impl Display, color: AnsiColors) -> String {
+        if self.color {
+            text.color(color).to_string()
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        match &self.buffer {
+            Some(buffer) => {
+                buffer.lock().unwrap().extend_from_slice(bytes);
+                Ok(())
+            }
+            None => io::stderr().lock().write_all(bytes),
+        }
+    }
+
+    /// Write `line` followed by a newline.
+    pub fn line(&self, line: impl Display) -> io::Result<()> {
+        self.write(format!("{line}\n").as_bytes())
+    }
+
+    /// Throw away the buffered output, e.g. because everything passed.
+    pub fn discard(&self) {
+        if let Some(buffer) = &self.buffer {
+            buffer.lock().unwrap().clear();
+        }
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some(buffer) = &self.buffer {
+            let buffer = buffer.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = io::stderr().lock().write_all(&buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_discard() {
+        let output = Output::buffered(false);
+        output.line("hello").unwrap();
+        output.write(b"world").unwrap();
+        assert_eq!(
+            output.buffer.as_ref().unwrap().lock().unwrap().as_slice(),
+            b"hello\nworld"
+        );
+        output.discard();
+        assert!(output.buffer.as_ref().unwrap().lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_paint() {
+        let colored = Output::buffered(true).paint("failed", AnsiColors::Red);
+        assert_eq!(colored, "\x1b[31mfailed\x1b[39m");
+        let plain = Output::buffered(false).paint("failed", AnsiColors::Red);
+        assert_eq!(plain, "failed");
+    }
+}
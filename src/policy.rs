@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result, anyhow};
+use serde::Deserialize;
+
+/// Restrictions set by the user or an administrator. These aren't read from
+/// the repo's config, since they are meant to limit what a repo can do.
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// If set, remote linters can only be downloaded from these hosts, e.g.
+    /// `["lints.example.com"]`, including when following redirects.
+    pub allowed_hosts: Option<Vec<String>>,
+}
+
+impl Policy {
+    /// Combine two policies so that anything either forbids is forbidden.
+    fn restrict(self, other: Policy) -> Policy {
+        let allowed_hosts = match (self.allowed_hosts, other.allowed_hosts) {
+            (Some(a), Some(b)) => Some(
+                a.into_iter()
+                    .filter(|host| b.iter().any(|other| other.eq_ignore_ascii_case(host)))
+                    .collect(),
+            ),
+            (a, b) => a.or(b),
+        };
+        Policy { allowed_hosts }
+    }
+}
+
+/// Where policy files are read from: the system-wide one, then the user's.
+fn policy_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    #[cfg(unix)]
+    paths.push(PathBuf::from("/etc/nit/policy.json5"));
+    #[cfg(windows)]
+    if let Some(program_data) = std::env::var_os("ProgramData") {
+        paths.push(PathBuf::from(program_data).join("nit").join("policy.json5"));
+    }
+    if let Some(config_dir) = dirs::config_dir() {
+        paths.push(config_dir.join("nit").join("policy.json5"));
+    }
+    paths
+}
+
+/// Read the policy files that exist. A user's policy can only add
+/// restrictions to the system one.
+pub fn load_policy() -> Result<Policy> {
+    let mut policy = Policy::default();
+    for path in policy_paths() {
+        if let Some(file_policy) = read_policy(&path)? {
+            policy = policy.restrict(file_policy);
+        }
+    }
+    Ok(policy)
+}
+
+fn read_policy(path: &Path) -> Result<Option<Policy>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| anyhow!("Failed to read {}", path.display())),
+    };
+    serde_json5::from_str(&content)
+        .map(Some)
+        .with_context(|| anyhow!("Invalid policy file {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json5");
+        assert_eq!(read_policy(&path).unwrap(), None);
+
+        std::fs::write(&path, r#"{ allowed_hosts: ["lints.example.com"] }"#).unwrap();
+        assert_eq!(
+            read_policy(&path).unwrap(),
+            Some(Policy {
+                allowed_hosts: Some(vec!["lints.example.com".to_owned()]),
+            })
+        );
+
+        std::fs::write(&path, r#"{ allowed_host: [] }"#).unwrap();
+        assert!(read_policy(&path).is_err());
+    }
+
+    #[test]
+    fn test_restrict() {
+        let hosts = |hosts: &[&str]| Policy {
+            allowed_hosts: Some(hosts.iter().map(|host| host.to_string()).collect()),
+        };
+        let system = hosts(&["lints.example.com", "github.com"]);
+        assert_eq!(
+            system.restrict(hosts(&["GitHub.com", "evil.com"])),
+            hosts(&["github.com"])
+        );
+        assert_eq!(
+            Policy::default().restrict(hosts(&["github.com"])),
+            hosts(&["github.com"])
+        );
+        assert_eq!(
+            hosts(&["github.com"]).restrict(Policy::default()),
+            hosts(&["github.com"])
+        );
+    }
+}
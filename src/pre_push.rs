@@ -0,0 +1,259 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use log::{debug, warn};
+
+use crate::git::{git_changed_paths, git_commit_exists, git_commits_not_on_remote, git_remotes};
+
+/// A ref being pushed, from one line of the pre-push hook's stdin:
+///
+///    <local ref> SP <local sha1> SP <remote ref> SP <remote sha1> LF
+#[derive(Debug, PartialEq, Eq)]
+pub struct PushedRef {
+    pub local_ref: String,
+    pub local_sha: String,
+    pub remote_ref: String,
+    pub remote_sha: String,
+}
+
+impl PushedRef {
+    /// The remote ref is being deleted.
+    fn is_deletion(&self) -> bool {
+        is_null_sha(&self.local_sha)
+    }
+
+    /// The remote ref doesn't exist yet.
+    fn is_new(&self) -> bool {
+        is_null_sha(&self.remote_sha)
+    }
+}
+
+/// Git uses all zeros for refs that don't exist.
+fn is_null_sha(sha: &str) -> bool {
+    sha.bytes().all(|b| b == b'0')
+}
+
+/// SHA-1 or SHA-256 hash.
+fn is_sha(sha: &str) -> bool {
+    matches!(sha.len(), 40 | 64) && sha.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Parse the pre-push hook's stdin. Malformed lines are skipped with a
+/// warning.
+pub fn parse_pushed_refs(input: &str) -> Vec<PushedRef> {
+    input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [local_ref, local_sha, remote_ref, remote_sha]
+                    if is_sha(local_sha) && is_sha(remote_sha) =>
+                {
+                    Some(PushedRef {
+                        local_ref: local_ref.to_string(),
+                        local_sha: local_sha.to_string(),
+                        remote_ref: remote_ref.to_string(),
+                        remote_sha: remote_sha.to_string(),
+                    })
+                }
+                _ => {
+                    warn!("Ignoring malformed pre-push line: {line:?}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Get the name of the remote being pushed to. Git passes its URL as its
+/// name if it doesn't have one (e.g. `git push <url>`), in which case a
+/// configured remote with the same URL is used. Returns `None` if there
+/// isn't one.
+pub fn remote_name(top_level: &Path, remote: &str, url: &str) -> Result<Option<String>> {
+    let remotes = git_remotes(top_level)?;
+    if remotes.iter().any(|(name, _)| name == remote) {
+        return Ok(Some(remote.to_owned()));
+    }
+    Ok(remotes
+        .into_iter()
+        .find(|(_, remote_url)| remote_url == url)
+        .map(|(name, _)| name))
+}
+
+/// Get the files added or modified by the pushed refs, for each pushed
+/// commit. Refs that point to the same commit share one set of files.
+/// For new branches, the commits that aren't on any of `remote`'s
+/// remote-tracking branches are used, or any remote's if `remote` is `None`
+/// (see `remote_name()`).
+pub fn pushed_files(
+    top_level: &Path,
+    remote: Option<&str>,
+    refs: &[PushedRef],
+) -> Result<BTreeMap<String, BTreeSet<PathBuf>>> {
+    let mut files = BTreeMap::<String, BTreeSet<PathBuf>>::new();
+    for pushed in refs {
+        if pushed.is_deletion() {
+            debug!("Nothing to lint for deleting {}", pushed.remote_ref);
+            continue;
+        }
+
+        // If we don't have the remote commit (e.g. someone else pushed to it)
+        // treat it like a new branch.
+        let base = if !pushed.is_new() && git_commit_exists(top_level, &pushed.remote_sha)? {
+            Some(pushed.remote_sha.clone())
+        } else {
+            let new_commits = git_commits_not_on_remote(top_level, &pushed.local_sha, remote)?;
+            let Some(first) = new_commits.first() else {
+                debug!("{} has no new commits", pushed.local_ref);
+                continue;
+            };
+            let parent = format!("{first}^");
+            // The whole history is new if the first commit is a root commit.
+            git_commit_exists(top_level, &parent)?.then_some(parent)
+        };
+
+        files
+            .entry(pushed.local_sha.clone())
+            .or_default()
+            .extend(git_changed_paths(
+                top_level,
+                base.as_deref(),
+                &pushed.local_sha,
+            )?);
+    }
+    files.retain(|_, paths| !paths.is_empty());
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    const ZERO: &str = "0000000000000000000000000000000000000000";
+
+    #[test]
+    fn test_parse_pushed_refs() {
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+        let input = format!(
+            "refs/heads/main {a} refs/heads/main {b}\n\
+             garbage\n\
+             refs/heads/new {a} refs/heads/new {ZERO}\n\
+             (delete) {ZERO} refs/heads/old {b}\n"
+        );
+        let refs = parse_pushed_refs(&input);
+        assert_eq!(refs.len(), 3);
+        assert_eq!(refs[0].remote_sha, b);
+        assert!(!refs[0].is_new() && !refs[0].is_deletion());
+        assert!(refs[1].is_new());
+        assert!(refs[2].is_deletion());
+    }
+
+    #[test]
+    fn test_remote_name() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        let name = |remote: &str, url: &str| remote_name(dir.path(), remote, url).unwrap();
+        assert_eq!(name("origin", "https://example.com/a.git"), None);
+
+        git(&["remote", "add", "origin", "https://example.com/a.git"]);
+        git(&["remote", "add", "my.fork", "https://example.com/b.git"]);
+        assert_eq!(
+            name("origin", "https://example.com/a.git").as_deref(),
+            Some("origin")
+        );
+        // Pushing to a URL.
+        let url = "https://example.com/b.git";
+        assert_eq!(name(url, url).as_deref(), Some("my.fork"));
+        let url = "https://example.com/c.git";
+        assert_eq!(name(url, url), None);
+    }
+
+    #[test]
+    fn test_pushed_files() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .args(["-c", "user.name=nit", "-c", "user.email=nit@example.com"])
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap().trim().to_owned()
+        };
+        let commit = |path: &str| {
+            std::fs::write(dir.path().join(path), path).unwrap();
+            git(&["add", "-A"]);
+            git(&["commit", "-q", "-m", path]);
+            git(&["rev-parse", "HEAD"])
+        };
+        git(&["init", "-q"]);
+        let first = commit("a.txt");
+        std::fs::write(dir.path().join("gone.txt"), "").unwrap();
+        let second = commit("b.txt");
+        std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+        let third = commit("c.txt");
+
+        let pushed = |local_sha: &str, remote_sha: &str| PushedRef {
+            local_ref: "refs/heads/main".to_owned(),
+            local_sha: local_sha.to_owned(),
+            remote_ref: "refs/heads/main".to_owned(),
+            remote_sha: remote_sha.to_owned(),
+        };
+        let files = |refs: &[PushedRef]| -> Vec<(String, Vec<PathBuf>)> {
+            pushed_files(dir.path(), Some("origin"), refs)
+                .unwrap()
+                .into_iter()
+                .map(|(commit, paths)| (commit, paths.into_iter().collect()))
+                .collect()
+        };
+
+        // Existing branch; deleted files are ignored.
+        assert_eq!(
+            files(&[pushed(&third, &second)]),
+            [(third.clone(), vec![PathBuf::from("c.txt")])]
+        );
+        // New branch with no remote-tracking branches, so everything is new.
+        assert_eq!(
+            files(&[pushed(&third, ZERO)]),
+            [(
+                third.clone(),
+                vec![PathBuf::from("a.txt"), "b.txt".into(), "c.txt".into()]
+            )]
+        );
+        // Refs pushing the same commit are combined, and deletions are
+        // skipped.
+        let mut expected = vec![
+            (
+                second.clone(),
+                vec![PathBuf::from("b.txt"), "gone.txt".into()],
+            ),
+            (third.clone(), vec![PathBuf::from("b.txt"), "c.txt".into()]),
+        ];
+        expected.sort();
+        assert_eq!(
+            files(&[
+                pushed(&second, &first),
+                pushed(&third, &second),
+                pushed(&third, &first),
+                pushed(ZERO, &first)
+            ]),
+            expected
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use owo_colors::{OwoColorize as _, Stream};
+
+/// Whether `--profile` was given. Spans do nothing unless this is set, so
+/// profiling has no overhead otherwise.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Total time and number of spans for each phase, in the order they first
+/// finished.
+static PHASES: Mutex<Vec<(String, Duration, usize)>> = Mutex::new(Vec::new());
+
+/// Start recording spans.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Time a phase until the returned guard is dropped. Spans with the same name
+/// are added together, e.g. for linters with several chunks.
+pub fn span(name: impl Into<String>) -> Span {
+    Span(
+        ENABLED
+            .load(Ordering::Relaxed)
+            .then(|| (name.into(), Instant::now())),
+    )
+}
+
+#[must_use = "the span ends when this is dropped"]
+pub struct Span(Option<(String, Instant)>);
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some((name, start)) = self.0.take() {
+            record(name, start.elapsed());
+        }
+    }
+}
+
+fn record(name: String, duration: Duration) {
+    let mut phases = PHASES.lock().unwrap();
+    if let Some((_, total, count)) = phases.iter_mut().find(|(n, _, _)| *n == name) {
+        *total += duration;
+        *count += 1;
+    } else {
+        phases.push((name, duration, 1));
+    }
+}
+
+/// Print the time spent in each phase to stderr, if profiling is enabled.
+/// Phases can overlap (e.g. linters that run in parallel), so they don't
+/// necessarily add up to the total run time.
+pub fn print_report() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let phases = PHASES.lock().unwrap();
+    let width = phases
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0);
+    eprintln!(
+        "{}",
+        "Profile:".if_supports_color(Stream::Stderr, |t| t.bold())
+    );
+    for (name, total, count) in phases.iter() {
+        let count = if *count > 1 {
+            format!(" ({count} times)")
+        } else {
+            String::new()
+        };
+        eprintln!("  {name:width$}  {:>10.1?}{count}", total);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        record("test a".to_owned(), Duration::from_millis(1));
+        record("test b".to_owned(), Duration::from_millis(2));
+        record("test a".to_owned(), Duration::from_millis(3));
+        let phases = PHASES.lock().unwrap();
+        let test_phases: Vec<_> = phases
+            .iter()
+            .filter(|(name, ..)| name.starts_with("test "))
+            .collect();
+        assert_eq!(
+            test_phases,
+            [
+                &("test a".to_owned(), Duration::from_millis(4), 2),
+                &("test b".to_owned(), Duration::from_millis(2), 1),
+            ]
+        );
+    }
+}
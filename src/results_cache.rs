@@ -0,0 +1,221 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use log::debug;
+
+use crate::{file_hashes::FileHash, git::FileInfo, metadata::NitMetadata};
+
+/// Entries that haven't been used for this long are removed by `evict()`.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Record of linter runs that passed without modifying anything, so they can
+/// be skipped if nothing they depend on has changed. Each run is identified
+/// by a key covering the linter binary, its settings, arguments and
+/// environment, and the paths and contents of the files it was run on.
+pub struct ResultsCache {
+    dir: PathBuf,
+    /// Whether runs that passed before are skipped. If not (`--no-cache`),
+    /// passes are still recorded.
+    read: bool,
+}
+
+/// The inputs to one run of a linter.
+pub struct RunKey {
+    key: String,
+}
+
+impl ResultsCache {
+    /// A cache stored in `dir`, which is created when needed.
+    pub fn new(dir: PathBuf, read: bool) -> Self {
+        Self { dir, read }
+    }
+
+    /// Get the key for running the linter whose binary has `binary_hash` with
+    /// `args` and `env` over `files`. The contents of each file are
+    /// identified by its Git object ID if it has one, otherwise by its hash
+    /// in `recorded`, so files aren't read again.
+    pub fn key(
+        &self,
+        binary_hash: &str,
+        metadata: &NitMetadata,
+        args: &[&str],
+        env: &[(String, String)],
+        files: &[&FileInfo],
+        recorded: &BTreeMap<PathBuf, FileHash>,
+    ) -> RunKey {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(binary_hash.as_bytes());
+        // Includes settings from a sidecar metadata file and the config's
+        // overrides.
+        hasher.update(format!("{metadata:?}").as_bytes());
+        hasher.update(format!("{args:?}").as_bytes());
+        // Inherited variables can change the linter's behaviour.
+        hasher.update(format!("{env:?}").as_bytes());
+
+        for file in files {
+            hasher.update(format!("{:?} {:?}\0", file.path, file.ty).as_bytes());
+            match (&file.object_id, recorded.get(&file.path)) {
+                (Some(object_id), _) => hasher.update(format!("git {object_id}\0").as_bytes()),
+                (None, Some(hash)) => hasher.update(hash.digest().as_bytes()),
+                (None, None) => hasher.update(b"unknown\0"),
+            };
+        }
+
+        RunKey {
+            key: hasher.finalize().to_hex().to_string(),
+        }
+    }
+
+    /// Whether a run with this key passed before. Always false with
+    /// `--no-cache`.
+    pub fn passed(&self, key: &RunKey) -> bool {
+        if !self.read {
+            return false;
+        }
+        let path = self.dir.join(&key.key);
+        // Keep entries that are still used from being evicted.
+        match std::fs::File::options().write(true).open(&path) {
+            Ok(file) => {
+                if let Err(e) = file.set_modified(SystemTime::now()) {
+                    debug!("Failed to touch {}: {e}", path.display());
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Record that a run passed. The caller must check that it didn't modify
+    /// any of its files.
+    pub fn record_pass(&self, key: &RunKey) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| anyhow!("Failed to create {}", self.dir.display()))?;
+        std::fs::write(self.dir.join(&key.key), "")
+            .with_context(|| anyhow!("Failed to write to {}", self.dir.display()))
+    }
+
+    /// Remove entries that haven't been used for `MAX_AGE`, so the cache
+    /// doesn't grow forever.
+    pub fn evict(&self) -> Result<()> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(e).with_context(|| anyhow!("Failed to read {}", self.dir.display()));
+            }
+        };
+        let now = SystemTime::now();
+        for entry in entries {
+            let entry = entry?;
+            let old = entry
+                .metadata()?
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > MAX_AGE);
+            if old {
+                std::fs::remove_file(entry.path())
+                    .with_context(|| anyhow!("Failed to remove {}", entry.path().display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{file_hashes::hash_file, git::text_file as file, metadata::parse_metadata};
+    use std::path::Path;
+
+    #[test]
+    fn test_results_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let work_dir = dir.path().join("work");
+        std::fs::create_dir(&work_dir).unwrap();
+        std::fs::write(work_dir.join("a.txt"), "a").unwrap();
+        let metadata = parse_metadata(
+            br#"{
+                "argv0": "lint",
+                "max_filenames": 10,
+                "require_serial": false,
+                "args": [],
+                "default_match": { "bool": true },
+                "repo": ""
+            }"#,
+            Path::new("lint.wasm"),
+        )
+        .unwrap();
+
+        let cache = ResultsCache::new(dir.path().join("results"), true);
+        let files = [
+            file("a.txt"),
+            FileInfo {
+                object_id: Some("1234".to_owned()),
+                ..file("b.txt")
+            },
+        ];
+        let files: Vec<&FileInfo> = files.iter().collect();
+        let recorded = |contents: &str| {
+            std::fs::write(work_dir.join("a.txt"), contents).unwrap();
+            BTreeMap::from([(
+                PathBuf::from("a.txt"),
+                hash_file(&work_dir.join("a.txt")).unwrap(),
+            )])
+        };
+        let key_with = |binary: &str, args: &[&str], env: &[(String, String)]| {
+            cache.key(binary, &metadata, args, env, &files, &recorded("a"))
+        };
+        let key = |args: &[&str]| key_with("binary", args, &[]);
+
+        let run = key(&["lint"]);
+        assert!(!cache.passed(&run));
+        cache.record_pass(&run).unwrap();
+        assert!(cache.passed(&key(&["lint"])));
+        assert!(!cache.passed(&key(&["lint", "--strict"])));
+        assert!(!cache.passed(&key_with("other binary", &["lint"], &[])));
+        let env = [("LINT_STRICT".to_owned(), "1".to_owned())];
+        assert!(!cache.passed(&key_with("binary", &["lint"], &env)));
+
+        // A file without an object ID is identified by its contents.
+        let changed = cache.key("binary", &metadata, &["lint"], &[], &files, &recorded("b"));
+        assert!(!cache.passed(&changed));
+
+        // Passes are still recorded with `--no-cache`.
+        let no_cache = ResultsCache::new(dir.path().join("results"), false);
+        assert!(!no_cache.passed(&key(&["lint"])));
+        no_cache.record_pass(&key(&["lint", "--fix"])).unwrap();
+        assert!(cache.passed(&key(&["lint", "--fix"])));
+    }
+
+    #[test]
+    fn test_evict() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResultsCache::new(dir.path().to_owned(), true);
+        for name in ["old", "used", "new"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let long_ago = SystemTime::now() - MAX_AGE * 2;
+        for name in ["old", "used"] {
+            std::fs::File::options()
+                .write(true)
+                .open(dir.path().join(name))
+                .unwrap()
+                .set_modified(long_ago)
+                .unwrap();
+        }
+        assert!(cache.passed(&RunKey {
+            key: "used".to_owned()
+        }));
+
+        cache.evict().unwrap();
+        assert!(!dir.path().join("old").exists());
+        assert!(dir.path().join("used").exists());
+        assert!(dir.path().join("new").exists());
+    }
+}
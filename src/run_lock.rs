@@ -0,0 +1,68 @@
+use std::{
+    fs::{File, TryLockError},
+    path::Path,
+};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use log::info;
+
+use crate::git::git_path;
+
+/// Advisory lock that stops two instances of Nit linting the same repo at
+/// once (e.g. a manual `nit run` and a hook), since they would both fix the
+/// same files. The lock is released when this is dropped, including if Nit
+/// exits early. The lock file itself is left in place.
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Take the lock for the repo at `top_level`. If another instance holds
+    /// it this waits for it to finish, or fails if `wait` is false.
+    pub fn acquire(top_level: &Path, wait: bool) -> Result<Self> {
+        let path = git_path(top_level, "nit.lock")?;
+        let file = File::create(&path)
+            .with_context(|| anyhow!("Failed to create lock file {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) if wait => {
+                info!("Waiting for another instance of nit to finish");
+                file.lock()
+                    .with_context(|| anyhow!("Failed to lock {}", path.display()))?;
+            }
+            Err(TryLockError::WouldBlock) => {
+                bail!(
+                    "Another instance of nit is running in this repo (locked {})",
+                    path.display()
+                );
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| anyhow!("Failed to lock {}", path.display()));
+            }
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let status = Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let lock = RunLock::acquire(dir.path(), false).unwrap();
+        assert!(RunLock::acquire(dir.path(), false).is_err());
+        drop(lock);
+        RunLock::acquire(dir.path(), false).unwrap();
+    }
+}
@@ -2,9 +2,9 @@ use glob::Pattern;
 use std::borrow::Cow;
 
 use serde::de::Error;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-// Newtype so we can impl Deserialize for Pattern.
+// Newtype so we can impl Deserialize and Serialize for Pattern.
 pub struct Serde<T>(pub T);
 
 impl<'de> Deserialize<'de> for Serde<Pattern> {
@@ -28,3 +28,20 @@ where
 {
     Serde::deserialize(deserializer).map(|x| x.0)
 }
+
+impl Serialize for Serde<&Pattern> {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.serialize_str(self.0.as_str())
+    }
+}
+
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    for<'a> Serde<&'a T>: Serialize,
+{
+    Serde(value).serialize(serializer)
+}
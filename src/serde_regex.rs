@@ -1,10 +1,10 @@
-use regex::Regex;
+use regex::{Regex, RegexSet};
 use std::borrow::Cow;
 
 use serde::de::Error;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-// Newtype so we can impl Deserialize for Regex.
+// Newtype so we can impl Deserialize and Serialize for Regex.
 pub struct Serde<T>(pub T);
 
 impl<'de> Deserialize<'de> for Serde<Regex> {
@@ -21,6 +21,29 @@ impl<'de> Deserialize<'de> for Serde<Regex> {
     }
 }
 
+impl<'de> Deserialize<'de> for Serde<Option<Regex>> {
+    fn deserialize<D>(d: D) -> Result<Serde<Option<Regex>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Serde<Regex>>::deserialize(d).map(|maybe_regex| Serde(maybe_regex.map(|r| r.0)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Serde<RegexSet> {
+    fn deserialize<D>(d: D) -> Result<Serde<RegexSet>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let patterns = <Vec<Cow<str>>>::deserialize(d)?;
+
+        match RegexSet::new(patterns) {
+            Ok(set) => Ok(Serde(set)),
+            Err(err) => Err(D::Error::custom(err)),
+        }
+    }
+}
+
 pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
     D: Deserializer<'de>,
@@ -28,3 +51,38 @@ where
 {
     Serde::deserialize(deserializer).map(|x| x.0)
 }
+
+impl Serialize for Serde<&Regex> {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        s.serialize_str(self.0.as_str())
+    }
+}
+
+impl Serialize for Serde<&Option<Regex>> {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.as_ref().map(Serde).serialize(s)
+    }
+}
+
+impl Serialize for Serde<&RegexSet> {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.patterns().serialize(s)
+    }
+}
+
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    for<'a> Serde<&'a T>: Serialize,
+{
+    Serde(value).serialize(serializer)
+}
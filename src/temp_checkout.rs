@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result, anyhow};
+use log::{debug, info};
+use regex::RegexSet;
+
+use crate::{
+    file_hashes::{FileHash, hash_files, rehash_files},
+    git::{
+        FileInfo, FileType, git_checkout_commit, git_checkout_index, git_staged_modes,
+        git_update_index,
+    },
+    unique_filename::unique_filename,
+};
+
+/// A temporary copy of some files that linters can be run on without
+/// affecting the working directory. The directory is deleted when this is
+/// dropped.
+pub struct TempCheckout {
+    dir: PathBuf,
+    files: Vec<FileInfo>,
+    original_hashes: Vec<FileHash>,
+}
+
+impl TempCheckout {
+    fn empty(prefix: &str) -> Result<Self> {
+        let dir = std::env::temp_dir().join(unique_filename(prefix, ""));
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            files: Vec::new(),
+            original_hashes: Vec::new(),
+        })
+    }
+
+    fn set_files(&mut self, files: Vec<FileInfo>) -> Result<()> {
+        self.original_hashes = hash_files(&self.dir, &files.iter().collect::<Vec<_>>())?;
+        self.files = files;
+        Ok(())
+    }
+
+    /// Check out the index. Running linters here means unstaged changes can't
+    /// affect the result, and fixes apply to the staged content.
+    pub fn of_index(top_level: &Path, generated_markers: &RegexSet) -> Result<Self> {
+        // Construct this first so the directory is cleaned up on error.
+        let mut checkout = Self::empty("nit-staged-")?;
+        let files = git_checkout_index(top_level, &checkout.dir, generated_markers)?;
+        checkout.set_files(files)?;
+        debug!("Checked out index to {}", checkout.dir.display());
+        Ok(checkout)
+    }
+
+    /// Check out `paths` (relative to `top_level`) from `commit`.
+    pub fn of_commit(
+        top_level: &Path,
+        commit: &str,
+        paths: &[PathBuf],
+        generated_markers: &RegexSet,
+    ) -> Result<Self> {
+        let mut checkout = Self::empty("nit-commit-")?;
+        let files =
+            git_checkout_commit(top_level, commit, paths, &checkout.dir, generated_markers)?;
+        checkout.set_files(files)?;
+        debug!("Checked out {commit} to {}", checkout.dir.display());
+        Ok(checkout)
+    }
+
+    /// Copy `files` from the working directory. Symlinks aren't copied.
+    pub fn of_files(top_level: &Path, files: Vec<FileInfo>) -> Result<Self> {
+        let mut checkout = Self::empty("nit-copy-")?;
+        for file in &files {
+            if file.ty == FileType::Symlink {
+                continue;
+            }
+            let dest = checkout.dir.join(&file.path);
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(top_level.join(&file.path), &dest)
+                .with_context(|| anyhow!("Copying {}", file.path.display()))?;
+        }
+        checkout.set_files(files)?;
+        debug!("Copied files to {}", checkout.dir.display());
+        Ok(checkout)
+    }
+
+    /// Directory containing the checkout.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Info on the checked out files.
+    pub fn files(&self) -> &[FileInfo] {
+        &self.files
+    }
+
+    /// Indices of files that have been modified since they were checked out.
+    fn modified_indices(&self) -> Result<Vec<usize>> {
+        let hashes = rehash_files(
+            &self.dir,
+            &self.files.iter().collect::<Vec<_>>(),
+            &self.original_hashes,
+        )?;
+        Ok(hashes
+            .iter()
+            .zip(&self.original_hashes)
+            .enumerate()
+            .filter(|(_, (hash, original))| hash != original)
+            .map(|(index, _)| index)
+            .collect())
+    }
+
+    /// Files that have been modified since they were checked out.
+    pub fn modified_files(&self) -> Result<Vec<&FileInfo>> {
+        Ok(self
+            .modified_indices()?
+            .into_iter()
+            .map(|index| &self.files[index])
+            .collect())
+    }
+
+    /// Copy files that were modified in a checkout of the index back into the
+    /// index. If a file has no unstaged changes, the working directory is
+    /// updated too. Otherwise the unstaged changes are left alone. Returns the
+    /// modified files.
+    pub fn apply_fixes_to_index(&self, top_level: &Path) -> Result<Vec<&FileInfo>> {
+        let mut modified = Vec::new();
+        let indices = self.modified_indices()?;
+        let paths: Vec<&Path> = indices
+            .iter()
+            .map(|&index| self.files[index].path.as_path())
+            .collect();
+        let staged_modes = git_staged_modes(top_level, &paths)?;
+        for index in indices {
+            let file = &self.files[index];
+            git_update_index(
+                top_level,
+                &self.dir,
+                file,
+                staged_modes.get(&file.path).copied(),
+            )?;
+
+            let working = hash_files(top_level, &[file])?;
+            if working[0] == self.original_hashes[index] {
+                let fixed_path = self.dir.join(&file.path);
+                let working_path = top_level.join(&file.path);
+                if fixed_path.exists() {
+                    std::fs::copy(&fixed_path, &working_path)
+                        .with_context(|| anyhow!("Copying fix to {}", working_path.display()))?;
+                } else {
+                    std::fs::remove_file(&working_path)?;
+                }
+            } else {
+                info!(
+                    "Staged {} was fixed but it has unstaged changes, so only the index was updated",
+                    file.path.display()
+                );
+            }
+            modified.push(file);
+        }
+        Ok(modified)
+    }
+}
+
+impl Drop for TempCheckout {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            debug!("Failed to remove {}: {e}", self.dir.display());
+        }
+    }
+}
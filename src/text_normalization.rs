@@ -0,0 +1,181 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result, anyhow};
+
+use crate::{
+    config::{FinalNewline, LineEndings, TextNormalization, TrailingWhitespace},
+    engine::retain_inside_root,
+    file_matching::matching_files,
+    git::{Encoding, FileInfo, FileType},
+};
+
+/// Name of the built-in pseudo-linter in the output.
+pub const NAME: &str = "text_normalization";
+
+/// Normalise the UTF-8 text files in `files` that match the settings, and
+/// return the paths of the ones that were changed. Like linters' files,
+/// files outside `work_dir` (through symlinked directories) are skipped.
+pub fn normalize_files(
+    work_dir: &Path,
+    files: &[FileInfo],
+    settings: &TextNormalization,
+) -> Result<Vec<PathBuf>> {
+    let mut text_files = matching_files(files, &settings.match_expression);
+    text_files.retain(|file| {
+        matches!(file.ty, FileType::Text | FileType::ExecutableText)
+            && file.encoding == Encoding::Utf8
+    });
+    let mut fixed = Vec::new();
+    for file in retain_inside_root(work_dir, text_files, NAME) {
+        let path = work_dir.join(&file.path);
+        let contents =
+            std::fs::read(&path).with_context(|| anyhow!("Failed to read {}", path.display()))?;
+        let normalized = normalize(&contents, settings);
+        if normalized != contents {
+            std::fs::write(&path, normalized)
+                .with_context(|| anyhow!("Failed to write {}", path.display()))?;
+            fixed.push(file.path.clone());
+        }
+    }
+    Ok(fixed)
+}
+
+/// Normalise the contents of a text file.
+fn normalize(contents: &[u8], settings: &TextNormalization) -> Vec<u8> {
+    // Each line and its line ending, which is empty for the last line if
+    // the file doesn't end with a newline.
+    let mut lines: Vec<(&[u8], &[u8])> = Vec::new();
+    let mut rest = contents;
+    while !rest.is_empty() {
+        match rest.iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                let end = if i > 0 && rest[i - 1] == b'\r' {
+                    i - 1
+                } else {
+                    i
+                };
+                lines.push((&rest[..end], &rest[end..=i]));
+                rest = &rest[i + 1..];
+            }
+            None => {
+                lines.push((rest, b""));
+                rest = b"";
+            }
+        }
+    }
+
+    let newline: &[u8] = match settings.line_endings {
+        LineEndings::Lf => b"\n",
+        LineEndings::Crlf => b"\r\n",
+        // Use the file's first line ending for any that are added.
+        LineEndings::Keep => lines
+            .iter()
+            .map(|(_, ending)| *ending)
+            .find(|ending| !ending.is_empty())
+            .unwrap_or(b"\n"),
+    };
+
+    for (line, ending) in &mut lines {
+        if settings.trailing_whitespace == TrailingWhitespace::Strip {
+            let untrimmed: &[u8] = line;
+            let len = untrimmed
+                .iter()
+                .rposition(|&b| b != b' ' && b != b'\t')
+                .map_or(0, |i| i + 1);
+            *line = &untrimmed[..len];
+        }
+        if settings.line_endings != LineEndings::Keep && !ending.is_empty() {
+            *ending = newline;
+        }
+    }
+
+    if settings.final_newline != FinalNewline::Keep {
+        while lines.last().is_some_and(|(line, _)| line.is_empty()) {
+            lines.pop();
+        }
+        if let Some((_, ending)) = lines.last_mut() {
+            *ending = match settings.final_newline {
+                FinalNewline::None => b"",
+                _ if ending.is_empty() => newline,
+                _ => *ending,
+            };
+        }
+    }
+
+    lines
+        .iter()
+        .flat_map(|(line, ending)| line.iter().chain(ending.iter()))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::git::text_file;
+
+    fn normalized(
+        contents: &str,
+        final_newline: FinalNewline,
+        trailing_whitespace: TrailingWhitespace,
+        line_endings: LineEndings,
+    ) -> String {
+        let settings = TextNormalization {
+            final_newline,
+            trailing_whitespace,
+            line_endings,
+            ..Default::default()
+        };
+        String::from_utf8(normalize(contents.as_bytes(), &settings)).unwrap()
+    }
+
+    #[test]
+    fn test_normalize() {
+        use FinalNewline as F;
+        use LineEndings as L;
+        use TrailingWhitespace as T;
+
+        let keep = |s| normalized(s, F::Keep, T::Keep, L::Keep);
+        assert_eq!(keep("a \r\nb\n\n"), "a \r\nb\n\n");
+        assert_eq!(keep(""), "");
+
+        let one = |s| normalized(s, F::One, T::Keep, L::Keep);
+        assert_eq!(one("a"), "a\n");
+        assert_eq!(one("a\r\nb"), "a\r\nb\r\n");
+        assert_eq!(one("a\n\n\n"), "a\n");
+        assert_eq!(one("a\n \n"), "a\n \n");
+        assert_eq!(one("\n\n"), "");
+
+        let none = |s| normalized(s, F::None, T::Keep, L::Keep);
+        assert_eq!(none("a\nb\n\n"), "a\nb");
+
+        let strip = |s| normalized(s, F::Keep, T::Strip, L::Keep);
+        assert_eq!(strip("a \t\r\n b \n\t"), "a\r\n b\n");
+
+        let lf = |s| normalized(s, F::Keep, T::Keep, L::Lf);
+        assert_eq!(lf("a\r\nb\nc\r\n"), "a\nb\nc\n");
+
+        let crlf = |s| normalized(s, F::One, T::Strip, L::Crlf);
+        assert_eq!(crlf("a \nb\r\nc\n \n"), "a\r\nb\r\nc\r\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_normalize_files_inside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a \n").unwrap();
+        std::fs::write(outside.path().join("b.txt"), "b \n").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("escape")).unwrap();
+
+        let settings = TextNormalization {
+            trailing_whitespace: TrailingWhitespace::Strip,
+            ..Default::default()
+        };
+        let files = [text_file("a.txt"), text_file("escape/b.txt")];
+        let fixed = normalize_files(dir.path(), &files, &settings).unwrap();
+        assert_eq!(fixed, [PathBuf::from("a.txt")]);
+        let outside_file = std::fs::read_to_string(outside.path().join("b.txt")).unwrap();
+        assert_eq!(outside_file, "b \n");
+    }
+}
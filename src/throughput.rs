@@ -0,0 +1,99 @@
+use std::{fmt, time::Duration};
+
+/// How much data was given to linters and how long they took, for reporting
+/// throughput.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Throughput {
+    pub files: usize,
+    pub bytes: u64,
+    pub duration: Duration,
+    /// Peak memory used by a linter run, in bytes. For totals this is the
+    /// largest of the peaks.
+    pub peak_memory: u64,
+}
+
+impl Throughput {
+    fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.duration.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl std::ops::AddAssign for Throughput {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.duration += other.duration;
+        self.peak_memory = self.peak_memory.max(other.peak_memory);
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} in {:.2?} ({}/s)",
+            self.files,
+            format_bytes(self.bytes as f64),
+            self.duration,
+            format_bytes(self.bytes_per_second())
+        )?;
+        if self.peak_memory > 0 {
+            write!(f, ", peak memory {}", format_bytes(self.peak_memory as f64))?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a number of bytes with binary units, e.g. `1.5 MiB`.
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024.0 {
+        return format!("{bytes:.0} B");
+    }
+    let mut value = bytes;
+    let mut unit = "B";
+    for u in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = u;
+    }
+    format!("{value:.1} {unit}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut total = Throughput::default();
+        total += Throughput {
+            files: 2,
+            bytes: 3 * 1024 * 1024,
+            duration: Duration::from_secs(1),
+            peak_memory: 0,
+        };
+        total += Throughput {
+            files: 1,
+            bytes: 0,
+            duration: Duration::from_secs(1),
+            peak_memory: 0,
+        };
+        assert_eq!(total.to_string(), "3 files, 3.0 MiB in 2.00s (1.5 MiB/s)");
+        total += Throughput {
+            peak_memory: 64 * 1024 * 1024,
+            ..Throughput::default()
+        };
+        total += Throughput {
+            peak_memory: 2 * 1024 * 1024,
+            ..Throughput::default()
+        };
+        assert_eq!(
+            total.to_string(),
+            "3 files, 3.0 MiB in 2.00s (1.5 MiB/s), peak memory 64.0 MiB"
+        );
+        assert_eq!(format_bytes(100.0), "100 B");
+    }
+}
@@ -1,14 +1,49 @@
-use std::time::SystemTime;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+/// Number of names generated by this process so far. The time alone isn't
+/// enough because some platforms have coarse clocks, so two threads could get
+/// the same time.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
 
 /// Generate a filename that's unique on this computer (among names created
 /// by this function).
 pub fn unique_filename(prefix: &str, suffix: &str) -> String {
     format!(
-        "{prefix}{}-{}{suffix}",
+        "{prefix}{}-{}-{}{suffix}",
         std::process::id(),
         SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap_or_default()
-            .as_nanos()
+            .as_nanos(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_unique_filename_threads() {
+        let threads: Vec<_> = (0..16)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    (0..1000)
+                        .map(|_| unique_filename("tmp-", ".cache"))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut names = BTreeSet::new();
+        for thread in threads {
+            for name in thread.join().unwrap() {
+                assert!(names.insert(name.clone()), "Duplicate name {name}");
+            }
+        }
+        assert_eq!(names.len(), 16 * 1000);
+    }
+}
@@ -1,39 +1,39 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
 use tokio::fs;
 use wasmtime::{Engine, component::Component};
 
-use crate::{hash_adapter, unique_filename::unique_filename};
+use crate::{hash_adapter, unique_filename::unique_filename, wasm::is_core_module};
 
-pub async fn load_component_cached(engine: &Engine, wasi_path: &Path) -> Result<Component> {
-    let wasi = fs::read(wasi_path).await.context("reading WASI module")?;
+const NOT_A_COMMAND_HINT: &str =
+    "Linters must be WASI command components; build with `--target wasm32-wasip2` and a `main()`";
 
-    let compatibility_hash = engine.precompile_compatibility_hash();
+/// Prefix of the compiled versions of linters that aren't in the cache.
+const LOCAL_PREFIX: &str = "local-";
 
-    let mut digest = blake3::Hasher::new();
-    digest.update(&wasi);
-    let compatibility_digest = hash_adapter::hash_digest(compatibility_hash, digest);
-
-    // TODO: Use with_added_extension() when stable.
-    let mut filename = wasi_path
-        .file_name()
-        .expect("wasi file must have filename")
-        .to_owned();
-    filename.push(format!(".{}.cache", compatibility_digest.to_hex()));
-    let cache_path = wasi_path.with_file_name(filename);
-
-    if !cache_path.exists() {
-        let compiled = engine
-            .precompile_component(&wasi)
-            .context("precompiling WASI module")?;
-
-        let tmpfile = wasi_path.with_file_name(unique_filename("tmp-", ".cache"));
-        fs::write(&tmpfile, compiled).await?;
-        // Check again in case another process just wrote the file.
-        if !cache_path.exists() {
-            fs::rename(tmpfile, &cache_path).await?;
-        }
+/// Load a linter, compiling it if there isn't a compiled version in the cache.
+/// Compiled linters can run arbitrary native code, so they are only loaded
+/// from `cache_dir`, which Nit writes itself. Downloaded linters are in the
+/// cache already and their compiled versions are kept next to them. Linters
+/// from elsewhere (i.e. local linters in the repo) are compiled into
+/// `cache_dir` instead, so a compiled file committed to the repo is never
+/// used.
+pub async fn load_component_cached(
+    engine: &Engine,
+    wasi_path: &Path,
+    cache_dir: &Path,
+) -> Result<Component> {
+    let wasi = read_component(wasi_path).await?;
+    let digest = compiled_digest(engine, &wasi);
+    let cache_path = compiled_cache_path(wasi_path, cache_dir, &digest);
+
+    if !cache_path.exists()
+        && let Some(compiled) = compile_to_cache(engine, &wasi, &cache_path, false).await?
+    {
+        // SAFETY: We just compiled it.
+        return unsafe { Component::deserialize(engine, &compiled) };
     }
 
     // SAFETY: The file must be trusted (it can cause arbitrary code execution)
@@ -42,5 +42,257 @@ pub async fn load_component_cached(engine: &Engine, wasi_path: &Path) -> Result<
     // where we might end up overwriting it, but it should be with an atomic
     // rename and the contents should remain the same (assuming WASM compilation
     // is deterministic).
-    unsafe { Component::deserialize_file(&engine, cache_path) }
+    match unsafe { Component::deserialize_file(engine, &cache_path) } {
+        Ok(component) => Ok(component),
+        Err(e) => {
+            // The cache is corrupt (e.g. truncated by a full disk). Recompile
+            // it once.
+            warn!(
+                "Compiled linter cache {} is invalid, recompiling: {e:#}",
+                cache_path.display()
+            );
+            match compile_to_cache(engine, &wasi, &cache_path, true).await? {
+                // SAFETY: We just compiled it.
+                Some(compiled) => unsafe { Component::deserialize(engine, &compiled) },
+                // SAFETY: As above.
+                None => unsafe { Component::deserialize_file(engine, &cache_path) },
+            }
+        }
+    }
+}
+
+/// Where the compiled version of the linter at `wasi_path` with `digest` (see
+/// `compiled_digest()`) is cached.
+fn compiled_cache_path(wasi_path: &Path, cache_dir: &Path, digest: &str) -> PathBuf {
+    if wasi_path.starts_with(cache_dir) {
+        // TODO: Use with_added_extension() when stable.
+        let mut filename = wasi_path
+            .file_name()
+            .expect("wasi file must have filename")
+            .to_owned();
+        filename.push(format!(".{digest}.cache"));
+        wasi_path.with_file_name(filename)
+    } else {
+        // The digest covers the linter's contents, so the path isn't needed.
+        cache_dir.join(format!("{LOCAL_PREFIX}{digest}.cache"))
+    }
+}
+
+async fn read_component(wasi_path: &Path) -> Result<Vec<u8>> {
+    let wasi = fs::read(wasi_path).await.context("reading WASI module")?;
+    if is_core_module(&wasi) {
+        bail!(
+            "{} is a core WASM module, not a component. {NOT_A_COMMAND_HINT}.",
+            wasi_path.display()
+        );
+    }
+    Ok(wasi)
+}
+
+/// Hash of the linter and everything that affects how it's compiled, which
+/// identifies the compiled version.
+pub fn compiled_digest(engine: &Engine, wasi: &[u8]) -> String {
+    let compatibility_hash = engine.precompile_compatibility_hash();
+
+    let mut digest = blake3::Hasher::new();
+    digest.update(wasi);
+    hash_adapter::hash_digest(compatibility_hash, digest)
+        .to_hex()
+        .to_string()
+}
+
+/// Compile `wasi` and atomically write it to `cache_path`. Unless `overwrite`
+/// is set, an existing cache file (e.g. written by another process in the
+/// meantime) is left alone. If it can't be written (e.g. because the cache
+/// directory is read-only or full) the compiled linter is returned instead,
+/// so it can be used for this run.
+async fn compile_to_cache(
+    engine: &Engine,
+    wasi: &[u8],
+    cache_path: &Path,
+    overwrite: bool,
+) -> Result<Option<Vec<u8>>> {
+    let compiled = engine
+        .precompile_component(wasi)
+        .context("precompiling WASI module")?;
+
+    let result = async {
+        if let Some(dir) = cache_path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let tmpfile = TempFile::new(cache_path);
+        fs::write(tmpfile.path(), &compiled).await?;
+        // Check again in case another process just wrote the file. If it did
+        // ours is removed when it's dropped.
+        if overwrite || !cache_path.exists() {
+            tmpfile.persist(cache_path).await?;
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    match result {
+        Ok(()) => Ok(None),
+        Err(e) => {
+            warn!(
+                "Failed to write compiled linter cache {}: {e:#}. It will be compiled again next time; set NIT_CACHE_DIR to a writable directory to avoid this",
+                cache_path.display()
+            );
+            Ok(Some(compiled))
+        }
+    }
+}
+
+/// A temporary cache file that is removed when it's dropped (e.g. if Nit is
+/// interrupted while writing it), unless it has been renamed into place.
+struct TempFile {
+    path: Option<PathBuf>,
+}
+
+impl TempFile {
+    /// A temporary file next to `path`.
+    fn new(path: &Path) -> Self {
+        Self {
+            path: Some(path.with_file_name(unique_filename("tmp-", ".cache"))),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        self.path.as_deref().expect("temp file has been persisted")
+    }
+
+    /// Atomically move the file to `dest`.
+    async fn persist(mut self, dest: &Path) -> Result<()> {
+        fs::rename(self.path(), dest).await?;
+        self.path = None;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Delete any compiled caches for `wasi_path`. This should be done when the
+/// file is replaced so stale (or corrupt) caches don't build up.
+pub async fn remove_compiled_caches(wasi_path: &Path) -> Result<()> {
+    let (Some(dir), Some(filename)) = (wasi_path.parent(), wasi_path.file_name()) else {
+        return Ok(());
+    };
+    let mut prefix = filename.to_owned();
+    prefix.push(".");
+    let prefix = prefix.to_string_lossy().into_owned();
+
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(&prefix) && name.ends_with(".cache") {
+            debug!("Removing compiled cache {}", entry.path().display());
+            fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that `component` is a WASI command, i.e. it exports `wasi:cli/run`.
+/// Otherwise instantiating it gives a fairly inscrutable error.
+pub fn check_is_command(engine: &Engine, component: &Component, wasi_path: &Path) -> Result<()> {
+    let is_command = component
+        .component_type()
+        .exports(engine)
+        .any(|(name, _)| name.split('@').next() == Some("wasi:cli/run"));
+    if !is_command {
+        bail!(
+            "{} doesn't export `wasi:cli/run` so it isn't a WASI command. {NOT_A_COMMAND_HINT}.",
+            wasi_path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_non_commands_are_rejected() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let engine = Engine::default();
+
+        let module_path = dir.path().join("module.wasm");
+        std::fs::write(&module_path, b"\0asm\x01\0\0\0").unwrap();
+        let err = load_component_cached(&engine, &module_path, dir.path())
+            .await
+            .err()
+            .expect("Core modules should be rejected");
+        assert!(err.to_string().contains("core WASM module"));
+
+        // A reactor-like component that exports nothing.
+        let component_path = dir.path().join("component.wasm");
+        std::fs::write(&component_path, b"\0asm\x0d\0\x01\0").unwrap();
+        let component = load_component_cached(&engine, &component_path, dir.path())
+            .await
+            .unwrap();
+        let err = check_is_command(&engine, &component, &component_path).unwrap_err();
+        assert!(err.to_string().contains("wasi:cli/run"));
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_cache_is_recompiled() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let engine = Engine::default();
+
+        let component_path = dir.path().join("component.wasm");
+        std::fs::write(&component_path, b"\0asm\x0d\0\x01\0").unwrap();
+        load_component_cached(&engine, &component_path, dir.path())
+            .await
+            .unwrap();
+
+        let cache_files = || {
+            std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .filter(|p| p.extension().is_some_and(|e| e == "cache"))
+                .collect::<Vec<_>>()
+        };
+        let cache = cache_files();
+        assert_eq!(cache.len(), 1);
+
+        std::fs::write(&cache[0], "corrupt").unwrap();
+        load_component_cached(&engine, &component_path, dir.path())
+            .await
+            .unwrap();
+
+        remove_compiled_caches(&component_path).await.unwrap();
+        assert!(cache_files().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_local_linters_are_compiled_into_the_cache() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let engine = Engine::default();
+        let cache_dir = dir.path().join("cache");
+        let repo = dir.path().join("repo");
+        std::fs::create_dir(&repo).unwrap();
+
+        let wasi = b"\0asm\x0d\0\x01\0";
+        let component_path = repo.join("component.wasm");
+        std::fs::write(&component_path, wasi).unwrap();
+        // A compiled file where downloaded linters' caches go, e.g. committed
+        // to the repo. It must not be loaded.
+        let digest = compiled_digest(&engine, wasi);
+        let planted = repo.join(format!("component.wasm.{digest}.cache"));
+        std::fs::write(&planted, "planted").unwrap();
+
+        load_component_cached(&engine, &component_path, &cache_dir)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&planted).unwrap(), b"planted");
+        assert!(cache_dir.join(format!("local-{digest}.cache")).exists());
+    }
 }
@@ -45,12 +45,19 @@ pub fn make_custom_section(name: &str, content: &[u8]) -> Vec<u8> {
     section
 }
 
+/// Returns true if `bytes` looks like a core WASM module rather than a
+/// component.
+pub fn is_core_module(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\0asm") && bytes.get(6..8) == Some(&[0, 0])
+}
+
 /// Find all custom sections in a WASM file with the given name. Note that
 /// for WASM components we do not recurse into modules so this will only
 /// find custom sections at the top level of the component.
 ///
 /// This returns the byte ranges of the entire section if you want to remove it,
 /// and also a byte slice to the section contents if you want to read it.
+#[allow(clippy::type_complexity)]
 pub fn find_custom_sections<'a>(
     bytes: &'a [u8],
     name: &str,
@@ -78,15 +85,15 @@ pub fn find_custom_sections<'a>(
     let version = &bytes[4..6];
     let layer = &bytes[6..8];
 
-    if layer == &[0, 0] {
-        if version != &[1, 0] {
+    if layer == [0, 0] {
+        if version != [1, 0] {
             bail!(
                 "WASM module does not have the expected version 1.0: found {:?}",
                 version
             );
         }
-    } else if layer == &[1, 0] {
-        if version != &[13, 0] {
+    } else if layer == [1, 0] {
+        if version != [13, 0] {
             bail!(
                 "WASM component does not have the expected version 13.0: found {:?}",
                 version